@@ -32,6 +32,11 @@ pub enum MethodBody {
 
 #[derive(Debug)]
 pub enum Expr {
+    /// An array literal e.g. `#(1 2 3)`.
+    Array {
+        span: Span,
+        items: Vec<Expr>,
+    },
     Assign {
         span: Span,
         id: Span,
@@ -49,6 +54,13 @@ pub enum Expr {
         vars: Vec<Span>,
         exprs: Vec<Expr>,
     },
+    /// A cascade: `first` is a message send whose receiver is also the receiver of each message
+    /// in `msgs`.
+    Cascade {
+        span: Span,
+        first: Box<Expr>,
+        msgs: Vec<CascadeMsg>,
+    },
     Double {
         span: Span,
         is_negative: bool,
@@ -78,12 +90,22 @@ pub enum Expr {
     VarLookup(Span),
 }
 
+/// A message in a cascade, sent to the receiver of the cascade's first message.
+#[derive(Debug)]
+pub enum CascadeMsg {
+    BinaryMsg { op: Span, arg: Expr },
+    KeywordMsg(Vec<(Span, Expr)>),
+    UnaryMsg(Span),
+}
+
 impl Expr {
     pub fn span(&self) -> Span {
         match self {
+            Expr::Array { span, .. } => *span,
             Expr::Assign { span, .. } => *span,
             Expr::BinaryMsg { span, .. } => *span,
             Expr::Block { span, .. } => *span,
+            Expr::Cascade { span, .. } => *span,
             Expr::Double { span, .. } => *span,
             Expr::Int { span, .. } => *span,
             Expr::KeywordMsg { span, .. } => *span,
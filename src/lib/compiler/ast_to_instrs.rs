@@ -5,14 +5,14 @@ use std::{
 };
 
 use abgc::Gc;
-use itertools::Itertools;
 use lrpar::{Lexer, Span};
+use num_bigint::BigInt;
 
 use crate::{
     compiler::{
         ast,
         instrs::{Instr, Primitive},
-        StorageT,
+        CompileError, StorageT,
     },
     vm::{
         objects::{BlockInfo, Class, Method, MethodBody, String_},
@@ -24,15 +24,32 @@ use crate::{
 pub struct Compiler<'a> {
     lexer: &'a dyn Lexer<StorageT>,
     path: &'a Path,
+    /// The name of the class being compiled.
+    cls_name: &'a str,
     /// The stack of variables at the current point of evaluation.
     vars_stack: Vec<HashMap<&'a str, usize>>,
+    /// The class-side variables which the methods currently being compiled can access as class
+    /// variables. This is empty when compiling class-side methods, since they access the same
+    /// variables as instance variables of the class.
+    class_vars: HashMap<&'a str, usize>,
     /// Since SOM's "^" operator returns from the enclosed method, we need to track whether we are
     /// in a closure -- and, if so, how many nested closures we are inside at the current point of
     /// evaluation.
     closure_depth: usize,
+    /// How many values has the current method or block pushed onto the stack beneath the
+    /// expression being compiled (e.g. the receiver and earlier arguments of a send whose
+    /// argument is being compiled)? A `^` in a method must discard these before returning.
+    stack_depth: usize,
 }
 
-type CompileResult<T> = Result<T, Vec<(Span, String)>>;
+type CompileResult<T> = Result<T, Vec<CompileError>>;
+
+/// A message to be sent to a receiver which has already been evaluated.
+enum Msg<'b> {
+    Binary(Span, &'b ast::Expr),
+    Keywords(&'b [(Span, ast::Expr)]),
+    Unary(Span),
+}
 
 impl<'a> Compiler<'a> {
     pub fn compile(
@@ -40,33 +57,25 @@ impl<'a> Compiler<'a> {
         lexer: &dyn Lexer<StorageT>,
         path: &Path,
         astcls: &ast::Class,
-    ) -> Result<(String, Val), String> {
+    ) -> CompileResult<(String, Val)> {
         let mut compiler = Compiler {
             lexer,
             path,
+            cls_name: lexer.span_str(astcls.name),
             vars_stack: Vec::new(),
+            class_vars: HashMap::new(),
             closure_depth: 0,
+            stack_depth: 0,
         };
 
         let name = lexer.span_str(astcls.name).to_owned();
-        let (supercls, supercls_meta) = if name != "Object" {
-            let supercls = if let Some(n) = astcls.supername.map(|x| lexer.span_str(x)) {
-                match n {
-                    "Block" => vm.block_cls.clone(),
-                    "Class" => vm.cls_cls.clone(),
-                    "Boolean" => vm.bool_cls.clone(),
-                    "String" => vm.str_cls.clone(),
-                    _ => unimplemented!(),
-                }
-            } else {
-                vm.obj_cls.clone()
-            };
-            (supercls.clone(), supercls.get_class(vm).clone())
-        } else {
-            (vm.nil.clone(), vm.nil.clone())
-        };
+        let (supercls, supercls_meta) =
+            superclasses(vm, &name, astcls.supername.map(|x| lexer.span_str(x)));
 
-        // Create the "main" class.
+        // Create the "main" class, whose methods can also access the class-side variables.
+        for (i, var) in astcls.class_inst_vars.iter().enumerate() {
+            compiler.class_vars.insert(lexer.span_str(*var), i);
+        }
         let mut errs = vec![];
         let cls = match compiler.c_class(
             vm,
@@ -84,6 +93,7 @@ impl<'a> Compiler<'a> {
         };
 
         // Create the metaclass (i.e. for a class C, this creates a class called "C class").
+        compiler.class_vars.clear();
         let metacls = match compiler.c_class(
             vm,
             lexer,
@@ -100,27 +110,7 @@ impl<'a> Compiler<'a> {
         };
 
         if !errs.is_empty() {
-            let err_strs = errs
-                .into_iter()
-                .map(|(span, msg)| {
-                    let ((line_off, col), _) = compiler.lexer.line_col(span);
-                    let line = compiler
-                        .lexer
-                        .span_lines_str(span)
-                        .split('\n')
-                        .next()
-                        .unwrap();
-                    format!(
-                        "File '{}', line {}, column {}:\n  {}\n{}",
-                        compiler.path.to_str().unwrap(),
-                        line_off,
-                        col,
-                        line.trim(),
-                        msg
-                    )
-                })
-                .join("\n\n");
-            return Err(err_strs);
+            return Err(errs);
         }
 
         let cls = cls.unwrap();
@@ -158,7 +148,7 @@ impl<'a> Compiler<'a> {
         for astmeth in ast_methods {
             match self.c_method(vm, astmeth) {
                 Ok(m) => {
-                    methods.insert(m.name.clone(), Gc::new(m));
+                    methods.insert(vm.intern_symbol(&m.name), Gc::new(m));
                 }
                 Err(mut e) => {
                     errs.extend(e.drain(..));
@@ -178,12 +168,15 @@ impl<'a> Compiler<'a> {
             self.path.to_path_buf(),
             instrs_off,
             supercls,
-            ast_inst_vars.len(),
+            ast_inst_vars
+                .iter()
+                .map(|v| lexer.span_str(*v).to_owned())
+                .collect(),
             methods,
         );
         let cls_val = Val::from_obj(vm, cls);
         let cls: &Class = cls_val.downcast(vm).unwrap();
-        for m in cls.methods.values() {
+        for m in cls.methods().values() {
             m.set_class(vm, cls_val.clone());
         }
         Ok(cls_val)
@@ -220,148 +213,19 @@ impl<'a> Compiler<'a> {
         params: Vec<Span>,
         body: &ast::MethodBody,
     ) -> CompileResult<MethodBody> {
-        // We check the number of arguments at compile-time so that we don't have to check them
-        // continuously at run-time.
-        let requires_args = |n: usize| -> CompileResult<()> {
-            if params.len() != n {
-                Err(vec![(
-                    name.0,
-                    format!("Expected {} parameters, got {}", n, params.len()),
-                )])
-            } else {
-                Ok(())
-            }
-        };
-
         match body {
-            ast::MethodBody::Primitive => match name.1 {
-                "+" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Add))
-                }
-                "-" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Sub))
-                }
-                "*" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Mul))
-                }
-                "/" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Div))
-                }
-                "//" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::DoubleDiv))
-                }
-                "%" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Mod))
-                }
-                "=" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Equals))
-                }
-                "==" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::RefEquals))
-                }
-                "~=" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::NotEquals))
-                }
-                "<<" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Shl))
-                }
-                "<" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::LessThan))
-                }
-                "<=" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::LessThanEquals))
-                }
-                ">" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::GreaterThan))
-                }
-                ">>>" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::Shr))
-                }
-                ">=" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::GreaterThanEquals))
-                }
-                "&" => {
-                    requires_args(1)?;
-                    Ok(MethodBody::Primitive(Primitive::And))
-                }
-                "bitXor:" => Ok(MethodBody::Primitive(Primitive::BitXor)),
-                "as32BitSignedValue" => Ok(MethodBody::Primitive(Primitive::As32BitSignedValue)),
-                "as32BitUnsignedValue" => {
-                    Ok(MethodBody::Primitive(Primitive::As32BitUnsignedValue))
-                }
-                "asInteger" => Ok(MethodBody::Primitive(Primitive::AsInteger)),
-                "asString" => Ok(MethodBody::Primitive(Primitive::AsString)),
-                "asSymbol" => Ok(MethodBody::Primitive(Primitive::AsSymbol)),
-                "atRandom" => Ok(MethodBody::Primitive(Primitive::AtRandom)),
-                "class" => Ok(MethodBody::Primitive(Primitive::Class)),
-                "concatenate:" => Ok(MethodBody::Primitive(Primitive::Concatenate)),
-                "cos" => Ok(MethodBody::Primitive(Primitive::Cos)),
-                "exit:" => Ok(MethodBody::Primitive(Primitive::Exit)),
-                "fields" => Ok(MethodBody::Primitive(Primitive::Fields)),
-                "fromString:" => Ok(MethodBody::Primitive(Primitive::FromString)),
-                "global:" => Ok(MethodBody::Primitive(Primitive::Global)),
-                "global:put:" => Ok(MethodBody::Primitive(Primitive::GlobalPut)),
-                "halt" => Ok(MethodBody::Primitive(Primitive::Halt)),
-                "hashcode" => Ok(MethodBody::Primitive(Primitive::Hashcode)),
-                "inspect" => Ok(MethodBody::Primitive(Primitive::Inspect)),
-                "instVarAt:" => Ok(MethodBody::Primitive(Primitive::InstVarAt)),
-                "instVarAt:put:" => Ok(MethodBody::Primitive(Primitive::InstVarAtPut)),
-                "instVarNamed:" => Ok(MethodBody::Primitive(Primitive::InstVarNamed)),
-                "length" => Ok(MethodBody::Primitive(Primitive::Length)),
-                "load:" => Ok(MethodBody::Primitive(Primitive::Load)),
-                "methods" => Ok(MethodBody::Primitive(Primitive::Methods)),
-                "name" => Ok(MethodBody::Primitive(Primitive::Name)),
-                "new" => Ok(MethodBody::Primitive(Primitive::New)),
-                "objectSize" => Ok(MethodBody::Primitive(Primitive::ObjectSize)),
-                "perform:" => Ok(MethodBody::Primitive(Primitive::Perform)),
-                "perform:inSuperclass:" => {
-                    Ok(MethodBody::Primitive(Primitive::PerformInSuperClass))
-                }
-                "perform:withArguments:" => {
-                    Ok(MethodBody::Primitive(Primitive::PerformWithArguments))
-                }
-                "perform:withArguments:inSuperclass:" => Ok(MethodBody::Primitive(
-                    Primitive::PerformWithArgumentsInSuperClass,
-                )),
-                "PositiveInfinity" => Ok(MethodBody::Primitive(Primitive::PositiveInfinity)),
-                "primSubstringFrom:to:" => {
-                    Ok(MethodBody::Primitive(Primitive::PrimSubstringFromTo))
-                }
-                "printNewline" => Ok(MethodBody::Primitive(Primitive::PrintNewline)),
-                "printString:" => Ok(MethodBody::Primitive(Primitive::PrintString)),
-                "rem:" => Ok(MethodBody::Primitive(Primitive::Rem)),
-                "sin" => Ok(MethodBody::Primitive(Primitive::Sin)),
-                "sqrt" => Ok(MethodBody::Primitive(Primitive::Sqrt)),
-                "restart" => Ok(MethodBody::Primitive(Primitive::Restart)),
-                "round" => Ok(MethodBody::Primitive(Primitive::Round)),
-                "superclass" => Ok(MethodBody::Primitive(Primitive::Superclass)),
-                "value" => Ok(MethodBody::Primitive(Primitive::Value(0))),
-                "value:" => Ok(MethodBody::Primitive(Primitive::Value(1))),
-                "value:with:" => Ok(MethodBody::Primitive(Primitive::Value(2))),
-                _ => Err(vec![(name.0, format!("Unknown primitive '{}'", name.1))]),
-            },
+            ast::MethodBody::Primitive => {
+                Ok(MethodBody::Primitive(primitive(vm, name, params.len())?))
+            }
             ast::MethodBody::Body { vars, exprs } => {
                 let bytecode_off = vm.instrs_len();
                 let (num_vars, max_stack) = self.c_block(vm, true, span, &params, vars, exprs)?;
                 Ok(MethodBody::User {
+                    num_params: params.len(),
                     num_vars,
                     bytecode_off,
                     max_stack,
+                    inline: inline_instr(vm, bytecode_off, params.len()),
                 })
             }
         }
@@ -390,10 +254,10 @@ impl<'a> Compiler<'a> {
             let vars_len = vars.len();
             let var_str = self.lexer.span_str(var_sp);
             match vars.entry(var_str) {
-                hash_map::Entry::Occupied(_) => Err(vec![(
-                    var_sp,
-                    format!("Variable '{}' shadows another of the same name", var_str),
-                )]),
+                hash_map::Entry::Occupied(_) => Err(vec![CompileError::ShadowedVar {
+                    name: var_str.to_owned(),
+                    span: var_sp,
+                }]),
                 hash_map::Entry::Vacant(e) => {
                     e.insert(vars_len);
                     Ok(vars_len)
@@ -423,15 +287,21 @@ impl<'a> Compiler<'a> {
                 vm.instrs_push(Instr::Pop, e.span());
             }
         }
-        // Blocks return the value of the last statement, but methods return `self`.
-        if is_method {
-            vm.instrs_push(Instr::Pop, span);
-            debug_assert_eq!(*self.vars_stack.last().unwrap().get("self").unwrap(), 0);
-            vm.instrs_push(Instr::VarLookup(0, 0), span);
-            max_stack = max(max_stack, 1);
-            vm.instrs_push(Instr::Return, span);
-        } else {
-            vm.instrs_push(Instr::Return, exprs.iter().last().unwrap().span());
+        // Blocks return the value of the last statement, but methods return `self`. However, if
+        // the last statement is a `^`, any instructions we'd emit here could never be reached.
+        if !matches!(exprs.last(), Some(ast::Expr::Return { .. })) {
+            if is_method {
+                // An empty method has no value on the stack to discard.
+                if !exprs.is_empty() {
+                    vm.instrs_push(Instr::Pop, span);
+                }
+                debug_assert_eq!(*self.vars_stack.last().unwrap().get("self").unwrap(), 0);
+                vm.instrs_push(Instr::VarLookup(0, 0), span);
+                max_stack = max(max_stack, 1);
+                vm.instrs_push(Instr::Return, span);
+            } else {
+                vm.instrs_push(Instr::Return, exprs.iter().last().unwrap().span());
+            }
         }
         self.vars_stack.pop();
 
@@ -441,14 +311,28 @@ impl<'a> Compiler<'a> {
     /// Evaluate an expression, returning `Ok(max_stack_size)` if successful.
     fn c_expr(&mut self, vm: &mut VM, expr: &ast::Expr) -> CompileResult<usize> {
         match expr {
+            ast::Expr::Array { span, items } => {
+                let mut max_stack = 1;
+                for (i, item) in items.iter().enumerate() {
+                    max_stack = max(max_stack, i + self.c_expr_above(vm, i, item)?);
+                }
+                vm.instrs_push(Instr::Array(items.len()), *span);
+                Ok(max_stack)
+            }
             ast::Expr::Assign { span, id, expr } => {
                 let (depth, var_num) = match self.find_var(*id) {
                     Some((d, v)) => (d, v),
+                    None if self.class_vars.contains_key(self.lexer.span_str(*id)) => {
+                        let max_stack = self.c_expr(vm, expr)?;
+                        let n = self.class_vars[self.lexer.span_str(*id)];
+                        vm.instrs_push(Instr::ClassVarSet(n), *span);
+                        return Ok(max_stack);
+                    }
                     None => {
-                        return Err(vec![(
-                            *span,
-                            format!("No such field '{}' in class", self.lexer.span_str(*id)),
-                        )])
+                        return Err(vec![CompileError::UnknownField {
+                            name: self.lexer.span_str(*id).to_owned(),
+                            span: *span,
+                        }])
                     }
                 };
                 let max_stack = self.c_expr(vm, expr)?;
@@ -461,11 +345,12 @@ impl<'a> Compiler<'a> {
                 Ok(max_stack)
             }
             ast::Expr::BinaryMsg { span, lhs, op, rhs } => {
-                let mut stack_size = self.c_expr(vm, lhs)?;
-                stack_size = max(stack_size, 1 + self.c_expr(vm, rhs)?);
-                let send_off = vm.add_send((self.lexer.span_str(*op).to_string(), 1));
-                let instr = Instr::Send(send_off, vm.new_inline_cache());
-                vm.instrs_push(instr, *span);
+                if let Some(i) = self.fold_int(expr) {
+                    vm.instrs_push(Instr::Int(i), *span);
+                    return Ok(1);
+                }
+                let stack_size = self.c_expr(vm, lhs)?;
+                let stack_size = max(stack_size, self.c_msg(vm, *span, Msg::Binary(*op, rhs))?);
                 debug_assert!(stack_size > 0);
                 Ok(stack_size)
             }
@@ -485,8 +370,11 @@ impl<'a> Compiler<'a> {
                 });
                 vm.instrs_push(Instr::Block(blkinfo_idx), *span);
                 self.closure_depth += 1;
+                let stack_depth = self.stack_depth;
+                self.stack_depth = 0;
                 let bytecode_off = vm.instrs_len();
                 let (num_vars, max_stack) = self.c_block(vm, false, *span, &params, vars, exprs)?;
+                self.stack_depth = stack_depth;
                 self.closure_depth -= 1;
                 let bytecode_end = vm.instrs_len();
                 vm.set_blockinfo(
@@ -501,11 +389,69 @@ impl<'a> Compiler<'a> {
                 );
                 Ok(1)
             }
+            ast::Expr::Cascade { span, first, msgs } => {
+                // The receiver of the first message is evaluated once. It is then `Dup`ed before
+                // each send bar the last, with the result of all but the last send being
+                // discarded.
+                let (mut max_stack, first_msg) = match &**first {
+                    ast::Expr::BinaryMsg { lhs, op, rhs, .. } => {
+                        (self.c_expr(vm, lhs)?, Msg::Binary(*op, rhs))
+                    }
+                    ast::Expr::KeywordMsg {
+                        receiver, msglist, ..
+                    } => (self.c_expr(vm, receiver)?, Msg::Keywords(msglist)),
+                    ast::Expr::UnaryMsg { receiver, ids, .. } if !ids.is_empty() => {
+                        let max_stack = self.c_expr(vm, receiver)?;
+                        for id in &ids[..ids.len() - 1] {
+                            self.c_msg(vm, *span, Msg::Unary(*id))?;
+                        }
+                        (max_stack, Msg::Unary(*ids.last().unwrap()))
+                    }
+                    _ => return Err(vec![CompileError::InvalidCascade { span: first.span() }]),
+                };
+                let mut all_msgs = vec![first_msg];
+                all_msgs.extend(msgs.iter().map(|m| match m {
+                    ast::CascadeMsg::BinaryMsg { op, arg } => Msg::Binary(*op, arg),
+                    ast::CascadeMsg::KeywordMsg(msglist) => Msg::Keywords(msglist),
+                    ast::CascadeMsg::UnaryMsg(id) => Msg::Unary(*id),
+                }));
+                let last = all_msgs.len() - 1;
+                for (i, msg) in all_msgs.into_iter().enumerate() {
+                    if i < last {
+                        vm.instrs_push(Instr::Dup, *span);
+                        // The original receiver stays beneath the `Dup`ed one.
+                        self.stack_depth += 1;
+                        let msg_stack = self.c_msg(vm, *span, msg);
+                        self.stack_depth -= 1;
+                        max_stack = max(max_stack, 1 + msg_stack?);
+                        vm.instrs_push(Instr::Pop, *span);
+                    } else {
+                        max_stack = max(max_stack, self.c_msg(vm, *span, msg)?);
+                    }
+                }
+                Ok(max_stack)
+            }
             ast::Expr::Double {
                 span,
                 is_negative,
                 val,
             } => {
+                // The lexer also accepts malformed literals such as `1e` and `1.5e+` so that we
+                // can give a better error than the parser would.
+                let lit = self.lexer.span_str(*val);
+                let msg = if lit.ends_with(|c| matches!(c, 'e' | 'E' | '+' | '-')) {
+                    Some("Floating point literal has no exponent digits")
+                } else if !lit.contains('.') {
+                    Some("Floating point literal has no fractional part")
+                } else {
+                    None
+                };
+                if let Some(msg) = msg {
+                    return Err(vec![CompileError::InvalidLiteral {
+                        span: *val,
+                        msg: msg.to_owned(),
+                    }]);
+                }
                 let s = if *is_negative {
                     format!("-{}", self.lexer.span_str(*val))
                 } else {
@@ -516,7 +462,10 @@ impl<'a> Compiler<'a> {
                         vm.instrs_push(Instr::Double(i), *span);
                         Ok(1)
                     }
-                    Err(e) => Err(vec![(*val, format!("{}", e))]),
+                    Err(e) => Err(vec![CompileError::InvalidLiteral {
+                        span: *val,
+                        msg: e.to_string(),
+                    }]),
                 }
             }
             ast::Expr::Int {
@@ -524,7 +473,8 @@ impl<'a> Compiler<'a> {
                 is_negative,
                 val,
             } => {
-                match self.lexer.span_str(*val).parse::<isize>() {
+                let s = self.lexer.span_str(*val);
+                match s.parse::<isize>() {
                     Ok(mut i) => {
                         if *is_negative {
                             // With twos complement, `0-i` will always succeed, but just in case...
@@ -533,7 +483,22 @@ impl<'a> Compiler<'a> {
                         vm.instrs_push(Instr::Int(i), *span);
                         Ok(1)
                     }
-                    Err(e) => Err(vec![(*val, format!("{}", e))]),
+                    // The literal is too big to fit in an isize, so we have to fall back to a
+                    // BigInt.
+                    Err(_) => match s.parse::<BigInt>() {
+                        Ok(mut i) => {
+                            if *is_negative {
+                                i = -i;
+                            }
+                            let instr = Instr::ArbInt(vm.add_arbint(i));
+                            vm.instrs_push(instr, *span);
+                            Ok(1)
+                        }
+                        Err(e) => Err(vec![CompileError::InvalidLiteral {
+                            span: *val,
+                            msg: e.to_string(),
+                        }]),
+                    },
                 }
             }
             ast::Expr::KeywordMsg {
@@ -541,16 +506,11 @@ impl<'a> Compiler<'a> {
                 receiver,
                 msglist,
             } => {
-                let mut max_stack = self.c_expr(vm, receiver)?;
-                let mut mn = String::new();
-                for (i, (kw, expr)) in msglist.iter().enumerate() {
-                    mn.push_str(self.lexer.span_str(*kw));
-                    let expr_stack = self.c_expr(vm, expr)?;
-                    max_stack = max(max_stack, 1 + i + expr_stack);
+                if let Some(r) = self.c_inlined(vm, *span, receiver, msglist) {
+                    return r;
                 }
-                let send_off = vm.add_send((mn, msglist.len()));
-                let instr = Instr::Send(send_off, vm.new_inline_cache());
-                vm.instrs_push(instr, *span);
+                let max_stack = self.c_expr(vm, receiver)?;
+                let max_stack = max(max_stack, self.c_msg(vm, *span, Msg::Keywords(msglist))?);
                 debug_assert!(max_stack > 0);
                 Ok(max_stack)
             }
@@ -561,14 +521,21 @@ impl<'a> Compiler<'a> {
             } => {
                 let max_stack = self.c_expr(vm, receiver)?;
                 for id in ids {
-                    let send_off = vm.add_send((self.lexer.span_str(*id).to_string(), 0));
-                    let instr = Instr::Send(send_off, vm.new_inline_cache());
-                    vm.instrs_push(instr, *span);
+                    self.c_msg(vm, *span, Msg::Unary(*id))?;
                 }
                 debug_assert!(max_stack > 0);
                 Ok(max_stack)
             }
             ast::Expr::Return { span, expr } => {
+                if self.closure_depth == 0 {
+                    // A `^` inside an inlined block can occur part way through evaluating an
+                    // expression, whose intermediate values will never be used: discard them so
+                    // that the stack is balanced when we return. Non-local returns unwind the
+                    // stack to the enclosing method's frame, so they don't need to do this.
+                    for _ in 0..self.stack_depth {
+                        vm.instrs_push(Instr::Pop, *span);
+                    }
+                }
                 let max_stack = self.c_expr(vm, expr)?;
                 if self.closure_depth == 0 {
                     vm.instrs_push(Instr::Return, *span);
@@ -604,8 +571,18 @@ impl<'a> Compiler<'a> {
                         }
                     }
                     None => {
-                        let name = self.lexer.span_str(*span).to_owned();
-                        let instr = Instr::GlobalLookup(vm.add_global(name));
+                        let name = self.lexer.span_str(*span);
+                        if let Some(n) = self.class_vars.get(name) {
+                            vm.instrs_push(Instr::ClassVarLookup(*n), *span);
+                            return Ok(1);
+                        }
+                        if !self.is_global_name(vm, name) {
+                            return Err(vec![CompileError::UndeclaredName {
+                                name: name.to_owned(),
+                                span: *span,
+                            }]);
+                        }
+                        let instr = Instr::GlobalLookup(vm.add_global(name.to_owned()));
                         vm.instrs_push(instr, *span);
                     }
                 }
@@ -614,6 +591,217 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    /// Evaluate `expr` when `n` more values than usual are on the stack beneath it, returning
+    /// `Ok(max_stack_size)` if successful.
+    fn c_expr_above(&mut self, vm: &mut VM, n: usize, expr: &ast::Expr) -> CompileResult<usize> {
+        self.stack_depth += n;
+        let r = self.c_expr(vm, expr);
+        self.stack_depth -= n;
+        r
+    }
+
+    /// If `expr` is integer arithmetic on literals whose result fits in an `isize`, return that
+    /// result. If `None` is returned, the expression must be evaluated at run-time.
+    fn fold_int(&self, expr: &ast::Expr) -> Option<isize> {
+        match expr {
+            ast::Expr::Int {
+                is_negative, val, ..
+            } => {
+                let i = self.lexer.span_str(*val).parse::<isize>().ok()?;
+                if *is_negative {
+                    i.checked_neg()
+                } else {
+                    Some(i)
+                }
+            }
+            ast::Expr::UnaryMsg { receiver, ids, .. } if ids.is_empty() => self.fold_int(receiver),
+            ast::Expr::BinaryMsg { lhs, op, rhs, .. } => {
+                let lhs = self.fold_int(lhs)?;
+                let rhs = self.fold_int(rhs)?;
+                match self.lexer.span_str(*op) {
+                    "+" => lhs.checked_add(rhs),
+                    "-" => lhs.checked_sub(rhs),
+                    "*" => lhs.checked_mul(rhs),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// If `receiver msglist` is a conditional or a loop whose arguments (and, for loops, receiver)
+    /// are literal blocks without parameters or variables, compile it to jumps rather than sends,
+    /// returning `Some(Ok(max_stack_size))` if successful. If `None` is returned, nothing has been
+    /// compiled, and the message must be sent as normal.
+    fn c_inlined(
+        &mut self,
+        vm: &mut VM,
+        span: Span,
+        receiver: &ast::Expr,
+        msglist: &[(Span, ast::Expr)],
+    ) -> Option<CompileResult<usize>> {
+        fn inlinable(expr: &ast::Expr) -> Option<&[ast::Expr]> {
+            match expr {
+                ast::Expr::Block {
+                    params,
+                    vars,
+                    exprs,
+                    ..
+                } if params.is_empty() && vars.is_empty() => Some(exprs.as_slice()),
+                _ => None,
+            }
+        }
+
+        let mut blocks = Vec::with_capacity(msglist.len());
+        for (_, expr) in msglist {
+            blocks.push(inlinable(expr)?);
+        }
+        let mn = msglist
+            .iter()
+            .map(|(kw, _)| self.lexer.span_str(*kw))
+            .collect::<String>();
+        // Each form is compiled to the condition, a `JumpIfFalse` to the "else" branch, and
+        // the "then" branch followed by a `Jump` past the "else" branch. Loops jump back to
+        // re-evaluate the condition after running their body.
+        match mn.as_str() {
+            "ifTrue:" => Some(self.c_inlined_if(vm, span, receiver, blocks[0], &[])),
+            "ifFalse:" => Some(self.c_inlined_if(vm, span, receiver, &[], blocks[0])),
+            "ifTrue:ifFalse:" => Some(self.c_inlined_if(vm, span, receiver, blocks[0], blocks[1])),
+            "ifFalse:ifTrue:" => Some(self.c_inlined_if(vm, span, receiver, blocks[1], blocks[0])),
+            "whileTrue:" | "whileFalse:" => {
+                let cond = inlinable(receiver)?;
+                Some(self.c_inlined_while(vm, span, cond, mn == "whileTrue:", blocks[0]))
+            }
+            _ => None,
+        }
+    }
+
+    /// Compile `cond`, running `then` if it is `true` and `else_` if it is `false`, returning
+    /// `Ok(max_stack_size)` if successful.
+    fn c_inlined_if(
+        &mut self,
+        vm: &mut VM,
+        span: Span,
+        cond: &ast::Expr,
+        then: &[ast::Expr],
+        else_: &[ast::Expr],
+    ) -> CompileResult<usize> {
+        let mut max_stack = self.c_expr(vm, cond)?;
+        let jump_else = vm.instrs_len();
+        vm.instrs_push(Instr::JumpIfFalse(0), span);
+        max_stack = max(max_stack, self.c_inlined_body(vm, span, then)?);
+        let jump_end = vm.instrs_len();
+        vm.instrs_push(Instr::Jump(0), span);
+        vm.set_instr(jump_else, Instr::JumpIfFalse(vm.instrs_len()));
+        max_stack = max(max_stack, self.c_inlined_body(vm, span, else_)?);
+        vm.set_instr(jump_end, Instr::Jump(vm.instrs_len()));
+        Ok(max_stack)
+    }
+
+    /// Compile a loop which runs `body` for as long as `cond` evaluates to `while_true`, returning
+    /// `Ok(max_stack_size)` if successful. Like `whileTrue:` and `whileFalse:`, the loop evaluates
+    /// to `nil`.
+    fn c_inlined_while(
+        &mut self,
+        vm: &mut VM,
+        span: Span,
+        cond: &[ast::Expr],
+        while_true: bool,
+        body: &[ast::Expr],
+    ) -> CompileResult<usize> {
+        let loop_start = vm.instrs_len();
+        let mut max_stack = self.c_inlined_body(vm, span, cond)?;
+        let jump_exit = vm.instrs_len();
+        vm.instrs_push(Instr::JumpIfFalse(0), span);
+        let mut jump_end = None;
+        if !while_true {
+            // The loop continues when the condition is `false`, so we need to jump over the exit.
+            jump_end = Some(vm.instrs_len());
+            vm.instrs_push(Instr::Jump(0), span);
+            vm.set_instr(jump_exit, Instr::JumpIfFalse(vm.instrs_len()));
+        }
+        max_stack = max(max_stack, self.c_inlined_body(vm, span, body)?);
+        vm.instrs_push(Instr::Pop, span);
+        vm.instrs_push(Instr::Jump(loop_start), span);
+        match jump_end {
+            Some(off) => vm.set_instr(off, Instr::Jump(vm.instrs_len())),
+            None => vm.set_instr(jump_exit, Instr::JumpIfFalse(vm.instrs_len())),
+        }
+        self.c_nil(vm, span);
+        Ok(max_stack)
+    }
+
+    /// Compile the expressions of a literal block into the current method, leaving the value of
+    /// the last expression (or `nil` if there are none) on the stack. Returns
+    /// `Ok(max_stack_size)` if successful.
+    fn c_inlined_body(
+        &mut self,
+        vm: &mut VM,
+        span: Span,
+        exprs: &[ast::Expr],
+    ) -> CompileResult<usize> {
+        if exprs.is_empty() {
+            self.c_nil(vm, span);
+            return Ok(1);
+        }
+        let mut max_stack = 0;
+        for (i, e) in exprs.iter().enumerate() {
+            max_stack = max(max_stack, self.c_expr(vm, e)?);
+            if i != exprs.len() - 1 {
+                vm.instrs_push(Instr::Pop, e.span());
+            }
+        }
+        Ok(max_stack)
+    }
+
+    /// Push `nil` onto the stack.
+    fn c_nil(&mut self, vm: &mut VM, span: Span) {
+        let instr = Instr::GlobalLookup(vm.add_global("nil".to_owned()));
+        vm.instrs_push(instr, span);
+    }
+
+    /// Send the message `msg` to the receiver on top of the stack, returning `Ok(max_stack_size)`
+    /// (which includes the receiver) if successful.
+    fn c_msg(&mut self, vm: &mut VM, span: Span, msg: Msg) -> CompileResult<usize> {
+        let (mn, nargs, max_stack) = match msg {
+            Msg::Binary(op, arg) => {
+                let max_stack = 1 + self.c_expr_above(vm, 1, arg)?;
+                (self.lexer.span_str(op).to_string(), 1, max_stack)
+            }
+            Msg::Keywords(msglist) => {
+                let mut max_stack = 1;
+                let mut mn = String::new();
+                for (i, (kw, expr)) in msglist.iter().enumerate() {
+                    mn.push_str(self.lexer.span_str(*kw));
+                    let expr_stack = self.c_expr_above(vm, 1 + i, expr)?;
+                    max_stack = max(max_stack, 1 + i + expr_stack);
+                }
+                (mn, msglist.len(), max_stack)
+            }
+            Msg::Unary(id) => (self.lexer.span_str(id).to_string(), 0, 1),
+        };
+        let send_off = vm.add_send((mn, nargs));
+        let instr = Instr::Send(send_off, vm.new_inline_cache());
+        vm.instrs_push(instr, span);
+        Ok(max_stack)
+    }
+
+    /// Can `name`, which is not a variable in scope, be a global? Since classes are loaded
+    /// lazily, we can't know for sure at compile-time, so we accept the builtin globals, the
+    /// name of the class being compiled, globals which have already been set in `vm` (e.g. classes
+    /// with lowercase names, or globals set by `System>>global:put:`), and anything which looks
+    /// like a class name.
+    fn is_global_name(&self, vm: &VM, name: &str) -> bool {
+        match name {
+            "false" | "nil" | "system" | "true" => true,
+            _ => {
+                name == self.cls_name
+                    || name.chars().next().map_or(false, |c| c.is_uppercase())
+                    || vm.get_global(name).is_ok()
+            }
+        }
+    }
+
     /// Find the variable at `span` in the variable stack returning a tuple `Some((depth,
     /// var_num))` or `Err` if the variable isn't found. `depth` is the number of closures away
     /// from the "current" one that the variable is found.
@@ -627,3 +815,246 @@ impl<'a> Compiler<'a> {
         None
     }
 }
+
+/// Return the superclass of the class `name`, whose declared superclass is `supername`, and the
+/// superclass's metaclass.
+pub(crate) fn superclasses(vm: &mut VM, name: &str, supername: Option<&str>) -> (Val, Val) {
+    if name != "Object" {
+        let supercls = if let Some(n) = supername {
+            match n {
+                "Block" => vm.block_cls.clone(),
+                "Class" => vm.cls_cls.clone(),
+                "Boolean" => vm.bool_cls.clone(),
+                "String" => vm.str_cls.clone(),
+                _ => unimplemented!(),
+            }
+        } else {
+            vm.obj_cls.clone()
+        };
+        (supercls.clone(), supercls.get_class(vm).clone())
+    } else {
+        (vm.nil.clone(), vm.nil.clone())
+    }
+}
+
+/// Return the primitive which implements the method `name` when it is declared with `num_params`
+/// parameters.
+pub(crate) fn primitive(
+    vm: &VM,
+    name: (Span, &str),
+    num_params: usize,
+) -> CompileResult<Primitive> {
+    // We check the number of arguments at compile-time so that we don't have to check them
+    // continuously at run-time.
+    let requires_args = |n: usize| -> CompileResult<()> {
+        if num_params != n {
+            Err(vec![CompileError::Arity {
+                span: name.0,
+                expected: n,
+                got: num_params,
+            }])
+        } else {
+            Ok(())
+        }
+    };
+
+    match name.1 {
+        "+" => {
+            requires_args(1)?;
+            Ok(Primitive::Add)
+        }
+        "-" => {
+            requires_args(1)?;
+            Ok(Primitive::Sub)
+        }
+        "*" => {
+            requires_args(1)?;
+            Ok(Primitive::Mul)
+        }
+        "/" => {
+            requires_args(1)?;
+            Ok(Primitive::Div)
+        }
+        "//" => {
+            requires_args(1)?;
+            Ok(Primitive::DoubleDiv)
+        }
+        "%" => {
+            requires_args(1)?;
+            Ok(Primitive::Mod)
+        }
+        "=" => {
+            requires_args(1)?;
+            Ok(Primitive::Equals)
+        }
+        "==" => {
+            requires_args(1)?;
+            Ok(Primitive::RefEquals)
+        }
+        "~=" => {
+            requires_args(1)?;
+            Ok(Primitive::NotEquals)
+        }
+        "<<" => {
+            requires_args(1)?;
+            Ok(Primitive::Shl)
+        }
+        "<" => {
+            requires_args(1)?;
+            Ok(Primitive::LessThan)
+        }
+        "<=" => {
+            requires_args(1)?;
+            Ok(Primitive::LessThanEquals)
+        }
+        ">" => {
+            requires_args(1)?;
+            Ok(Primitive::GreaterThan)
+        }
+        ">>" => {
+            requires_args(1)?;
+            Ok(Primitive::Shr)
+        }
+        ">=" => {
+            requires_args(1)?;
+            Ok(Primitive::GreaterThanEquals)
+        }
+        "&" => {
+            requires_args(1)?;
+            Ok(Primitive::And)
+        }
+        "add:" => Ok(Primitive::AddElement),
+        "bitAnd:" => Ok(Primitive::And),
+        "bitOr:" => Ok(Primitive::BitOr),
+        "bitShift:" => Ok(Primitive::BitShift),
+        "bitXor:" => Ok(Primitive::BitXor),
+        "arguments" => Ok(Primitive::Arguments),
+        "as32BitSignedValue" => Ok(Primitive::As32BitSignedValue),
+        "as32BitUnsignedValue" => Ok(Primitive::As32BitUnsignedValue),
+        "asArray" => Ok(Primitive::AsArray),
+        "asDouble" => Ok(Primitive::AsDouble),
+        "asInteger" => Ok(Primitive::AsInteger),
+        "asInteger:" => Ok(Primitive::AsIntegerRadix),
+        "asLowercase" => Ok(Primitive::AsLowercase),
+        "asString" => Ok(Primitive::AsString),
+        "asString:" => Ok(Primitive::AsStringRadix),
+        "asSymbol" => Ok(Primitive::AsSymbol),
+        "asUppercase" => Ok(Primitive::AsUppercase),
+        "at:" => Ok(Primitive::At),
+        "at:put:" => Ok(Primitive::AtPut),
+        "atRandom" => Ok(Primitive::AtRandom),
+        "caseSensitiveMatch:" => Ok(Primitive::CaseSensitiveMatch),
+        "ceiling" => Ok(Primitive::Ceiling),
+        "class" => Ok(Primitive::Class),
+        "collect:" => Ok(Primitive::Collect),
+        "compile:" => Ok(Primitive::Compile),
+        "concatenate:" => Ok(Primitive::Concatenate),
+        "contains:" => Ok(Primitive::Contains),
+        "containsKey:" => Ok(Primitive::ContainsKey),
+        "copyWith:" => Ok(Primitive::CopyWith),
+        "cos" => Ok(Primitive::Cos),
+        "do:" => Ok(Primitive::Do),
+        "exit:" => Ok(Primitive::Exit),
+        "fields" => Ok(Primitive::Fields),
+        "floor" => Ok(Primitive::Floor),
+        "format:" => Ok(Primitive::Format),
+        "fromString:" => Ok(Primitive::FromString),
+        "gcd:" => Ok(Primitive::Gcd),
+        "gcObjectCount" => Ok(Primitive::GcObjectCount),
+        "global:" => Ok(Primitive::Global),
+        "global:put:" => Ok(Primitive::GlobalPut),
+        "halt" => Ok(Primitive::Halt),
+        "hashcode" => Ok(Primitive::Hashcode),
+        "identityHash" => Ok(Primitive::IdentityHash),
+        "ifFalse:" => Ok(Primitive::IfFalse),
+        "ifTrue:" => Ok(Primitive::IfTrue),
+        "ifTrue:ifFalse:" => Ok(Primitive::IfTrueIfFalse),
+        "indexOf:" => Ok(Primitive::IndexOf),
+        "inject:into:" => Ok(Primitive::InjectInto),
+        "inspect" => Ok(Primitive::Inspect),
+        "instVarAt:" => Ok(Primitive::InstVarAt),
+        "instVarAt:put:" => Ok(Primitive::InstVarAtPut),
+        "instVarNamed:" => Ok(Primitive::InstVarNamed),
+        "isKindOf:" => Ok(Primitive::IsKindOf),
+        "isMemberOf:" => Ok(Primitive::IsMemberOf),
+        "isNil" => Ok(Primitive::IsNil),
+        "joinUsing:" => Ok(Primitive::JoinUsing),
+        "keysAndValuesDo:" => Ok(Primitive::KeysAndValuesDo),
+        "lcm:" => Ok(Primitive::Lcm),
+        "length" => Ok(Primitive::Length),
+        "load:" => Ok(Primitive::Load),
+        "loadSource:" => Ok(Primitive::LoadSource),
+        "match:" => Ok(Primitive::Match),
+        "methods:" => Ok(Primitive::Methods),
+        "name" => Ok(Primitive::Name),
+        "new" => Ok(Primitive::New),
+        "new:" => Ok(Primitive::NewArray),
+        "notNil" => Ok(Primitive::NotNil),
+        "on:" => Ok(Primitive::NewWeakRef),
+        "objectSize" => Ok(Primitive::ObjectSize),
+        "perform:" => Ok(Primitive::Perform),
+        "perform:inSuperclass:" => Ok(Primitive::PerformInSuperClass),
+        "perform:withArguments:" => Ok(Primitive::PerformWithArguments),
+        "perform:withArguments:inSuperclass:" => Ok(Primitive::PerformWithArgumentsInSuperClass),
+        "PositiveInfinity" => Ok(Primitive::PositiveInfinity),
+        "primSubstringFrom:to:" => Ok(Primitive::PrimSubstringFromTo),
+        "printNewline" => Ok(Primitive::PrintNewline),
+        "printString" => Ok(Primitive::QuotedString),
+        "printString:" => Ok(Primitive::PrintString),
+        "rem:" => Ok(Primitive::Rem),
+        "remove:" => Ok(Primitive::Remove),
+        "removeKey:" => Ok(Primitive::RemoveKey),
+        "replaceAll:with:" => Ok(Primitive::ReplaceAllWith),
+        "shallowCopy" => Ok(Primitive::ShallowCopy),
+        "sin" => Ok(Primitive::Sin),
+        "size" => Ok(Primitive::Size),
+        "sqrt" => Ok(Primitive::Sqrt),
+        "respondsTo:" => Ok(Primitive::RespondsTo),
+        "restart" => Ok(Primitive::Restart),
+        "round" => Ok(Primitive::Round),
+        "roundTo:" => Ok(Primitive::RoundTo),
+        "substrings" => Ok(Primitive::Substrings),
+        "superclass" => Ok(Primitive::Superclass),
+        "ticks" => Ok(Primitive::Ticks),
+        "time" => Ok(Primitive::Time),
+        "tokenize:" => Ok(Primitive::Tokenize),
+        "truncated" => Ok(Primitive::Truncated),
+        "value" => Ok(Primitive::Value(0)),
+        "value:" => Ok(Primitive::Value(1)),
+        "value:with:" => Ok(Primitive::Value(2)),
+        "whileFalse:" => Ok(Primitive::WhileFalse),
+        "whileTrue:" => Ok(Primitive::WhileTrue),
+        "with:" => Ok(Primitive::With(1)),
+        "with:with:" => Ok(Primitive::With(2)),
+        "with:with:with:" => Ok(Primitive::With(3)),
+        "yourself" => Ok(Primitive::Yourself),
+        _ => match vm.primitive_idx(name.1) {
+            Some(i) => Ok(Primitive::Registered(i, num_params)),
+            None => Err(vec![CompileError::UnknownPrimitive {
+                name: name.1.to_owned(),
+                span: name.0,
+            }]),
+        },
+    }
+}
+
+/// If the method whose instructions start at `bytecode_off` simply returns an instance variable or
+/// a constant, return the instruction which pushes that value, so that sends to the method can be
+/// inlined (see [`MethodBody::User`]).
+pub(crate) fn inline_instr(vm: &VM, bytecode_off: usize, num_params: usize) -> Option<Instr> {
+    // Only methods without parameters are inlined: `exec_inline` pushes its result without
+    // removing any arguments from the stack.
+    if num_params > 0 || bytecode_off + 1 >= vm.instrs_len() {
+        return None;
+    }
+    match (vm.instr(bytecode_off), vm.instr(bytecode_off + 1)) {
+        (
+            i @ Instr::InstVarLookup(_)
+            | i @ Instr::Int(_)
+            | i @ Instr::String(_)
+            | i @ Instr::Symbol(_),
+            Instr::Return,
+        ) => Some(i),
+        _ => None,
+    }
+}
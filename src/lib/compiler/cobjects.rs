@@ -0,0 +1,746 @@
+//! Compiled classes which are independent of any particular VM. [`compile_file`] produces a
+//! [`Class`] which can be serialised with [`Class::to_bytes`], written to disk, and later loaded
+//! into a VM with [`VM::load_compiled_class`] without the class's source being recompiled.
+//!
+//! The serialised format is: the magic bytes `YKSOMC`; a version byte; and then the class's
+//! fields in the order they are declared in [`Class`]. Unsigned integers are encoded in LEB128,
+//! signed integers are zigzag encoded and then LEB128 encoded, doubles are stored as their
+//! little-endian IEEE 754 bytes, and strings and vectors are prefixed with their length.
+//!
+//! [`compile_file`]: super::compile_file
+//! [`VM::load_compiled_class`]: crate::vm::VM::load_compiled_class
+
+use std::{collections::HashMap, convert::TryInto, hash::Hash, mem::size_of, path::PathBuf};
+
+use abgc::Gc;
+use lrpar::Span;
+use num_bigint::BigInt;
+
+use crate::{
+    compiler::{
+        ast_to_instrs::{inline_instr, primitive, superclasses},
+        instrs::Instr,
+    },
+    vm::{
+        core::{selector_arity, SymId},
+        error::{VMError, VMErrorKind},
+        objects::{self, BlockInfo, String_},
+        val::Val,
+        VM,
+    },
+};
+
+const MAGIC: &[u8] = b"YKSOMC";
+const VERSION: u8 = 0;
+
+/// A compiled class and its metaclass. Unlike a VM's classes, the operands of `instrs` refer to
+/// this class's own tables (`arbints`, `blocks`, etc.) and jump targets and bytecode offsets are
+/// relative to the start of `instrs`.
+#[derive(Debug, PartialEq)]
+pub struct Class {
+    pub name: String,
+    /// The name of this class's superclass, or `None` if it is `Object` (or if this class is
+    /// `Object`).
+    pub supername: Option<String>,
+    /// The path of the file this class was compiled from, used when reporting errors.
+    pub path: PathBuf,
+    pub inst_vars: Vec<String>,
+    pub methods: Vec<Method>,
+    pub class_inst_vars: Vec<String>,
+    pub class_methods: Vec<Method>,
+    /// The offset in `instrs` of the metaclass's instructions.
+    pub class_instrs_off: usize,
+    pub instrs: Vec<Instr>,
+    /// The span of source text associated with each instruction in `instrs`.
+    pub instr_spans: Vec<Span>,
+    pub arbints: Vec<BigInt>,
+    pub blocks: Vec<Block>,
+    pub globals: Vec<String>,
+    /// (method name, number of arguments) pairs.
+    pub sends: Vec<(String, usize)>,
+    pub strings: Vec<String>,
+    pub symbols: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Method {
+    pub name: String,
+    pub body: MethodBody,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MethodBody {
+    /// A built-in primitive, which is looked up by the method's name when the class is loaded.
+    Primitive,
+    /// User bytecode.
+    User {
+        num_params: usize,
+        num_vars: usize,
+        /// The offset of this method's bytecode in [`Class::instrs`].
+        bytecode_off: usize,
+        max_stack: usize,
+    },
+}
+
+/// A block whose bytecode lies between `bytecode_off` and `bytecode_end` in [`Class::instrs`].
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    pub bytecode_off: usize,
+    pub bytecode_end: usize,
+    pub num_params: usize,
+    pub num_vars: usize,
+    pub max_stack: usize,
+}
+
+impl Class {
+    /// Serialise this class into the format described in the [module documentation](self).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer {
+            buf: MAGIC.to_vec(),
+        };
+        w.u8(VERSION);
+        w.str(&self.name);
+        match &self.supername {
+            Some(n) => {
+                w.u8(1);
+                w.str(n);
+            }
+            None => w.u8(0),
+        }
+        w.str(&self.path.to_string_lossy());
+        w.vec(&self.inst_vars, |w, s| w.str(s));
+        w.vec(&self.methods, Writer::method);
+        w.vec(&self.class_inst_vars, |w, s| w.str(s));
+        w.vec(&self.class_methods, Writer::method);
+        w.usize(self.class_instrs_off);
+        w.vec(&self.instrs, Writer::instr);
+        w.vec(&self.instr_spans, |w, sp| {
+            w.usize(sp.start());
+            w.usize(sp.end());
+        });
+        w.vec(&self.arbints, |w, i| {
+            let bytes = i.to_signed_bytes_le();
+            w.usize(bytes.len());
+            w.buf.extend_from_slice(&bytes);
+        });
+        w.vec(&self.blocks, |w, b| {
+            w.usize(b.bytecode_off);
+            w.usize(b.bytecode_end);
+            w.usize(b.num_params);
+            w.usize(b.num_vars);
+            w.usize(b.max_stack);
+        });
+        w.vec(&self.globals, |w, s| w.str(s));
+        w.vec(&self.sends, |w, (s, nargs)| {
+            w.str(s);
+            w.usize(*nargs);
+        });
+        w.vec(&self.strings, |w, s| w.str(s));
+        w.vec(&self.symbols, |w, s| w.str(s));
+        w.buf
+    }
+
+    /// Deserialise a class serialised with [`Class::to_bytes`]. Returns `None` if `bytes` is not
+    /// a serialised class, or if any of the class's instructions refer to constants, sends, blocks
+    /// or bytecode offsets which do not exist.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Class> {
+        let mut r = Reader { bytes, off: 0 };
+        if r.bytes(MAGIC.len())? != MAGIC || r.u8()? != VERSION {
+            return None;
+        }
+        let cls = Class {
+            name: r.string()?,
+            supername: match r.u8()? {
+                0 => None,
+                1 => Some(r.string()?),
+                _ => return None,
+            },
+            path: PathBuf::from(r.string()?),
+            inst_vars: r.vec(Reader::string)?,
+            methods: r.vec(Reader::method)?,
+            class_inst_vars: r.vec(Reader::string)?,
+            class_methods: r.vec(Reader::method)?,
+            class_instrs_off: r.usize()?,
+            instrs: r.vec(Reader::instr)?,
+            instr_spans: r.vec(|r| Some(Span::new(r.usize()?, r.usize()?)))?,
+            arbints: r.vec(|r| {
+                let len = r.usize()?;
+                Some(BigInt::from_signed_bytes_le(r.bytes(len)?))
+            })?,
+            blocks: r.vec(|r| {
+                Some(Block {
+                    bytecode_off: r.usize()?,
+                    bytecode_end: r.usize()?,
+                    num_params: r.usize()?,
+                    num_vars: r.usize()?,
+                    max_stack: r.usize()?,
+                })
+            })?,
+            globals: r.vec(Reader::string)?,
+            sends: r.vec(|r| Some((r.string()?, r.usize()?)))?,
+            strings: r.vec(Reader::string)?,
+            symbols: r.vec(Reader::string)?,
+        };
+        if r.off == bytes.len() && cls.is_well_formed() {
+            Some(cls)
+        } else {
+            None
+        }
+    }
+
+    /// Do this class's instructions, methods, and blocks only refer to things which exist? This
+    /// is the minimum needed for [`Class::link`] to succeed: checking that the bytecode itself is
+    /// well-formed is left to [`VMConfig::verify_bytecode`](crate::vm::VMConfig::verify_bytecode).
+    fn is_well_formed(&self) -> bool {
+        let len = self.instrs.len();
+        let supername_ok = match self.supername.as_deref() {
+            None | Some("Block") | Some("Class") | Some("Boolean") | Some("String") => true,
+            Some(_) => false,
+        };
+        let instrs_ok = self.instrs.iter().all(|instr| match *instr {
+            Instr::ArbInt(i) => i < self.arbints.len(),
+            Instr::Block(i) => i < self.blocks.len(),
+            Instr::GlobalLookup(i) => i < self.globals.len(),
+            Instr::Jump(i) | Instr::JumpIfFalse(i) => i < len,
+            Instr::Send(i, _) => i < self.sends.len(),
+            Instr::String(i) => i < self.strings.len(),
+            Instr::Symbol(i) => i < self.symbols.len(),
+            _ => true,
+        });
+        let meths_ok = self
+            .methods
+            .iter()
+            .chain(&self.class_methods)
+            .all(|m| match m.body {
+                MethodBody::Primitive => true,
+                MethodBody::User {
+                    num_params,
+                    bytecode_off,
+                    ..
+                } => num_params == selector_arity(&m.name) && bytecode_off < len,
+            });
+        let blocks_ok = self
+            .blocks
+            .iter()
+            .all(|b| b.bytecode_off <= b.bytecode_end && b.bytecode_end <= len);
+        supername_ok
+            && instrs_ok
+            && meths_ok
+            && blocks_ok
+            && self.instr_spans.len() == len
+            && self.class_instrs_off <= len
+    }
+
+    /// Copy the class `cls_val`, which must just have been compiled in `vm` (so that its and its
+    /// metaclass's instructions are the last in the VM), out of `vm`.
+    pub(crate) fn from_vm(vm: &VM, cls_val: &Val) -> Result<Class, Box<VMError>> {
+        let cls: &objects::Class = cls_val.downcast(vm)?;
+        let metacls_val = cls.metacls(vm);
+        let metacls: &objects::Class = metacls_val.downcast(vm)?;
+        let start = cls.instrs_off;
+
+        let mut arbints = Table::default();
+        let mut globals = Table::default();
+        let mut sends = Table::default();
+        let mut strings = Table::default();
+        let mut symbols = Table::default();
+        let mut blocks = Vec::new();
+        let mut instrs = Vec::with_capacity(vm.instrs_len() - start);
+        let mut instr_spans = Vec::with_capacity(vm.instrs_len() - start);
+        for pc in start..vm.instrs_len() {
+            let instr = match vm.instr(pc) {
+                Instr::ArbInt(i) => Instr::ArbInt(arbints.intern(vm.arbint(i).clone())),
+                Instr::Block(i) => {
+                    let blkinfo = vm.blockinfo(i);
+                    blocks.push(Block {
+                        bytecode_off: blkinfo.bytecode_off - start,
+                        bytecode_end: blkinfo.bytecode_end - start,
+                        num_params: blkinfo.num_params,
+                        num_vars: blkinfo.num_vars,
+                        max_stack: blkinfo.max_stack,
+                    });
+                    Instr::Block(blocks.len() - 1)
+                }
+                Instr::GlobalLookup(i) => {
+                    Instr::GlobalLookup(globals.intern(vm.global_name(i).to_owned()))
+                }
+                Instr::Jump(i) => Instr::Jump(i - start),
+                Instr::JumpIfFalse(i) => Instr::JumpIfFalse(i - start),
+                Instr::Send(i, _) => {
+                    let (name, nargs) = vm.send(i);
+                    Instr::Send(sends.intern((name.to_owned(), nargs)), 0)
+                }
+                Instr::String(i) => Instr::String(strings.intern(vm.string_str(i).to_owned())),
+                Instr::Symbol(i) => {
+                    Instr::Symbol(symbols.intern(vm.symbol_str(SymId(i)).to_owned()))
+                }
+                i => i,
+            };
+            instrs.push(instr);
+            instr_spans.push(vm.instr_span(pc));
+        }
+
+        let supercls_val = cls.supercls(vm);
+        let supername = if supercls_val.is_nil(vm) {
+            None
+        } else {
+            match supercls_val.downcast::<objects::Class>(vm)?.name_str(vm) {
+                "Object" => None,
+                n => Some(n.to_owned()),
+            }
+        };
+
+        Ok(Class {
+            name: cls.name_str(vm).to_owned(),
+            supername,
+            path: cls.path.clone(),
+            inst_vars: cls.inst_var_names.clone(),
+            methods: methods_from_vm(cls, start),
+            class_inst_vars: metacls.inst_var_names.clone(),
+            class_methods: methods_from_vm(metacls, start),
+            class_instrs_off: metacls.instrs_off - start,
+            instrs,
+            instr_spans,
+            arbints: arbints.vec,
+            blocks,
+            globals: globals.vec,
+            sends: sends.vec,
+            strings: strings.vec,
+            symbols: symbols.vec,
+        })
+    }
+
+    /// Add this class's instructions and constants to `vm`, returning a new class (which is not
+    /// registered as a global).
+    pub(crate) fn link(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        let base = vm.instrs_len();
+        let arbints = self
+            .arbints
+            .iter()
+            .map(|i| vm.add_arbint(i.clone()))
+            .collect::<Vec<_>>();
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|b| {
+                vm.push_blockinfo(BlockInfo {
+                    bytecode_off: base + b.bytecode_off,
+                    bytecode_end: base + b.bytecode_end,
+                    num_params: b.num_params,
+                    num_vars: b.num_vars,
+                    max_stack: b.max_stack,
+                })
+            })
+            .collect::<Vec<_>>();
+        let globals = self
+            .globals
+            .iter()
+            .map(|s| vm.add_global(s.clone()))
+            .collect::<Vec<_>>();
+        let sends = self
+            .sends
+            .iter()
+            .map(|s| vm.add_send(s.clone()))
+            .collect::<Vec<_>>();
+        let strings = self
+            .strings
+            .iter()
+            .map(|s| vm.add_string(s.clone()))
+            .collect::<Vec<_>>();
+        let symbols = self
+            .symbols
+            .iter()
+            .map(|s| vm.add_symbol(s.clone()))
+            .collect::<Vec<_>>();
+        for (instr, span) in self.instrs.iter().zip(&self.instr_spans) {
+            let instr = match *instr {
+                Instr::ArbInt(i) => Instr::ArbInt(arbints[i]),
+                Instr::Block(i) => Instr::Block(blocks[i]),
+                Instr::GlobalLookup(i) => Instr::GlobalLookup(globals[i]),
+                Instr::Jump(i) => Instr::Jump(base + i),
+                Instr::JumpIfFalse(i) => Instr::JumpIfFalse(base + i),
+                Instr::Send(i, _) => Instr::Send(sends[i], vm.new_inline_cache()),
+                Instr::String(i) => Instr::String(strings[i]),
+                Instr::Symbol(i) => Instr::Symbol(symbols[i]),
+                i => i,
+            };
+            vm.instrs_push(instr, *span);
+        }
+
+        let (supercls, supercls_meta) = superclasses(vm, &self.name, self.supername.as_deref());
+        let cls = self.link_class(
+            vm,
+            self.name.clone(),
+            base,
+            base,
+            supercls,
+            &self.inst_vars,
+            &self.methods,
+        )?;
+        let metacls = self.link_class(
+            vm,
+            format!("{} class", self.name),
+            base,
+            base + self.class_instrs_off,
+            supercls_meta,
+            &self.class_inst_vars,
+            &self.class_methods,
+        )?;
+        metacls
+            .downcast::<objects::Class>(vm)?
+            .set_metacls(vm, vm.metacls_cls.clone());
+        cls.downcast::<objects::Class>(vm)?.set_metacls(vm, metacls);
+        Ok(cls)
+    }
+
+    /// Create a class called `name` whose methods are `meths` and whose instructions start at
+    /// `instrs_off`. `base` is the offset at which this class's instructions were added to `vm`.
+    fn link_class(
+        &self,
+        vm: &mut VM,
+        name: String,
+        base: usize,
+        instrs_off: usize,
+        supercls: Val,
+        inst_vars: &[String],
+        meths: &[Method],
+    ) -> Result<Val, Box<VMError>> {
+        let mut methods = HashMap::with_capacity(meths.len());
+        for meth in meths {
+            let body = match meth.body {
+                MethodBody::Primitive => {
+                    let name = (Span::new(0, 0), meth.name.as_str());
+                    match primitive(vm, name, selector_arity(&meth.name)) {
+                        Ok(p) => objects::MethodBody::Primitive(p),
+                        Err(errs) => return Err(VMError::new(vm, VMErrorKind::CompileError(errs))),
+                    }
+                }
+                MethodBody::User {
+                    num_params,
+                    num_vars,
+                    bytecode_off,
+                    max_stack,
+                } => objects::MethodBody::User {
+                    num_params,
+                    num_vars,
+                    bytecode_off: base + bytecode_off,
+                    max_stack,
+                    inline: inline_instr(vm, base + bytecode_off, num_params),
+                },
+            };
+            let meth = objects::Method::new(vm, meth.name.clone(), body);
+            methods.insert(vm.intern_symbol(&meth.name), Gc::new(meth));
+        }
+
+        let name_val = String_::new(vm, name, false);
+        let cls = objects::Class::new(
+            vm,
+            vm.cls_cls.clone(),
+            name_val,
+            self.path.clone(),
+            instrs_off,
+            supercls,
+            inst_vars.to_vec(),
+            methods,
+        );
+        let cls_val = Val::from_obj(vm, cls);
+        let cls: &objects::Class = cls_val.downcast(vm)?;
+        for m in cls.methods().values() {
+            m.set_class(vm, cls_val.clone());
+        }
+        Ok(cls_val)
+    }
+}
+
+/// Copy the methods of `cls`, whose instructions start at `start` in its VM, sorted by name (so
+/// that serialising the same class always produces the same bytes).
+fn methods_from_vm(cls: &objects::Class, start: usize) -> Vec<Method> {
+    let mut meths = cls
+        .methods()
+        .values()
+        .map(|m| Method {
+            name: m.name.clone(),
+            body: match m.body {
+                objects::MethodBody::Primitive(_) => MethodBody::Primitive,
+                objects::MethodBody::User {
+                    num_params,
+                    num_vars,
+                    bytecode_off,
+                    max_stack,
+                    ..
+                } => MethodBody::User {
+                    num_params,
+                    num_vars,
+                    bytecode_off: bytecode_off - start,
+                    max_stack,
+                },
+            },
+        })
+        .collect::<Vec<_>>();
+    meths.sort_by(|a, b| a.name.cmp(&b.name));
+    meths
+}
+
+/// A vector of unique values, used to give each constant a single index in a [`Class`].
+struct Table<T> {
+    vec: Vec<T>,
+    idxs: HashMap<T, usize>,
+}
+
+impl<T> Default for Table<T> {
+    fn default() -> Self {
+        Table {
+            vec: Vec::new(),
+            idxs: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash> Table<T> {
+    /// Return the index of `x`, adding it to the table if necessary.
+    fn intern(&mut self, x: T) -> usize {
+        if let Some(i) = self.idxs.get(&x) {
+            *i
+        } else {
+            let len = self.vec.len();
+            self.idxs.insert(x.clone(), len);
+            self.vec.push(x);
+            len
+        }
+    }
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, b: u8) {
+        self.buf.push(b);
+    }
+
+    fn usize(&mut self, mut n: usize) {
+        while n >= 0x80 {
+            self.buf.push((n & 0x7f) as u8 | 0x80);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+
+    fn isize(&mut self, i: isize) {
+        self.usize(((i << 1) ^ (i >> (size_of::<isize>() * 8 - 1))) as usize);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn vec<T>(&mut self, v: &[T], f: impl Fn(&mut Self, &T)) {
+        self.usize(v.len());
+        for x in v {
+            f(self, x);
+        }
+    }
+
+    fn method(&mut self, meth: &Method) {
+        self.str(&meth.name);
+        match meth.body {
+            MethodBody::Primitive => self.u8(0),
+            MethodBody::User {
+                num_params,
+                num_vars,
+                bytecode_off,
+                max_stack,
+            } => {
+                self.u8(1);
+                self.usize(num_params);
+                self.usize(num_vars);
+                self.usize(bytecode_off);
+                self.usize(max_stack);
+            }
+        }
+    }
+
+    fn instr(&mut self, instr: &Instr) {
+        match *instr {
+            Instr::ArbInt(i) => {
+                self.u8(0);
+                self.usize(i);
+            }
+            Instr::Array(n) => {
+                self.u8(1);
+                self.usize(n);
+            }
+            Instr::Block(i) => {
+                self.u8(2);
+                self.usize(i);
+            }
+            Instr::GlobalLookup(i) => {
+                self.u8(3);
+                self.usize(i);
+            }
+            Instr::ClassVarLookup(n) => {
+                self.u8(4);
+                self.usize(n);
+            }
+            Instr::ClassVarSet(n) => {
+                self.u8(5);
+                self.usize(n);
+            }
+            Instr::ClosureReturn(n) => {
+                self.u8(6);
+                self.usize(n);
+            }
+            Instr::Double(d) => {
+                self.u8(7);
+                self.buf.extend_from_slice(&d.to_le_bytes());
+            }
+            Instr::Dup => self.u8(8),
+            Instr::InstVarLookup(n) => {
+                self.u8(9);
+                self.usize(n);
+            }
+            Instr::InstVarSet(n) => {
+                self.u8(10);
+                self.usize(n);
+            }
+            Instr::Int(i) => {
+                self.u8(11);
+                self.isize(i);
+            }
+            Instr::Jump(i) => {
+                self.u8(12);
+                self.usize(i);
+            }
+            Instr::JumpIfFalse(i) => {
+                self.u8(13);
+                self.usize(i);
+            }
+            Instr::Pop => self.u8(14),
+            Instr::Return => self.u8(15),
+            // Inline caches are created afresh when a class is loaded.
+            Instr::Send(i, _) => {
+                self.u8(16);
+                self.usize(i);
+            }
+            Instr::String(i) => {
+                self.u8(17);
+                self.usize(i);
+            }
+            Instr::Symbol(i) => {
+                self.u8(18);
+                self.usize(i);
+            }
+            Instr::VarLookup(d, n) => {
+                self.u8(19);
+                self.usize(d);
+                self.usize(n);
+            }
+            Instr::VarSet(d, n) => {
+                self.u8(20);
+                self.usize(d);
+                self.usize(n);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let b = *self.bytes.get(self.off)?;
+        self.off += 1;
+        Some(b)
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let end = self.off.checked_add(len)?;
+        let bytes = self.bytes.get(self.off..end)?;
+        self.off = end;
+        Some(bytes)
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        let mut n = 0usize;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            let bits = (b & 0x7f) as usize;
+            if shift >= size_of::<usize>() * 8 || (bits << shift) >> shift != bits {
+                return None;
+            }
+            n |= bits << shift;
+            if b & 0x80 == 0 {
+                return Some(n);
+            }
+            shift += 7;
+        }
+    }
+
+    fn isize(&mut self) -> Option<isize> {
+        let n = self.usize()?;
+        Some(((n >> 1) as isize) ^ -((n & 1) as isize))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.usize()?;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+
+    fn vec<T>(&mut self, mut f: impl FnMut(&mut Self) -> Option<T>) -> Option<Vec<T>> {
+        let len = self.usize()?;
+        // `len` hasn't been checked, so we don't use it to preallocate the vector.
+        let mut v = Vec::new();
+        for _ in 0..len {
+            v.push(f(self)?);
+        }
+        Some(v)
+    }
+
+    fn method(&mut self) -> Option<Method> {
+        let name = self.string()?;
+        let body = match self.u8()? {
+            0 => MethodBody::Primitive,
+            1 => MethodBody::User {
+                num_params: self.usize()?,
+                num_vars: self.usize()?,
+                bytecode_off: self.usize()?,
+                max_stack: self.usize()?,
+            },
+            _ => return None,
+        };
+        Some(Method { name, body })
+    }
+
+    fn instr(&mut self) -> Option<Instr> {
+        Some(match self.u8()? {
+            0 => Instr::ArbInt(self.usize()?),
+            1 => Instr::Array(self.usize()?),
+            2 => Instr::Block(self.usize()?),
+            3 => Instr::GlobalLookup(self.usize()?),
+            4 => Instr::ClassVarLookup(self.usize()?),
+            5 => Instr::ClassVarSet(self.usize()?),
+            6 => Instr::ClosureReturn(self.usize()?),
+            7 => Instr::Double(f64::from_le_bytes(self.bytes(8)?.try_into().unwrap())),
+            8 => Instr::Dup,
+            9 => Instr::InstVarLookup(self.usize()?),
+            10 => Instr::InstVarSet(self.usize()?),
+            11 => Instr::Int(self.isize()?),
+            12 => Instr::Jump(self.usize()?),
+            13 => Instr::JumpIfFalse(self.usize()?),
+            14 => Instr::Pop,
+            15 => Instr::Return,
+            16 => Instr::Send(self.usize()?, 0),
+            17 => Instr::String(self.usize()?),
+            18 => Instr::Symbol(self.usize()?),
+            19 => Instr::VarLookup(self.usize()?, self.usize()?),
+            20 => Instr::VarSet(self.usize()?, self.usize()?),
+            _ => return None,
+        })
+    }
+}
@@ -1,12 +1,26 @@
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Instr {
+    ArbInt(usize),
+    /// Pop the top `usize` values from the stack and push an `Array` containing them.
+    Array(usize),
     Block(usize),
     GlobalLookup(usize),
+    /// Push the class-side variable `usize` of the class of the currently executing method.
+    ClassVarLookup(usize),
+    /// Set the class-side variable `usize` of the class of the currently executing method to the
+    /// value on top of the stack.
+    ClassVarSet(usize),
     ClosureReturn(usize),
     Double(f64),
+    Dup,
     InstVarLookup(usize),
     InstVarSet(usize),
     Int(isize),
+    /// Continue execution at the instruction at offset `usize`.
+    Jump(usize),
+    /// Pop the boolean on top of the stack: if it is `false`, continue execution at the
+    /// instruction at offset `usize`.
+    JumpIfFalse(usize),
     Pop,
     Return,
     Send(usize, usize),
@@ -19,43 +33,84 @@ pub enum Instr {
 #[derive(Clone, Copy, Debug)]
 pub enum Primitive {
     Add,
+    AddElement,
     And,
+    Arguments,
     As32BitSignedValue,
     As32BitUnsignedValue,
+    AsArray,
+    AsDouble,
     AsInteger,
+    AsIntegerRadix,
+    AsLowercase,
     AsString,
+    AsStringRadix,
     AsSymbol,
+    AsUppercase,
+    At,
+    AtPut,
     AtRandom,
+    BitOr,
+    BitShift,
     BitXor,
+    CaseSensitiveMatch,
+    Ceiling,
     Class,
+    Collect,
+    Compile,
     Cos,
     Concatenate,
+    Contains,
+    ContainsKey,
+    CopyWith,
     Div,
+    Do,
     DoubleDiv,
     Equals,
     Exit,
     Fields,
+    Floor,
+    Format,
     FromString,
+    Gcd,
+    GcObjectCount,
     Global,
     GlobalPut,
     GreaterThan,
     GreaterThanEquals,
     Halt,
     Hashcode,
+    IdentityHash,
+    IfFalse,
+    IfTrue,
+    IfTrueIfFalse,
+    IndexOf,
+    InjectInto,
     Inspect,
     InstVarAt,
     InstVarAtPut,
     InstVarNamed,
+    IsKindOf,
+    IsMemberOf,
+    IsNil,
+    JoinUsing,
+    KeysAndValuesDo,
+    Lcm,
     Length,
     Load,
+    LoadSource,
     LessThan,
     LessThanEquals,
+    Match,
     Methods,
     Mod,
     Mul,
     Name,
     NotEquals,
+    NotNil,
     New,
+    NewArray,
+    NewWeakRef,
     ObjectSize,
     Perform,
     PerformInSuperClass,
@@ -65,16 +120,39 @@ pub enum Primitive {
     PrimSubstringFromTo,
     PrintNewline,
     PrintString,
+    /// `printString` on a `String`: the string in quotes, as it would be written in source code.
+    QuotedString,
     RefEquals,
+    /// A primitive registered with
+    /// [`VM::register_primitive`](crate::vm::core::VM::register_primitive): the first `usize` is
+    /// the primitive's index in the VM, the second the number of arguments it takes.
+    Registered(usize, usize),
     Rem,
+    Remove,
+    RemoveKey,
+    ReplaceAllWith,
+    RespondsTo,
     Restart,
     Round,
+    RoundTo,
+    ShallowCopy,
     Shl,
     Shr,
     Sin,
+    Size,
     Sqrt,
     Sub,
+    Substrings,
     Superclass,
+    Ticks,
+    Time,
+    Tokenize,
+    Truncated,
     /// Is this `value` (0), `value:` (1), or `value:with:` (2)?
     Value(u8),
+    WhileFalse,
+    WhileTrue,
+    /// Is this `with:` (1), `with:with:` (2), or `with:with:with:` (3)?
+    With(u8),
+    Yourself,
 }
@@ -3,15 +3,21 @@
 //! messages). The interchange format between the compiler and the VM currently uses a Rust `enum`
 //! and is probably fairly inefficient.
 
-use std::{fs, path::Path, process};
+use std::{fmt, fs, path::Path};
 
+use itertools::Itertools;
 use lrlex::lrlex_mod;
-use lrpar::lrpar_mod;
+use lrpar::{lrpar_mod, LexParseError, Lexer, Span};
 
-use crate::vm::{val::Val, VM};
+use crate::vm::{
+    error::{VMError, VMErrorKind},
+    val::Val,
+    VM,
+};
 
 mod ast;
 mod ast_to_instrs;
+pub mod cobjects;
 pub mod instrs;
 
 lrlex_mod!("lib/compiler/som.l");
@@ -19,33 +25,188 @@ lrpar_mod!("lib/compiler/som.y");
 
 type StorageT = u32;
 
-/// Compile a class. Should only be called by the `VM`.
-pub fn compile(vm: &mut VM, path: &Path) -> (String, Val) {
-    let bytes = fs::read(path).unwrap_or_else(|_| panic!("Can't read {}.", path.to_str().unwrap()));
-    let txt = String::from_utf8_lossy(&bytes);
+/// An error which prevents a class from being compiled.
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    /// A method has `got` parameters but `expected` were needed.
+    Arity {
+        span: Span,
+        expected: usize,
+        got: usize,
+    },
+    /// A cascade whose first expression is not a message send.
+    InvalidCascade { span: Span },
+    /// A literal which can't be represented.
+    InvalidLiteral { span: Span, msg: String },
+    /// Input which the lexer could not make sense of.
+    Lex { span: Span },
+    /// A variable which shadows another of the same name.
+    ShadowedVar { name: String, span: Span },
+    /// A name which is neither a variable in scope nor a possible global.
+    UndeclaredName { name: String, span: Span },
+    /// A token the parser did not expect. `msg` includes any repairs the parser suggests.
+    UnexpectedToken { span: Span, msg: String },
+    /// An assignment to a name which is not a variable in scope.
+    UnknownField { name: String, span: Span },
+    /// A primitive method whose name the VM does not know about.
+    UnknownPrimitive { name: String, span: Span },
+}
 
-    let lexerdef = som_l::lexerdef();
-    let lexer = lexerdef.lexer(&txt);
-    let (astopt, errs) = som_y::parse(&lexer);
-    for e in &errs {
-        eprintln!("{}", e.pp(&lexer, &som_y::token_epp));
+impl CompileError {
+    /// The span of source text this error relates to.
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::Arity { span, .. }
+            | CompileError::InvalidCascade { span }
+            | CompileError::InvalidLiteral { span, .. }
+            | CompileError::Lex { span }
+            | CompileError::ShadowedVar { span, .. }
+            | CompileError::UndeclaredName { span, .. }
+            | CompileError::UnexpectedToken { span, .. }
+            | CompileError::UnknownField { span, .. }
+            | CompileError::UnknownPrimitive { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Arity { expected, got, .. } => {
+                write!(f, "Expected {} parameters, got {}", expected, got)
+            }
+            CompileError::InvalidCascade { .. } => {
+                write!(f, "Cascade must start with a message send")
+            }
+            CompileError::InvalidLiteral { msg, .. } => write!(f, "{}", msg),
+            CompileError::Lex { .. } => write!(f, "Unable to lex input"),
+            CompileError::ShadowedVar { name, .. } => {
+                write!(f, "Variable '{}' shadows another of the same name", name)
+            }
+            CompileError::UndeclaredName { name, .. } => write!(f, "Undeclared name '{}'", name),
+            CompileError::UnexpectedToken { msg, .. } => write!(f, "{}", msg),
+            CompileError::UnknownField { name, .. } => {
+                write!(f, "No such field '{}' in class", name)
+            }
+            CompileError::UnknownPrimitive { name, .. } => {
+                write!(f, "Unknown primitive '{}'", name)
+            }
+        }
     }
+}
+
+/// Compile the class whose source is `txt` (read from `path`), returning the class's name and the
+/// class itself. All errors found are returned: the caller should report them with
+/// [`format_errors`](fn.format_errors.html). Should only be called by the `VM`.
+pub fn compile(vm: &mut VM, path: &Path, txt: &str) -> Result<(String, Val), Vec<CompileError>> {
+    let lexerdef = som_l::lexerdef();
+    let lexer = lexerdef.lexer(txt);
+    let (astopt, parse_errs) = som_y::parse(&lexer);
+    let mut errs = parse_errs
+        .iter()
+        .map(|e| match e {
+            LexParseError::LexError(e) => CompileError::Lex { span: e.span() },
+            LexParseError::ParseError(pe) => CompileError::UnexpectedToken {
+                span: pe.lexeme().span(),
+                msg: e.pp(&lexer, &som_y::token_epp),
+            },
+        })
+        .collect::<Vec<_>>();
     match astopt {
-        Some(Ok(astcls)) => {
-            let (name, cls) = ast_to_instrs::Compiler::compile(vm, &lexer, &path, &astcls)
-                .unwrap_or_else(|msg| {
-                    eprintln!("{}", msg);
-                    process::exit(1);
-                });
+        Some(Ok(astcls)) => match ast_to_instrs::Compiler::compile(vm, &lexer, path, &astcls) {
+            Ok(r) if errs.is_empty() => return Ok(r),
+            Ok(_) => (),
+            Err(e) => errs.extend(e),
+        },
+        _ => {
             if errs.is_empty() {
-                (name, cls)
-            } else {
-                process::exit(1);
+                errs.push(CompileError::UnexpectedToken {
+                    span: Span::new(0, txt.len()),
+                    msg: "Unable to compile".to_owned(),
+                });
             }
         }
-        _ => {
-            eprintln!("Unable to compile {}", path.to_str().unwrap());
-            process::exit(1);
+    }
+    Err(errs)
+}
+
+/// Compile the class in the file at `path` into a [`cobjects::Class`] which does not depend on
+/// `vm`, and which can be serialised with [`cobjects::Class::to_bytes`] and later loaded with
+/// [`VM::load_compiled_class`]. The class is compiled using `vm` (so any primitives it uses must
+/// have been registered with `vm`) but is not registered as a global. A file which can't be read
+/// leads to a `CantLoadClass` error and source code which can't be compiled to a `CompileError`
+/// error.
+pub fn compile_file(vm: &mut VM, path: &Path) -> Result<cobjects::Class, Box<VMError>> {
+    let bytes = match fs::read(path) {
+        Ok(b) => b,
+        Err(_) => {
+            return Err(VMError::new(
+                vm,
+                VMErrorKind::CantLoadClass(path.to_path_buf()),
+            ))
         }
+    };
+    let txt = String::from_utf8_lossy(&bytes);
+    let (_, cls_val) = compile(vm, path, &txt)
+        .map_err(|errs| VMError::new(vm, VMErrorKind::CompileError(errs)))?;
+    cobjects::Class::from_vm(vm, &cls_val)
+}
+
+/// Format `errs`, which were returned by compiling `txt` (read from `path`), for the user.
+pub fn format_errors(path: &Path, txt: &str, errs: &[CompileError]) -> String {
+    let lexerdef = som_l::lexerdef();
+    let lexer = lexerdef.lexer(txt);
+    errs.iter()
+        .map(|e| {
+            let span = e.span();
+            let ((line_off, col), _) = lexer.line_col(span);
+            let line = lexer.span_lines_str(span).split('\n').next().unwrap();
+            format!(
+                "File '{}', line {}, column {}:\n  {}\n{}",
+                path.to_str().unwrap(),
+                line_off,
+                col,
+                line.trim(),
+                e
+            )
+        })
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiple_errors() {
+        let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+        let txt = "two_errors = (
+            a = ( ^x )
+            b = ( y := 1 )
+        )";
+        let errs = compile(&mut vm, Path::new("two_errors.som"), txt).unwrap_err();
+        assert_eq!(
+            errs,
+            vec![
+                CompileError::UndeclaredName {
+                    name: "x".to_owned(),
+                    span: Span::new(34, 35)
+                },
+                CompileError::UnknownField {
+                    name: "y".to_owned(),
+                    span: Span::new(56, 62)
+                }
+            ]
+        );
+        assert_eq!(
+            format_errors(Path::new("two_errors.som"), txt, &errs),
+            "File 'two_errors.som', line 2, column 20:
+  a = ( ^x )
+Undeclared name 'x'
+
+File 'two_errors.som', line 3, column 19:
+  b = ( y := 1 )
+No such field 'y' in class"
+        );
     }
 }
@@ -99,6 +99,7 @@ Exprs -> Result<Vec<Expr>, ()>:
 Expr -> Result<Expr, ()>:
       Assign { $1 }
     | KeywordMsg { $1 }
+    | KeywordMsg CascadeMsgs { Ok(Expr::Cascade{ span: $span, first: Box::new($1?), msgs: $2? }) }
     ;
 Assign -> Result<Expr, ()>:
       "ID" ":=" Expr { Ok(Expr::Assign{span: $span, id: map_err($1)?.span(), expr: Box::new($3?)}) };
@@ -116,6 +117,15 @@ KeywordMsgList -> Result<Vec<(Span, Expr)>, ()>:
       KeywordMsgList "KEYWORD" BinaryMsg { flattenr($1, Ok((map_err($2)?.span(), $3?))) }
     | "KEYWORD" BinaryMsg { Ok(vec![(map_err($1)?.span(), $2?)]) }
     ;
+CascadeMsgs -> Result<Vec<CascadeMsg>, ()>:
+      ";" CascadeMsg { Ok(vec![$2?]) }
+    | CascadeMsgs ";" CascadeMsg { flattenr($1, $3) }
+    ;
+CascadeMsg -> Result<CascadeMsg, ()>:
+      "ID" { Ok(CascadeMsg::UnaryMsg(map_err($1)?.span())) }
+    | BinOp UnaryMsg { Ok(CascadeMsg::BinaryMsg{ op: $1?, arg: $2? }) }
+    | KeywordMsgList { Ok(CascadeMsg::KeywordMsg($1?)) }
+    ;
 BinaryMsg -> Result<Expr, ()>:
       BinaryMsg BinOp UnaryMsg { Ok(Expr::BinaryMsg{ span: $span, lhs: Box::new($1?), op: $2?, rhs: Box::new($3?) }) }
     | UnaryMsg { $1 }
@@ -155,7 +165,7 @@ Literal -> Result<Expr, ()>:
     | "DOUBLE" { Ok(Expr::Double{ span: $span, is_negative: false, val: map_err($1)?.span() }) }
     | "-" "DOUBLE" { Ok(Expr::Double{ span: $span, is_negative: true, val: map_err($2)?.span() }) }
     | StringConst { $1 }
-    | ArrayConst { unimplemented!() }
+    | ArrayConst { $1 }
     ;
 Block -> Result<Expr, ()>:
       "[" BlockParamsOpt NameDefs BlockExprs "]" { Ok(Expr::Block{ span: $span, params: $2?, vars: $3?, exprs: $4? }) };
@@ -174,14 +184,14 @@ Argument -> Result<Option<Span>, ()>:
 StringConst -> Result<Expr, ()>:
       "#" "STRING" { unimplemented!() }
     | "#" "ID" { Ok(Expr::Symbol(map_err($2)?.span())) }
-    | "#" "KEYWORD" { unimplemented!() }
-    | "#" BinOp { unimplemented!() }
-    ;
-ArrayConst -> Result<(), ()>:
-      "#" "(" ArrayList ")" { unimplemented!() };
-ArrayList -> Result<(), ()>:
-      Unit { unimplemented!() }
-    | ArrayList Unit { unimplemented!() }
+    | "#" "KEYWORD" { Ok(Expr::Symbol(map_err($2)?.span())) }
+    | "#" BinOp { Ok(Expr::Symbol($2?)) }
+    ;
+ArrayConst -> Result<Expr, ()>:
+      "#" "(" ArrayList ")" { Ok(Expr::Array{ span: $span, items: $3? }) };
+ArrayList -> Result<Vec<Expr>, ()>:
+      Unit { Ok(vec![$1?]) }
+    | ArrayList Unit { flattenr($1, $2) }
     ;
 
 %%
@@ -0,0 +1,194 @@
+//! Configuration of a [`VM`](crate::vm::VM).
+
+use std::io::Write;
+
+use crate::vm::{core::VM, somstack::SOM_STACK_LEN};
+
+/// What should happen when an arithmetic operation on machine integers overflows?
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowMode {
+    /// Raise [`VMErrorKind::IntegerOverflow`](crate::vm::VMErrorKind::IntegerOverflow).
+    Error,
+    /// Promote the result to an arbitrary sized integer (SOM's standard semantics).
+    Promote,
+    /// Wrap around (i.e. twos complement arithmetic).
+    Wrap,
+}
+
+/// The user-configurable aspects of a [`VM`](crate::vm::VM). Users are expected to start from
+/// `VMConfig::default()` and override only those fields they care about e.g.:
+///
+/// ```text
+/// let vm = VM::with_config(VMConfig {
+///     classpath: vec!["lib/SOM".to_owned()],
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Clone, Debug)]
+pub struct VMConfig {
+    /// The directories to search (in order) for classes, including builtin classes such as
+    /// `Object`.
+    pub classpath: Vec<String>,
+    /// The number of values that can initially be stored on the SOM stack. The stack grows as
+    /// needed, so this is purely a hint.
+    pub stack_len: usize,
+    /// What happens when integer arithmetic overflows?
+    pub overflow_mode: OverflowMode,
+    /// The maximum depth of the call stack (or `None` for no limit). Exceeding this depth raises
+    /// [`VMErrorKind::CallDepthExceeded`](crate::vm::VMErrorKind::CallDepthExceeded).
+    pub max_call_depth: Option<usize>,
+    /// The maximum number of bytes the heap may occupy (or `None` for no limit), where each object
+    /// is counted as the size of its layout. If an allocation takes the heap over this limit, and
+    /// dropping the VM's caches (see [`VM::drop_caches`](crate::vm::VM::drop_caches)) doesn't bring
+    /// it back under, the next instruction executed raises
+    /// [`VMErrorKind::OutOfMemory`](crate::vm::VMErrorKind::OutOfMemory). Note that the limit
+    /// applies to the whole heap, including the objects created during bootstrapping.
+    pub max_heap_bytes: Option<usize>,
+    /// Should each class's bytecode be verified (see
+    /// [`Class::verify`](crate::vm::objects::Class::verify)) when it is loaded?
+    pub verify_bytecode: bool,
+    /// The maximum number of bytecode instructions the VM may execute over its lifetime (or `None`
+    /// for no limit). Executing more raises
+    /// [`VMErrorKind::InstructionLimitExceeded`](crate::vm::VMErrorKind::InstructionLimitExceeded).
+    pub max_instrs: Option<u64>,
+    /// The maximum number of bytes `System`'s printing primitives may write over the VM's lifetime
+    /// (or `None` for no limit). A print which would take the output over this limit writes
+    /// nothing and raises
+    /// [`VMErrorKind::OutputLimitExceeded`](crate::vm::VMErrorKind::OutputLimitExceeded).
+    pub max_output_bytes: Option<usize>,
+    /// Can SOM code read class files from the classpath (i.e. `System>>load:`)? If not, such loads
+    /// raise
+    /// [`VMErrorKind::FilesystemAccessDenied`](crate::vm::VMErrorKind::FilesystemAccessDenied).
+    /// Builtin classes are always loaded during bootstrapping, and the embedder can still call
+    /// [`VM::load_class`](crate::vm::VM::load_class).
+    pub allow_fs: bool,
+    /// Should a send to a user method in tail position (i.e. immediately followed by a `^`) reuse
+    /// the sender's frame, so that tail recursion runs in constant stack? The replaced frames are
+    /// then absent from backtraces, and don't count towards
+    /// [`max_call_depth`](VMConfig::max_call_depth).
+    pub tail_calls: bool,
+}
+
+impl Default for VMConfig {
+    fn default() -> Self {
+        VMConfig {
+            classpath: Vec::new(),
+            stack_len: SOM_STACK_LEN,
+            overflow_mode: OverflowMode::Promote,
+            max_call_depth: None,
+            max_heap_bytes: None,
+            verify_bytecode: false,
+            max_instrs: None,
+            max_output_bytes: None,
+            allow_fs: true,
+            tail_calls: false,
+        }
+    }
+}
+
+/// Build a [`VM`](crate::vm::VM) step-by-step e.g.:
+///
+/// ```text
+/// let vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+///     .output(Box::new(buf))
+///     .max_call_depth(1000)
+///     .build();
+/// ```
+///
+/// Any settings not explicitly set take their default values (see
+/// [`VMConfig::default`](VMConfig::default)).
+pub struct VMBuilder {
+    config: VMConfig,
+    output: Option<Box<dyn Write>>,
+    trace: Option<Box<dyn Write>>,
+}
+
+impl VMBuilder {
+    /// Start building a VM which searches for classes in `classpath`.
+    pub fn new(classpath: Vec<String>) -> Self {
+        VMBuilder {
+            config: VMConfig {
+                classpath,
+                ..Default::default()
+            },
+            output: None,
+            trace: None,
+        }
+    }
+
+    /// Where should `System`'s printing primitives write to (defaults to stdout)?
+    pub fn output(mut self, output: Box<dyn Write>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Log every instruction executed to `trace`.
+    pub fn trace(mut self, trace: Box<dyn Write>) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// See [`VMConfig::stack_len`](VMConfig::stack_len).
+    pub fn stack_len(mut self, stack_len: usize) -> Self {
+        self.config.stack_len = stack_len;
+        self
+    }
+
+    /// See [`VMConfig::overflow_mode`](VMConfig::overflow_mode).
+    pub fn overflow_mode(mut self, overflow_mode: OverflowMode) -> Self {
+        self.config.overflow_mode = overflow_mode;
+        self
+    }
+
+    /// See [`VMConfig::max_call_depth`](VMConfig::max_call_depth).
+    pub fn max_call_depth(mut self, max_call_depth: usize) -> Self {
+        self.config.max_call_depth = Some(max_call_depth);
+        self
+    }
+
+    /// See [`VMConfig::max_heap_bytes`](VMConfig::max_heap_bytes).
+    pub fn max_heap_bytes(mut self, max_heap_bytes: usize) -> Self {
+        self.config.max_heap_bytes = Some(max_heap_bytes);
+        self
+    }
+
+    /// See [`VMConfig::verify_bytecode`](VMConfig::verify_bytecode).
+    pub fn verify_bytecode(mut self, verify_bytecode: bool) -> Self {
+        self.config.verify_bytecode = verify_bytecode;
+        self
+    }
+
+    /// See [`VMConfig::max_instrs`](VMConfig::max_instrs).
+    pub fn max_instrs(mut self, max_instrs: u64) -> Self {
+        self.config.max_instrs = Some(max_instrs);
+        self
+    }
+
+    /// See [`VMConfig::max_output_bytes`](VMConfig::max_output_bytes).
+    pub fn max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+        self.config.max_output_bytes = Some(max_output_bytes);
+        self
+    }
+
+    /// See [`VMConfig::allow_fs`](VMConfig::allow_fs).
+    pub fn allow_fs(mut self, allow_fs: bool) -> Self {
+        self.config.allow_fs = allow_fs;
+        self
+    }
+
+    /// See [`VMConfig::tail_calls`](VMConfig::tail_calls).
+    pub fn tail_calls(mut self, tail_calls: bool) -> Self {
+        self.config.tail_calls = tail_calls;
+        self
+    }
+
+    /// Create, and bootstrap, the VM.
+    pub fn build(self) -> VM {
+        let mut vm = VM::with_config(self.config);
+        if let Some(output) = self.output {
+            vm.set_output(output);
+        }
+        vm.set_trace(self.trace);
+        vm
+    }
+}
@@ -4,31 +4,68 @@ use std::{
     cell::UnsafeCell,
     collections::HashMap,
     convert::TryFrom,
+    fs,
+    io::{self, Write},
+    mem,
     path::{Path, PathBuf},
     process,
-    rc::Rc,
+    time::{Duration, Instant},
 };
 
 use abgc::{Gc, GcLayout};
 use lrpar::Span;
+use num_bigint::BigInt;
 
 use crate::{
     compiler::{
-        compile,
+        cobjects, compile, format_errors,
         instrs::{Instr, Primitive},
     },
     vm::{
+        config::{OverflowMode, VMConfig},
         error::{VMError, VMErrorKind},
+        gc::{self, GCStats},
         objects::{
-            Block, BlockInfo, Class, Double, Inst, Int, Method, MethodBody, StaticObjType, String_,
+            gcd_lcm, to_radix_string, ArbInt, Array, Block, BlockInfo, Class, Dictionary, Double,
+            Inst, Int, Method, MethodBody, ObjType, RoundMode, Set, StaticObjType, String_,
+            ThinObj, WeakRef,
         },
-        somstack::SOMStack,
+        somstack::{SOMStack, SOM_STACK_LEN},
         val::{Val, ValKind},
     },
 };
 
 pub const SOM_EXTENSION: &str = "som";
 
+/// An interned symbol (see [`VM::intern_symbol`]), which is an index into the VM's symbols. Two
+/// `SymId`s from the same VM are equal if, and only if, they refer to the same symbol.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SymId(pub(crate) usize);
+
+/// The type of step hooks set with [`VM::set_step_hook`].
+pub type StepHook = Box<dyn FnMut(&mut VM) -> StepAction>;
+
+/// The type of handlers for unknown methods set with [`VM::set_dnu_handler`]. The handler is
+/// passed the receiver, the selector, and the arguments of the send which failed.
+pub type DnuHandler = Box<dyn FnMut(&mut VM, Val, &str, &[Val]) -> Result<Val, Box<VMError>>>;
+
+/// What should the VM do after a step hook returns?
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepAction {
+    /// Execute the instruction.
+    Continue,
+    /// Don't execute the instruction yet: call the hook again first.
+    Pause,
+    /// Stop executing, unwinding the call stack with an [`VMErrorKind::Aborted`] error.
+    Abort,
+}
+
+/// The type of primitives registered with [`VM::register_primitive`]. Such functions are passed
+/// the receiver and arguments of a send.
+pub type PrimitiveFn = fn(&mut VM, Val, &[Val]) -> Result<Val, Box<VMError>>;
+/// The name of the synthetic class which [`VM::eval`] compiles source code into.
+const EVAL_CLS_NAME: &str = "Eval";
+
 #[derive(Debug)]
 /// The result of a non-top-level SOM send.
 enum SendReturn {
@@ -43,25 +80,35 @@ enum SendReturn {
 /// The core VM struct.
 pub struct VM {
     classpath: Vec<String>,
+    pub array_cls: Val,
     pub block_cls: Val,
     pub block2_cls: Val,
     pub block3_cls: Val,
     pub bool_cls: Val,
+    pub char_cls: Val,
     pub cls_cls: Val,
+    pub dict_cls: Val,
     pub double_cls: Val,
     pub false_cls: Val,
+    pub fraction_cls: Val,
     pub int_cls: Val,
     pub metacls_cls: Val,
     pub nil_cls: Val,
     pub obj_cls: Val,
+    pub set_cls: Val,
     pub str_cls: Val,
     pub sym_cls: Val,
     pub system_cls: Val,
     pub true_cls: Val,
+    pub weakref_cls: Val,
     pub false_: Val,
     pub nil: Val,
     pub system: Val,
     pub true_: Val,
+    /// Integer literals which are too big to be represented as an `isize`.
+    arbints: Vec<Val>,
+    /// A map from an integer literal to its index in `arbints`.
+    reverse_arbints: HashMap<BigInt, usize>,
     blockinfos: Vec<BlockInfo>,
     /// The current known set of globals including those not yet assigned to: in other words, it is
     /// expected that some entries of this `Vec` are illegal (i.e. created by `Val::illegal`).
@@ -72,10 +119,11 @@ pub struct VM {
     /// rarely access `instr_spans`.
     instrs: Vec<Instr>,
     instr_spans: Vec<Span>,
-    sends: Vec<(Rc<String>, usize)>,
-    /// reverse_sends is an optimisation allowing us to reuse sends: it maps a send `(String,
+    /// Each send is a (selector, number of arguments) pair.
+    sends: Vec<(SymId, usize)>,
+    /// reverse_sends is an optimisation allowing us to reuse sends: it maps a send `(SymId,
     /// usize)` to a `usize` where the latter represents the index of the send in `sends`.
-    reverse_sends: HashMap<(Rc<String>, usize), usize>,
+    reverse_sends: HashMap<(SymId, usize), usize>,
     stack: SOMStack,
     strings: Vec<Val>,
     /// reverse_strings is an optimisation allowing us to reuse strings: it maps a `String to a
@@ -84,10 +132,59 @@ pub struct VM {
     symbols: Vec<Val>,
     reverse_symbols: HashMap<String, usize>,
     frames: Vec<Frame>,
+    /// Primitives registered by embedders, indexed by `Primitive::Registered`.
+    primitives: Vec<PrimitiveFn>,
+    /// Maps the name of a registered primitive to its index in `primitives`.
+    reverse_primitives: HashMap<String, usize>,
+    /// The names of the custom object types registered by embedders, indexed by `ObjType::Custom`.
+    custom_objtypes: Vec<String>,
+    /// Where `System`'s printing primitives write to.
+    output: Box<dyn Write>,
+    /// If set, every instruction is logged here before it is executed.
+    trace: Option<Box<dyn Write>>,
+    /// If set, called before every instruction is executed.
+    step_hook: Option<StepHook>,
+    /// While `step_hook` is running: the method being executed, the offset of the instruction
+    /// about to be executed within it, and the stack length when the method started.
+    hook_pos: Option<(Gc<Method>, usize, usize)>,
+    /// If set, called when a message is sent to an object which has no method for it.
+    dnu_handler: Option<DnuHandler>,
+    /// How many bytecode instructions have been executed?
+    instrs_executed: u64,
+    /// Should `profile` be updated as the VM executes?
+    profiling: bool,
+    profile: ProfileReport,
+    /// When was this VM created? `System ticks` and `System time` are relative to this.
+    start: Instant,
+    /// The program's arguments, as returned by `System arguments`.
+    args: Vec<String>,
+    overflow_mode: OverflowMode,
+    max_call_depth: Option<usize>,
+    max_heap_bytes: Option<usize>,
+    /// Set when an allocation has exceeded `max_heap_bytes`: the next instruction executed raises
+    /// [`VMErrorKind::OutOfMemory`].
+    heap_exhausted: bool,
+    verify_bytecode: bool,
+    max_instrs: Option<u64>,
+    max_output_bytes: Option<usize>,
+    /// How many bytes have `System`'s printing primitives written?
+    output_bytes: usize,
+    allow_fs: bool,
+    tail_calls: bool,
 }
 
 impl VM {
+    /// Create a new VM which searches for classes in `classpath`, using the default value for all
+    /// other settings.
     pub fn new(classpath: Vec<String>) -> Self {
+        VM::with_config(VMConfig {
+            classpath,
+            ..Default::default()
+        })
+    }
+
+    /// Create a new VM with the settings in `config`.
+    pub fn with_config(config: VMConfig) -> Self {
         // The bootstrapping phase is delicate: we need to bootstrap the Object, Class, and Nil
         // classes before we can create basic objects like nil. We thus perform bootstrapping in
         // two phases: the "very delicate" phase (with very strict rules on what is possible)
@@ -95,26 +192,34 @@ impl VM {
         // on what is possible).
 
         let mut vm = VM {
-            classpath,
+            classpath: config.classpath,
+            array_cls: Val::illegal(),
             block_cls: Val::illegal(),
             bool_cls: Val::illegal(),
             block2_cls: Val::illegal(),
             block3_cls: Val::illegal(),
+            char_cls: Val::illegal(),
             cls_cls: Val::illegal(),
+            dict_cls: Val::illegal(),
             double_cls: Val::illegal(),
             false_cls: Val::illegal(),
+            fraction_cls: Val::illegal(),
             int_cls: Val::illegal(),
             metacls_cls: Val::illegal(),
             nil_cls: Val::illegal(),
             obj_cls: Val::illegal(),
+            set_cls: Val::illegal(),
             str_cls: Val::illegal(),
             sym_cls: Val::illegal(),
             system_cls: Val::illegal(),
             true_cls: Val::illegal(),
+            weakref_cls: Val::illegal(),
             false_: Val::illegal(),
             nil: Val::illegal(),
             system: Val::illegal(),
             true_: Val::illegal(),
+            arbints: Vec::new(),
+            reverse_arbints: HashMap::new(),
             blockinfos: Vec::new(),
             globals: Vec::new(),
             reverse_globals: HashMap::new(),
@@ -123,12 +228,35 @@ impl VM {
             instr_spans: Vec::new(),
             sends: Vec::new(),
             reverse_sends: HashMap::new(),
-            stack: SOMStack::new(),
+            stack: SOMStack::new(config.stack_len),
             strings: Vec::new(),
             reverse_strings: HashMap::new(),
             symbols: Vec::new(),
             reverse_symbols: HashMap::new(),
             frames: Vec::new(),
+            primitives: Vec::new(),
+            reverse_primitives: HashMap::new(),
+            custom_objtypes: Vec::new(),
+            output: Box::new(io::stdout()),
+            trace: None,
+            step_hook: None,
+            dnu_handler: None,
+            hook_pos: None,
+            instrs_executed: 0,
+            profiling: false,
+            profile: ProfileReport::default(),
+            start: Instant::now(),
+            args: vec![],
+            overflow_mode: config.overflow_mode,
+            max_call_depth: config.max_call_depth,
+            max_heap_bytes: config.max_heap_bytes,
+            heap_exhausted: false,
+            verify_bytecode: config.verify_bytecode,
+            max_instrs: config.max_instrs,
+            max_output_bytes: config.max_output_bytes,
+            output_bytes: 0,
+            allow_fs: config.allow_fs,
+            tail_calls: config.tail_calls,
         };
         // The very delicate phase.
         //
@@ -183,17 +311,23 @@ impl VM {
         // The slightly delicate phase.
         //
         // Nothing in this phase must store references to any classes earlier than it in the phase.
+        vm.array_cls = vm.init_builtin_class("Array", false);
         vm.block_cls = vm.init_builtin_class("Block", false);
         vm.block2_cls = vm.init_builtin_class("Block2", false);
         vm.block3_cls = vm.init_builtin_class("Block3", false);
         vm.bool_cls = vm.init_builtin_class("Boolean", false);
+        vm.char_cls = vm.init_builtin_class("Character", false);
+        vm.dict_cls = vm.init_builtin_class("Dictionary", false);
         vm.double_cls = vm.init_builtin_class("Double", false);
         vm.false_cls = vm.init_builtin_class("False", false);
+        vm.fraction_cls = vm.init_builtin_class("Fraction", false);
         vm.int_cls = vm.init_builtin_class("Integer", false);
+        vm.set_cls = vm.init_builtin_class("Set", false);
         vm.str_cls = vm.init_builtin_class("String", false);
         vm.sym_cls = vm.init_builtin_class("Symbol", false);
         vm.system_cls = vm.init_builtin_class("System", false);
         vm.true_cls = vm.init_builtin_class("True", false);
+        vm.weakref_cls = vm.init_builtin_class("WeakRef", false);
         let v = vm.false_cls.clone();
         vm.false_ = Inst::new(&mut vm, v);
         let v = vm.system_cls.clone();
@@ -215,15 +349,158 @@ impl VM {
     /// Compile the file at `path`. `inst_vars_allowed` should be set to `false` only for those
     /// builtin classes which do not lead to run-time instances of `Inst`.
     pub fn compile(&mut self, path: &Path, inst_vars_allowed: bool) -> Val {
-        let (name, cls_val) = compile(self, path);
+        let bytes =
+            fs::read(path).unwrap_or_else(|_| panic!("Can't read {}.", path.to_str().unwrap()));
+        let txt = String::from_utf8_lossy(&bytes);
+        let (name, cls_val) = compile(self, path, &txt).unwrap_or_else(|errs| {
+            eprintln!("{}", format_errors(path, &txt, &errs));
+            process::exit(1);
+        });
         let cls: &Class = cls_val.downcast(self).unwrap();
         if !inst_vars_allowed && cls.num_inst_vars > 0 {
             panic!("No instance vars allowed in {}", path.to_str().unwrap());
         }
+        if let Err(e) = self.verify_if_enabled(cls) {
+            eprintln!("{}: {}", path.to_str().unwrap(), e.render(self));
+            process::exit(1);
+        }
         self.set_global(&name, cls_val.clone());
         cls_val
     }
 
+    /// If bytecode verification is enabled, verify `cls` and its metaclass.
+    fn verify_if_enabled(&self, cls: &Class) -> Result<(), Box<VMError>> {
+        if self.verify_bytecode {
+            cls.verify(self)?;
+            cls.metacls(self).downcast::<Class>(self)?.verify(self)?;
+        }
+        Ok(())
+    }
+
+    /// Compile the SOM class whose source code is `source`, registering it as a global under the
+    /// class's name and returning it. As with [`VM::load_class`], a class of the same name which
+    /// has already been loaded is replaced. Source code which can't be compiled leads to a
+    /// `CompileError` error.
+    pub fn load_source(&mut self, source: &str) -> Result<Val, Box<VMError>> {
+        let path = PathBuf::from("<source>");
+        let (name, cls_val) = compile(self, &path, source)
+            .map_err(|errs| VMError::new(self, VMErrorKind::CompileError(errs)))?;
+        self.verify_if_enabled(cls_val.downcast(self)?)?;
+        self.set_global(&name, cls_val.clone());
+        Ok(cls_val)
+    }
+
+    /// Load and compile the class at `path`, registering it as a global under the class's name
+    /// (which is taken from the file's contents, not its filename). If a class of the same name has
+    /// already been loaded, the new class replaces it, though existing instances of the old class
+    /// are unaffected. A file which can't be read leads to a `CantLoadClass` error and source code
+    /// which can't be compiled to a `CompileError` error.
+    pub fn load_class(&mut self, path: &Path) -> Result<Val, Box<VMError>> {
+        let bytes = match fs::read(path) {
+            Ok(b) => b,
+            Err(_) => {
+                return Err(VMError::new(
+                    self,
+                    VMErrorKind::CantLoadClass(path.to_path_buf()),
+                ))
+            }
+        };
+        let txt = String::from_utf8_lossy(&bytes);
+        let (name, cls_val) = compile(self, path, &txt)
+            .map_err(|errs| VMError::new(self, VMErrorKind::CompileError(errs)))?;
+        self.verify_if_enabled(cls_val.downcast(self)?)?;
+        self.set_global(&name, cls_val.clone());
+        Ok(cls_val)
+    }
+
+    /// Load a class compiled with [`compile_file`](crate::compiler::compile_file) and serialised
+    /// with [`cobjects::Class::to_bytes`], registering it as a global under the class's name and
+    /// returning it. As with [`VM::load_class`], a class of the same name which has already been
+    /// loaded is replaced. `bytes` which are not a serialised class lead to an
+    /// `InvalidCompiledClass` error.
+    pub fn load_compiled_class(&mut self, bytes: &[u8]) -> Result<Val, Box<VMError>> {
+        let ccls = cobjects::Class::from_bytes(bytes)
+            .ok_or_else(|| VMError::new(self, VMErrorKind::InvalidCompiledClass))?;
+        let cls_val = ccls.link(self)?;
+        self.verify_if_enabled(cls_val.downcast(self)?)?;
+        self.set_global(&ccls.name, cls_val.clone());
+        Ok(cls_val)
+    }
+
+    /// Compile the method(s) in `source` and add them to the class `cls_val` (see
+    /// [`Class::add_method`]), replacing any existing methods of the same name. The methods can
+    /// refer to the class's instance variables and, for a class (rather than a metaclass), to its
+    /// class-side variables. Source code which can't be compiled leads to a `CompileError` error,
+    /// in which case `cls_val` is left unchanged.
+    pub fn compile_method(&mut self, cls_val: &Val, source: &str) -> Result<(), Box<VMError>> {
+        let cls: &Class = cls_val.downcast(self)?;
+        // The methods are compiled as part of a synthetic class with the same variables as `cls`,
+        // so they refer to those variables by the same indices.
+        let metacls_val = cls.metacls(self);
+        let is_metacls = metacls_val.bit_eq(&self.metacls_cls);
+        let txt = if is_metacls {
+            format!(
+                "{} = ( ---- | {} | {}\n)",
+                EVAL_CLS_NAME,
+                cls.inst_var_names.join(" "),
+                source
+            )
+        } else {
+            let metacls: &Class = metacls_val.downcast(self)?;
+            format!(
+                "{} = ( | {} | {}\n---- | {} | )",
+                EVAL_CLS_NAME,
+                cls.inst_var_names.join(" "),
+                source,
+                metacls.inst_var_names.join(" ")
+            )
+        };
+        let path = PathBuf::from(format!("<{}>", EVAL_CLS_NAME));
+        let new_cls_val = compile(self, &path, &txt)
+            .map_err(|errs| VMError::new(self, VMErrorKind::CompileError(errs)))?
+            .1;
+        self.verify_if_enabled(new_cls_val.downcast(self)?)?;
+        let new_cls_val = if is_metacls {
+            new_cls_val.downcast::<Class>(self)?.metacls(self)
+        } else {
+            new_cls_val
+        };
+        let meths = new_cls_val
+            .downcast::<Class>(self)?
+            .methods()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for m in meths {
+            cls.add_method(self, m);
+        }
+        Ok(())
+    }
+
+    /// Create a new instance of the class `class`, with each of its instance variables set to
+    /// `nil` (see [`Inst::get_field`] and [`Inst::set_field`]). If `class` is not a class, a
+    /// `TypeError` is returned.
+    pub fn new_instance(&mut self, class: Val) -> Result<Val, Box<VMError>> {
+        class.downcast::<Class>(self)?;
+        Ok(Inst::new(self, class))
+    }
+
+    /// Compile and run the SOM statements in `source`, returning the value of the last statement.
+    /// `source` is compiled as the body of a block within a synthetic method, so it may declare
+    /// its own temporaries, but these do not persist between calls: only changes to the VM's
+    /// state (e.g. globals and loaded classes) do. Source code which can't be compiled leads to a
+    /// `CompileError` error.
+    pub fn eval(&mut self, source: &str) -> Result<Val, Box<VMError>> {
+        let txt = format!("{} = ( run = ( ^[ {}\n] value ) )", EVAL_CLS_NAME, source);
+        let path = PathBuf::from(format!("<{}>", EVAL_CLS_NAME));
+        let cls = compile(self, &path, &txt)
+            .map_err(|errs| VMError::new(self, VMErrorKind::CompileError(errs)))?
+            .1;
+        self.verify_if_enabled(cls.downcast(self)?)?;
+        let inst = Inst::new(self, cls);
+        self.top_level_send(inst, "run", vec![])
+    }
+
     fn find_class(&self, name: &str) -> Result<PathBuf, ()> {
         for dn in &self.classpath {
             let mut pb = PathBuf::new();
@@ -249,12 +526,93 @@ impl VM {
         val
     }
 
+    /// Return the SOM class whose instances are represented by the Rust type `objtype`, or `None`
+    /// if there is no such class.
+    pub fn objtype_cls(&self, objtype: ObjType) -> Option<&Val> {
+        match objtype {
+            ObjType::ArbInt | ObjType::Int => Some(&self.int_cls),
+            ObjType::Array => Some(&self.array_cls),
+            ObjType::Block => Some(&self.block_cls),
+            ObjType::Character => Some(&self.char_cls),
+            ObjType::Class => Some(&self.cls_cls),
+            ObjType::Custom(_) => None,
+            ObjType::Dictionary => Some(&self.dict_cls),
+            ObjType::Double => Some(&self.double_cls),
+            ObjType::Fraction => Some(&self.fraction_cls),
+            ObjType::Inst => Some(&self.obj_cls),
+            ObjType::Method => None,
+            ObjType::Set => Some(&self.set_cls),
+            ObjType::String_ => Some(&self.str_cls),
+            ObjType::WeakRef => Some(&self.weakref_cls),
+        }
+    }
+
     /// Inform the user of the error string `error` and then exit.
     pub fn error(&self, error: &str) -> ! {
         eprintln!("{}", error);
         process::exit(1);
     }
 
+    /// Run the SOM program whose main class is `cls` by sending `run` to a new instance of it,
+    /// returning the program's exit status: 0 if `run` returns normally, or the status passed to
+    /// `System>>exit:`.
+    pub fn run(&mut self, cls: Val) -> Result<i32, Box<VMError>> {
+        let app = Inst::new(self, cls);
+        match self.top_level_send(app, "run", vec![]) {
+            Ok(_) => Ok(0),
+            Err(e) => match e.kind {
+                VMErrorKind::Exit { code } => Ok(code),
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Send the message `selector` to `entry`, returning the result of the send and how long (in
+    /// wall-clock time) it took. The number of instructions executed during the send can be found
+    /// by comparing [`VM::instrs_executed`] before and after calling this function.
+    pub fn run_timed(
+        &mut self,
+        entry: Val,
+        selector: &str,
+    ) -> (Result<Val, Box<VMError>>, Duration) {
+        let before = Instant::now();
+        let r = self.top_level_send(entry, selector, vec![]);
+        (r, before.elapsed())
+    }
+
+    /// Return the number of bytecode instructions this VM has executed.
+    pub fn instrs_executed(&self) -> u64 {
+        self.instrs_executed
+    }
+
+    /// Turn profiling on or off. Turning profiling on resets the counters returned by
+    /// [`VM::profile_report`].
+    pub fn set_profiling(&mut self, profiling: bool) {
+        if profiling {
+            self.profile = ProfileReport::default();
+        }
+        self.profiling = profiling;
+    }
+
+    /// Return the counters collected since profiling was last turned on (see
+    /// [`VM::set_profiling`]).
+    pub fn profile_report(&self) -> ProfileReport {
+        self.profile.clone()
+    }
+
+    /// Record a send of `method` in the profile.
+    fn profile_send(&mut self, method: &Method) {
+        self.profile.sends += 1;
+        if let MethodBody::Primitive(_) = method.body {
+            match self.profile.primitives.get_mut(&method.name) {
+                Some(c) => *c += 1,
+                None => {
+                    self.profile.primitives.insert(method.name.clone(), 1);
+                }
+            }
+        }
+    }
+
     /// Send the message `msg` to the receiver `rcv` with arguments `args`.
     pub fn top_level_send(
         &mut self,
@@ -264,20 +622,33 @@ impl VM {
     ) -> Result<Val, Box<VMError>> {
         assert!(self.frames_len() == 0);
         let cls = rcv.get_class(self);
-        let meth = cls.downcast::<Class>(self)?.get_method(self, msg)?;
+        let meth = match cls.downcast::<Class>(self)?.get_method(self, msg) {
+            Ok(m) => m,
+            Err(e) => return self.dnu(rcv, msg, &args, e),
+        };
         match meth.body {
             MethodBody::Primitive(_) => {
                 panic!("Primitives can't be called outside of a function frame.");
             }
             MethodBody::User {
+                num_params,
                 num_vars,
                 bytecode_off,
                 max_stack,
+                ..
             } => {
-                if self.stack.remaining_capacity() < max_stack {
-                    panic!("Not enough stack space to execute method.");
-                }
                 let nargs = args.len();
+                if nargs != num_params {
+                    return Err(VMError::new(
+                        self,
+                        VMErrorKind::WrongNumberOfArgs {
+                            expected: num_params,
+                            got: nargs,
+                        },
+                    ));
+                }
+                let stack_len = self.stack.len();
+                self.stack.reserve(nargs + max_stack);
                 for a in args {
                     self.stack.push(a);
                 }
@@ -287,24 +658,75 @@ impl VM {
                 self.frame_pop();
                 match r {
                     SendReturn::ClosureReturn(_) => unreachable!(),
-                    SendReturn::Err(e) => Err(Box::new(*e)),
+                    SendReturn::Err(e) => {
+                        // An error can leave arbitrary values on the stack: discarding them
+                        // means that any objects only they reference can be freed.
+                        self.stack.truncate(stack_len);
+                        Err(Box::new(*e))
+                    }
                     SendReturn::Val => Ok(self.stack.pop()),
                 }
             }
         }
     }
 
+    /// Execute the `inline` instruction of a method (see [`MethodBody::User`]) with receiver `rcv`.
+    fn exec_inline(&mut self, rcv: Val, instr: Instr) -> SendReturn {
+        macro_rules! stry {
+            ($elem:expr) => {{
+                let e = $elem;
+                match e {
+                    Ok(o) => o,
+                    Err(e) => return SendReturn::Err(e),
+                }
+            }};
+        }
+
+        let v = match instr {
+            Instr::InstVarLookup(n) => stry!(rcv.tobj(self)).inst_var_lookup(n),
+            Instr::Int(i) => stry!(Val::from_isize(self, i)),
+            Instr::String(string_off) => self.strings[string_off].clone(),
+            Instr::Symbol(symbol_off) => self.symbols[symbol_off].clone(),
+            _ => unreachable!(),
+        };
+        self.stack.push(v);
+        SendReturn::Val
+    }
+
     /// This function should only be called via the `send_args_on_stack!` macro.
     fn send_args_on_stack(&mut self, rcv: Val, method: Gc<Method>, nargs: usize) -> SendReturn {
+        if self.profiling {
+            self.profile_send(&method);
+        }
         match method.body {
             MethodBody::Primitive(p) => self.exec_primitive(p, rcv),
             MethodBody::User {
+                num_params,
                 num_vars,
                 bytecode_off,
                 max_stack,
+                inline,
             } => {
-                if self.stack.remaining_capacity() < max_stack {
-                    panic!("Not enough stack space to execute method.");
+                if nargs != num_params {
+                    // The arguments have already been pushed, so we remove them to leave the stack
+                    // as the caller expects.
+                    self.stack.truncate(self.stack.len() - nargs);
+                    return SendReturn::Err(VMError::new(
+                        self,
+                        VMErrorKind::WrongNumberOfArgs {
+                            expected: num_params,
+                            got: nargs,
+                        },
+                    ));
+                }
+                if let Some(e) = self.check_call_depth() {
+                    return SendReturn::Err(e);
+                }
+                self.stack.reserve(max_stack);
+                // Step hooks expect to see every instruction of every method, so we only inline
+                // when there isn't one.
+                if let (Some(instr), None) = (inline, &self.step_hook) {
+                    return self.exec_inline(rcv, instr);
                 }
                 let nframe = Frame::new(self, true, rcv.clone(), None, num_vars, nargs);
                 self.frames.push(nframe);
@@ -317,7 +739,12 @@ impl VM {
 
     /// Execute a SOM method. Note that the frame for this method must have been created *before*
     /// calling this function.
-    fn exec_user(&mut self, rcv: Val, method: Gc<Method>, meth_start_pc: usize) -> SendReturn {
+    fn exec_user(
+        &mut self,
+        mut rcv: Val,
+        mut method: Gc<Method>,
+        mut meth_start_pc: usize,
+    ) -> SendReturn {
         let mut pc = meth_start_pc;
 
         macro_rules! stry {
@@ -333,6 +760,21 @@ impl VM {
             }};
         }
 
+        // Instructions refer to the VM's tables by index: the compiler only generates valid
+        // indices, but bytecode from elsewhere may not, so we check them rather than panic.
+        macro_rules! check_operand {
+            ($cond:expr) => {{
+                if !$cond {
+                    stry!(Err(VMError::new(
+                        self,
+                        VMErrorKind::MalformedBytecode {
+                            instr_offset: pc - meth_start_pc
+                        }
+                    )));
+                }
+            }};
+        }
+
         macro_rules! send_args_on_stack {
             ($send_rcv:expr, $send_method:expr, $nargs:expr) => {{
                 match self.send_args_on_stack($send_rcv, $send_method, $nargs) {
@@ -356,12 +798,54 @@ impl VM {
                 debug_assert!(pc < self.instrs.len());
                 *unsafe { self.instrs.get_unchecked(pc) }
             };
+            self.instrs_executed += 1;
+            if let Some(max) = self.max_instrs {
+                if self.instrs_executed > max {
+                    stry!(Err(VMError::new(
+                        self,
+                        VMErrorKind::InstructionLimitExceeded(max)
+                    )));
+                }
+            }
+            if self.profiling {
+                self.profile.instrs += 1;
+            }
+            if self.trace.is_some() {
+                self.trace_instr(&method, pc - meth_start_pc, instr, stack_start);
+            }
+            if self.step_hook.is_some() {
+                stry!(self.call_step_hook(&method, pc - meth_start_pc, stack_start));
+            }
+            if self.heap_exhausted {
+                // Allocation can't fail, so an over-large heap is only reported here, when the
+                // stack is in a consistent state.
+                self.heap_exhausted = false;
+                stry!(Err(VMError::new(self, VMErrorKind::OutOfMemory)));
+            }
             match instr {
+                Instr::ArbInt(arbint_off) => {
+                    check_operand!(arbint_off < self.arbints.len());
+                    let v = self.arbints[arbint_off].clone();
+                    self.stack.push(v);
+                    pc += 1;
+                }
+                Instr::Array(n) => {
+                    let mut elems = Vec::with_capacity(n);
+                    for _ in 0..n {
+                        elems.push(self.stack.pop());
+                    }
+                    elems.reverse();
+                    let v = Array::from_vec(self, elems);
+                    self.stack.push(v);
+                    pc += 1;
+                }
                 Instr::Block(blkinfo_off) => {
+                    check_operand!(blkinfo_off < self.blockinfos.len());
                     let (num_params, bytecode_end) = {
                         let blkinfo = &self.blockinfos[blkinfo_off];
                         (blkinfo.num_params, blkinfo.bytecode_end)
                     };
+                    self.current_frame().captured = true;
                     let closure = Gc::clone(&self.current_frame().closure);
                     let v = Block::new(
                         self,
@@ -374,6 +858,16 @@ impl VM {
                     self.stack.push(v);
                     pc = bytecode_end;
                 }
+                Instr::ClassVarLookup(n) => {
+                    let cls = stry!(method.class().tobj(self));
+                    self.stack.push(cls.inst_var_lookup(n));
+                    pc += 1;
+                }
+                Instr::ClassVarSet(n) => {
+                    let cls = stry!(method.class().tobj(self));
+                    cls.inst_var_set(n, self.stack.peek());
+                    pc += 1;
+                }
                 Instr::ClosureReturn(closure_depth) => {
                     // We want to do a non-local return. Before we attempt that, we need to
                     // check that the block hasn't escaped its function (and we know we're in a
@@ -398,7 +892,13 @@ impl VM {
                     self.stack.push(v);
                     pc += 1;
                 }
+                Instr::Dup => {
+                    let v = self.stack.peek();
+                    self.stack.push(v);
+                    pc += 1;
+                }
                 Instr::GlobalLookup(i) => {
+                    check_operand!(i < self.globals.len());
                     let v = &self.globals[i];
                     if v.valkind() != ValKind::ILLEGAL {
                         // The global value is already set
@@ -441,16 +941,42 @@ impl VM {
                     self.stack.push(v);
                     pc += 1;
                 }
+                Instr::Jump(target) => {
+                    check_operand!(target < self.instrs.len());
+                    pc = target;
+                }
+                Instr::JumpIfFalse(target) => {
+                    check_operand!(target < self.instrs.len());
+                    let v = self.stack.pop();
+                    if stry!(v.as_bool(self)) {
+                        pc += 1;
+                    } else {
+                        pc = target;
+                    }
+                }
                 Instr::Pop => {
                     self.stack.pop();
                     pc += 1;
                 }
                 Instr::Return => {
+                    // A method or block must leave exactly one value (its result) on the stack
+                    // relative to where it started: anything else means that the compiler (or a
+                    // primitive) has miscounted.
+                    debug_assert!(
+                        self.stack.len() == stack_start + 1,
+                        "Unbalanced stack on return from {} at offset {}: expected 1 value, \
+                         found {}",
+                        method.name,
+                        pc - meth_start_pc,
+                        self.stack.len() as isize - stack_start as isize
+                    );
                     return SendReturn::Val;
                 }
                 Instr::Send(send_idx, cache_idx) => {
+                    check_operand!(
+                        send_idx < self.sends.len() && cache_idx < self.inline_caches.len()
+                    );
                     let (send_rcv, nargs, meth) = {
-                        debug_assert!(send_idx < self.sends.len());
                         let nargs = unsafe { self.sends.get_unchecked(send_idx) }.1;
                         let rcv = self.stack.pop_n(nargs);
                         let rcv_cls = rcv.get_class(self);
@@ -462,11 +988,25 @@ impl VM {
                             _ => {
                                 // The inline cache is empty or out of date, so store a new value in it.
                                 let cls: &Class = stry!(rcv_cls.downcast(self));
-                                let name =
-                                    Rc::clone(&unsafe { self.sends.get_unchecked(send_idx) }.0);
-                                let meth = stry!(cls.get_method(self, &*name));
-                                self.inline_caches[cache_idx] = Some((rcv_cls, Gc::clone(&meth)));
-                                meth
+                                let name = unsafe { self.sends.get_unchecked(send_idx) }.0;
+                                match cls.get_method_sym(self, name) {
+                                    Ok(meth) => {
+                                        self.inline_caches[cache_idx] =
+                                            Some((rcv_cls, Gc::clone(&meth)));
+                                        meth
+                                    }
+                                    Err(e) => {
+                                        let mut args = (0..nargs)
+                                            .map(|_| self.stack.pop())
+                                            .collect::<Vec<_>>();
+                                        args.reverse();
+                                        let name = self.symbol_str(name).to_owned();
+                                        let v = stry!(self.dnu(rcv, &name, &args, e));
+                                        self.stack.push(v);
+                                        pc += 1;
+                                        continue;
+                                    }
+                                }
                             }
                         };
                         (rcv, nargs, meth)
@@ -478,19 +1018,53 @@ impl VM {
                         continue;
                     }
 
+                    // If enabled, a send to a user method in tail position (i.e. immediately
+                    // followed by a `Return`) reuses this method's frame rather than pushing a new
+                    // one, so that tail recursion runs in constant native stack.
+                    if let MethodBody::User {
+                        num_params,
+                        num_vars,
+                        bytecode_off,
+                        max_stack,
+                        inline: None,
+                    } = meth.body
+                    {
+                        if self.tail_calls
+                            && num_params == nargs
+                            && matches!(self.instrs.get(pc + 1), Some(Instr::Return))
+                            && self.current_frame().reusable()
+                        {
+                            if self.profiling {
+                                self.profile_send(&meth);
+                            }
+                            let nframe =
+                                Frame::new(self, true, send_rcv.clone(), None, num_vars, nargs);
+                            *self.current_frame() = nframe;
+                            // The compiler discards any intermediate values before a `^`, so
+                            // nothing is left beneath the arguments `Frame::new` has just popped.
+                            debug_assert_eq!(self.stack.len(), stack_start);
+                            self.stack.reserve(max_stack);
+                            rcv = send_rcv;
+                            method = meth;
+                            meth_start_pc = bytecode_off;
+                            pc = bytecode_off;
+                            continue;
+                        }
+                    }
+
                     let len = self.stack.len() - nargs;
                     self.current_frame().set_sp(len);
                     send_args_on_stack!(send_rcv, meth, nargs);
                     pc += 1;
                 }
                 Instr::String(string_off) => {
-                    debug_assert!(self.strings.len() > string_off);
+                    check_operand!(string_off < self.strings.len());
                     let s = unsafe { self.strings.get_unchecked(string_off) }.clone();
                     self.stack.push(s);
                     pc += 1;
                 }
                 Instr::Symbol(symbol_off) => {
-                    debug_assert!(self.symbols.len() > symbol_off);
+                    check_operand!(symbol_off < self.symbols.len());
                     let s = unsafe { self.symbols.get_unchecked(symbol_off) }.clone();
                     self.stack.push(s);
                     pc += 1;
@@ -520,6 +1094,18 @@ impl VM {
             }};
         }
 
+        // Like `stry`, but for functions which send SOM messages and thus return a `SendReturn` on
+        // error, which must be propagated as-is.
+        macro_rules! sstry {
+            ($elem:expr) => {{
+                let e = $elem;
+                match e {
+                    Ok(o) => o,
+                    Err(r) => return r,
+                }
+            }};
+        }
+
         match prim {
             Primitive::Add => {
                 let v = self.stack.pop();
@@ -527,43 +1113,205 @@ impl VM {
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::AddElement => {
+                let v = self.stack.pop();
+                let (hash, found) = sstry!(self.set_find(&rcv, &v));
+                if found.is_none() {
+                    stry!(rcv.downcast::<Set>(self)).add(hash, v.clone());
+                }
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::And => {
                 let v = self.stack.pop();
                 let v = stry!(rcv.and(self, v));
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::AsInteger => todo!(),
+            Primitive::Arguments => {
+                let args = self
+                    .args
+                    .clone()
+                    .into_iter()
+                    .map(|a| String_::new(self, a, true))
+                    .collect();
+                let v = Array::from_vec(self, args);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AsArray => {
+                let v = stry!(rcv.downcast::<String_>(self)).to_array(self);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AsDouble => {
+                let v = stry!(rcv.to_doubleval(self));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AsInteger => {
+                let v = stry!(rcv.to_intval(self));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AsIntegerRadix => {
+                let radix = self.stack.pop();
+                let v = stry!(stry!(rcv.downcast::<String_>(self)).to_intval_radix(self, radix));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AsLowercase => {
+                let v = stry!(rcv.downcast::<String_>(self)).to_lowercase(self);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AsString if rcv.try_downcast::<Array>(self).is_some() => {
+                // An array's elements may be of any type, so we need to send each of them
+                // `asString`.
+                let elems = rcv.downcast::<Array>(self).unwrap().to_vec();
+                let mut s = String::new();
+                for e in &elems {
+                    s.push_str(&sstry!(self.som_as_string(e)));
+                }
+                let v = String_::new(self, s, true);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::AsString => {
                 let v = stry!(rcv.to_strval(self));
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::AsStringRadix => {
+                let radix = self.stack.pop();
+                let v = stry!(to_radix_string(self, rcv, radix));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::AsSymbol => {
                 let v = stry!(stry!(rcv.downcast::<String_>(self)).to_symbol(self));
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::AsUppercase => {
+                let v = stry!(rcv.downcast::<String_>(self)).to_uppercase(self);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::As32BitSignedValue => todo!(),
             Primitive::As32BitUnsignedValue => todo!(),
+            Primitive::At => {
+                let idx = self.stack.pop();
+                let v = if let Some(str_) = rcv.try_downcast::<String_>(self) {
+                    stry!(str_.at(self, idx))
+                } else if let Some(arr) = rcv.try_downcast::<Array>(self) {
+                    stry!(arr.at(self, idx))
+                } else {
+                    let (hash, k) = sstry!(self.dict_find(&rcv, &idx));
+                    let dict: &Dictionary = stry!(rcv.downcast(self));
+                    match k.and_then(|k| dict.get(hash, &k)) {
+                        Some(v) => v,
+                        None => {
+                            return SendReturn::Err(VMError::new(self, VMErrorKind::KeyNotFound))
+                        }
+                    }
+                };
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::AtPut => {
+                let v = self.stack.pop();
+                let k = self.stack.pop();
+                if let Some(arr) = rcv.try_downcast::<Array>(self) {
+                    stry!(arr.at_put(self, k, v.clone()));
+                    self.stack.push(v);
+                    return SendReturn::Val;
+                }
+                let (hash, found) = sstry!(self.dict_find(&rcv, &k));
+                let dict: &Dictionary = stry!(rcv.downcast(self));
+                // If an equal key is already present, it keeps its identity.
+                dict.set(hash, found.unwrap_or(k), v.clone());
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::AtRandom => todo!(),
+            Primitive::BitOr => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.or(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::BitShift => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.bit_shift(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::BitXor => {
                 let v = self.stack.pop();
                 let v = stry!(rcv.xor(self, v));
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::CaseSensitiveMatch => {
+                let pat_val = self.stack.pop();
+                let pat: &String_ = stry!(pat_val.downcast(self));
+                let rcv_str: &String_ = stry!(rcv.downcast(self));
+                let v = Val::from_bool(self, rcv_str.match_pattern(pat.as_str(), false));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Class => {
                 let v = rcv.get_class(self);
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Collect => {
+                let blk = self.stack.pop();
+                // We iterate over a snapshot of the array, which keeps its elements alive even if
+                // the block mutates the array.
+                let elems = stry!(rcv.downcast::<Array>(self)).to_vec();
+                let mut res = Vec::with_capacity(elems.len());
+                for e in elems {
+                    res.push(sstry!(self.send_internal(blk.clone(), "value:", vec![e])));
+                }
+                let v = Array::from_vec(self, res);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Compile => {
+                let source = self.stack.pop();
+                let source = stry!(source.downcast::<String_>(self)).as_str().to_owned();
+                stry!(self.compile_method(&rcv, &source));
+                self.stack.push(rcv);
+                SendReturn::Val
+            }
             Primitive::Concatenate => {
                 let rhs = self.stack.pop();
                 let v = stry!(stry!(rcv.downcast::<String_>(self)).concatenate(self, rhs));
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Contains => {
+                let v = self.stack.pop();
+                let (_, found) = sstry!(self.set_find(&rcv, &v));
+                let v = Val::from_bool(self, found.is_some());
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::ContainsKey => {
+                let k = self.stack.pop();
+                let (_, found) = sstry!(self.dict_find(&rcv, &k));
+                let v = Val::from_bool(self, found.is_some());
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::CopyWith => {
+                let e = self.stack.pop();
+                let v = stry!(rcv.downcast::<Array>(self)).copy_with(self, e);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Cos => todo!(),
             Primitive::Div => {
                 let v = self.stack.pop();
@@ -571,12 +1319,34 @@ impl VM {
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Do => {
+                let blk = self.stack.pop();
+                // We iterate over a snapshot of the collection, so the block can safely mutate
+                // it.
+                let elems = if let Some(arr) = rcv.try_downcast::<Array>(self) {
+                    arr.to_vec()
+                } else {
+                    stry!(rcv.downcast::<Set>(self)).elements()
+                };
+                for e in elems {
+                    sstry!(self.send_internal(blk.clone(), "value:", vec![e]));
+                }
+                self.stack.push(rcv);
+                SendReturn::Val
+            }
             Primitive::DoubleDiv => {
                 let v = self.stack.pop();
                 let v = stry!(rcv.double_div(self, v));
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Equals if rcv.try_downcast::<Array>(self).is_some() => {
+                let other = self.stack.pop();
+                let b = sstry!(self.som_array_equals(&rcv, &other, &mut vec![]));
+                let v = Val::from_bool(self, b);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Equals => {
                 let v = self.stack.pop();
                 let v = stry!(rcv.equals(self, v));
@@ -590,8 +1360,9 @@ impl VM {
                 // value to an isize doesn't mean that the user hasn't handed us an integer: we
                 // have to craft a special error message below to capture this.
                 if let Some(c) = c_val.as_isize(self) {
-                    if let Ok(c) = i32::try_from(c) {
-                        process::exit(c);
+                    if let Ok(code) = i32::try_from(c) {
+                        self.output.flush().ok();
+                        return SendReturn::Err(VMError::new(self, VMErrorKind::Exit { code }));
                     }
                 }
                 if c_val.get_class(self) == self.int_cls {
@@ -603,7 +1374,24 @@ impl VM {
                 }
             }
             Primitive::Fields => todo!(),
+            Primitive::Format => {
+                let args = self.stack.pop();
+                let elems = stry!(args.downcast::<Array>(self)).to_vec();
+                let mut strs = Vec::with_capacity(elems.len());
+                for e in &elems {
+                    strs.push(sstry!(self.som_print_string(e)));
+                }
+                let v = stry!(rcv.downcast::<String_>(self)).format(self, &strs);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::FromString => todo!(),
+            Primitive::GcObjectCount => {
+                let count = self.live_object_count();
+                let v = stry!(Val::from_usize(self, count));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Global => {
                 let name_val = self.stack.pop();
                 // XXX This should use Symbols not strings.
@@ -636,60 +1424,277 @@ impl VM {
                 SendReturn::Val
             }
             Primitive::Halt => unimplemented!(),
-            Primitive::Hashcode => unimplemented!(),
-            Primitive::Inspect => unimplemented!(),
-            Primitive::InstVarAt => unimplemented!(),
-            Primitive::InstVarAtPut => unimplemented!(),
-            Primitive::InstVarNamed => unimplemented!(),
-            Primitive::Length => todo!(),
-            Primitive::LessThan => {
+            Primitive::Gcd | Primitive::Lcm => {
                 let v = self.stack.pop();
-                let v = stry!(rcv.less_than(self, v));
+                let v = stry!(gcd_lcm(self, rcv, v, matches!(prim, Primitive::Lcm)));
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::LessThanEquals => {
-                let v = self.stack.pop();
-                let v = stry!(rcv.less_than_equals(self, v));
+            Primitive::Hashcode => {
+                let v = stry!(rcv.hashcode(self));
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::Load => {
-                let name_val = self.stack.pop();
-                // XXX This should use Symbols not strings.
-                let name: &String_ = stry!(name_val.downcast(self));
-                match self.find_class(name.as_str()) {
-                    Ok(ref p) => {
-                        let cls = self.compile(p, true);
-                        self.stack.push(cls);
-                    }
-                    Err(_) => {
-                        let v = self.nil.clone();
-                        self.stack.push(v);
-                    }
-                }
+            Primitive::IdentityHash => {
+                let v = stry!(rcv.identity_hash(self));
+                self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::Methods => todo!(),
-            Primitive::Mod => {
-                let v = self.stack.pop();
-                let v = stry!(rcv.modulus(self, v));
+            Primitive::IfFalse | Primitive::IfTrue => {
+                let blk = self.stack.pop();
+                let b = stry!(rcv.as_bool(self));
+                let v = if b == matches!(prim, Primitive::IfTrue) {
+                    sstry!(self.send_internal(blk, "value", vec![]))
+                } else {
+                    self.nil.clone()
+                };
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::Mul => {
-                let v = self.stack.pop();
-                let v = stry!(rcv.mul(self, v));
+            Primitive::IfTrueIfFalse => {
+                let false_blk = self.stack.pop();
+                let true_blk = self.stack.pop();
+                let blk = if stry!(rcv.as_bool(self)) {
+                    true_blk
+                } else {
+                    false_blk
+                };
+                let v = sstry!(self.send_internal(blk, "value", vec![]));
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::Name => {
-                let v = stry!(stry!(rcv.downcast::<Class>(self)).name(self));
+            Primitive::IndexOf => {
+                let v = self.stack.pop();
+                if let Some(str_) = rcv.try_downcast::<String_>(self) {
+                    let i = stry!(str_.index_of(self, v));
+                    let v = stry!(Val::from_usize(self, i));
+                    self.stack.push(v);
+                    return SendReturn::Val;
+                }
+                let elems = stry!(rcv.downcast::<Array>(self)).to_vec();
+                let i = match sstry!(self.som_find(&v, elems)) {
+                    Some(i) => i + 1,
+                    None => 0,
+                };
+                let v = stry!(Val::from_usize(self, i));
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::New => {
-                let v = Inst::new(self, rcv);
+            Primitive::InjectInto => {
+                let blk = self.stack.pop();
+                let mut acc = self.stack.pop();
+                let elems = stry!(rcv.downcast::<Array>(self)).to_vec();
+                for e in elems {
+                    acc = sstry!(self.send_internal(blk.clone(), "value:with:", vec![acc, e]));
+                }
+                self.stack.push(acc);
+                SendReturn::Val
+            }
+            Primitive::Inspect => {
+                let s = sstry!(self.som_inspect(rcv));
+                let v = String_::new(self, s, true);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::InstVarAt => {
+                let idx = self.stack.pop();
+                let n = stry!(self.inst_var_index(&rcv, idx));
+                let v = stry!(rcv.tobj(self)).inst_var_lookup(n);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::InstVarAtPut => {
+                let v = self.stack.pop();
+                let idx = self.stack.pop();
+                let n = stry!(self.inst_var_index(&rcv, idx));
+                stry!(rcv.tobj(self)).inst_var_set(n, v.clone());
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::InstVarNamed => {
+                let name = self.stack.pop();
+                let name = stry!(name.downcast::<String_>(self)).as_str().to_owned();
+                let cls_val = rcv.get_class(self);
+                let n = match stry!(cls_val.downcast::<Class>(self)).inst_var_index(&name) {
+                    Some(n) => n,
+                    None => {
+                        return SendReturn::Err(VMError::new(self, VMErrorKind::UnknownField(name)))
+                    }
+                };
+                let v = stry!(rcv.tobj(self)).inst_var_lookup(n);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::IsKindOf => {
+                let cls = self.stack.pop();
+                let mut rcv_cls = rcv.get_class(self);
+                let b = loop {
+                    if rcv_cls.bit_eq(&cls) {
+                        break true;
+                    }
+                    let supercls = stry!(rcv_cls.downcast::<Class>(self)).supercls(self);
+                    if supercls.is_nil(self) {
+                        break false;
+                    }
+                    rcv_cls = supercls;
+                };
+                let v = Val::from_bool(self, b);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::IsMemberOf => {
+                let cls = self.stack.pop();
+                let b = rcv.get_class(self).bit_eq(&cls);
+                let v = Val::from_bool(self, b);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::IsNil => {
+                let v = Val::from_bool(self, rcv.is_nil(self));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::JoinUsing => {
+                let sep = self.stack.pop();
+                let v = stry!(stry!(rcv.downcast::<Array>(self)).join_using(self, sep));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::KeysAndValuesDo => {
+                let blk = self.stack.pop();
+                // As with `do:`, we iterate over a snapshot so the block can safely mutate the
+                // dictionary.
+                let entries = stry!(rcv.downcast::<Dictionary>(self)).entries();
+                for (k, v) in entries {
+                    sstry!(self.send_internal(blk.clone(), "value:with:", vec![k, v]));
+                }
+                self.stack.push(rcv);
+                SendReturn::Val
+            }
+            Primitive::Length => {
+                let len = stry!(rcv.downcast::<Array>(self)).length();
+                let v = stry!(Val::from_usize(self, len));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::LessThan => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.less_than(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::LessThanEquals => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.less_than_equals(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Load => {
+                let name_val = self.stack.pop();
+                // XXX This should use Symbols not strings.
+                let name: &String_ = stry!(name_val.downcast(self));
+                if !self.allow_fs {
+                    return SendReturn::Err(VMError::new(
+                        self,
+                        VMErrorKind::FilesystemAccessDenied,
+                    ));
+                }
+                match self.find_class(name.as_str()) {
+                    Ok(ref p) => {
+                        let cls = stry!(self.load_class(p));
+                        self.stack.push(cls);
+                    }
+                    Err(_) => {
+                        let v = self.nil.clone();
+                        self.stack.push(v);
+                    }
+                }
+                SendReturn::Val
+            }
+            Primitive::LoadSource => {
+                let src_val = self.stack.pop();
+                let src = stry!(src_val.downcast::<String_>(self)).as_str().to_owned();
+                let v = stry!(self.load_source(&src));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Match => {
+                let pat_val = self.stack.pop();
+                let pat: &String_ = stry!(pat_val.downcast(self));
+                let rcv_str: &String_ = stry!(rcv.downcast(self));
+                let v = Val::from_bool(self, rcv_str.match_pattern(pat.as_str(), true));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Methods => {
+                let inherited = self.stack.pop();
+                let cls = stry!(rcv.downcast::<Class>(self));
+                let names = if stry!(inherited.as_bool(self)) {
+                    cls.all_method_names(self)
+                } else {
+                    cls.method_names(self)
+                };
+                let syms = names
+                    .into_iter()
+                    .map(|n| {
+                        let i = self.add_symbol(n);
+                        self.symbols[i].clone()
+                    })
+                    .collect();
+                let v = Array::from_vec(self, syms);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Mod => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.modulus(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Mul => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.mul(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Name => {
+                let v = stry!(stry!(rcv.downcast::<Class>(self)).name(self));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::New => {
+                let v = if rcv.bit_eq(&self.dict_cls) {
+                    Dictionary::new(self)
+                } else if rcv.bit_eq(&self.set_cls) {
+                    Set::new(self)
+                } else {
+                    Inst::new(self, rcv)
+                };
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::NewArray => {
+                let len_val = self.stack.pop();
+                let len = match len_val.as_usize(self) {
+                    Some(l) => l,
+                    None => {
+                        let got = len_val.dyn_objtype(self);
+                        return SendReturn::Err(VMError::new(
+                            self,
+                            VMErrorKind::TypeError {
+                                expected: Int::static_objtype(),
+                                got,
+                            },
+                        ));
+                    }
+                };
+                let v = Array::new(self, len);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::NewWeakRef => {
+                let target = self.stack.pop();
+                let v = WeakRef::new(self, target);
                 self.stack.push(v);
                 SendReturn::Val
             }
@@ -699,44 +1704,165 @@ impl VM {
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::NotNil => {
+                let v = Val::from_bool(self, !rcv.is_nil(self));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::ObjectSize => unimplemented!(),
-            Primitive::Perform => unimplemented!(),
-            Primitive::PerformInSuperClass => unimplemented!(),
-            Primitive::PerformWithArguments => unimplemented!(),
-            Primitive::PerformWithArgumentsInSuperClass => unimplemented!(),
+            Primitive::Perform
+            | Primitive::PerformInSuperClass
+            | Primitive::PerformWithArguments
+            | Primitive::PerformWithArgumentsInSuperClass => {
+                let cls_val = match prim {
+                    Primitive::PerformInSuperClass
+                    | Primitive::PerformWithArgumentsInSuperClass => Some(self.stack.pop()),
+                    _ => None,
+                };
+                let args = match prim {
+                    Primitive::PerformWithArguments
+                    | Primitive::PerformWithArgumentsInSuperClass => {
+                        let args_val = self.stack.pop();
+                        stry!(args_val.downcast::<Array>(self)).to_vec()
+                    }
+                    _ => Vec::new(),
+                };
+                let sel = self.stack.pop();
+                let v = sstry!(self.som_perform(rcv, sel, args, cls_val));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::PositiveInfinity => todo!(),
-            Primitive::PrimSubstringFromTo => todo!(),
+            Primitive::PrimSubstringFromTo => {
+                let end = self.stack.pop();
+                let start = self.stack.pop();
+                let v = stry!(stry!(rcv.downcast::<String_>(self)).substring(self, start, end));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::QuotedString => {
+                let v = stry!(rcv.downcast::<String_>(self)).quoted(self);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::RefEquals => {
                 let v = self.stack.pop();
                 let v = stry!(rcv.ref_equals(self, v));
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::RespondsTo => {
+                let sel_val = self.stack.pop();
+                let sel: &String_ = stry!(sel_val.downcast(self));
+                let cls_val = rcv.get_class(self);
+                let cls: &Class = stry!(cls_val.downcast(self));
+                // This must use the same lookup as message sends.
+                let b = cls.get_method(self, sel.as_str()).is_ok();
+                let v = Val::from_bool(self, b);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::ReplaceAllWith => {
+                let new = self.stack.pop();
+                let old = self.stack.pop();
+                let v = stry!(stry!(rcv.downcast::<String_>(self)).replace_all(self, old, new));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Restart => unreachable!(),
             Primitive::PrintNewline => {
-                println!();
+                stry!(self.write_output("\n"));
                 let v = self.system.clone();
                 self.stack.push(v);
                 SendReturn::Val
             }
             Primitive::PrintString => {
                 let v = self.stack.pop();
-                let str_: &String_ = stry!(v.downcast(self));
-                print!("{}", str_.as_str());
+                let s = stry!(v.downcast::<String_>(self)).as_str().to_owned();
+                stry!(self.write_output(&s));
                 let v = self.system.clone();
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Registered(i, nargs) => {
+                let mut args = Vec::with_capacity(nargs);
+                for _ in 0..nargs {
+                    args.push(self.stack.pop());
+                }
+                args.reverse();
+                let f = self.primitives[i];
+                let v = stry!(f(self, rcv, &args));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Rem => todo!(),
-            Primitive::Round => todo!(),
+            Primitive::Remove => {
+                let v = self.stack.pop();
+                let (hash, found) = sstry!(self.set_find(&rcv, &v));
+                let set: &Set = stry!(rcv.downcast(self));
+                let v = match found.and_then(|e| set.remove(hash, &e)) {
+                    Some(e) => e,
+                    None => self.nil.clone(),
+                };
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::RemoveKey => {
+                let k = self.stack.pop();
+                let (hash, found) = sstry!(self.dict_find(&rcv, &k));
+                let dict: &Dictionary = stry!(rcv.downcast(self));
+                let v = match found.and_then(|k| dict.remove(hash, &k)) {
+                    Some(v) => v,
+                    None => return SendReturn::Err(VMError::new(self, VMErrorKind::KeyNotFound)),
+                };
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Ceiling | Primitive::Floor | Primitive::Round | Primitive::Truncated => {
+                let mode = match prim {
+                    Primitive::Ceiling => RoundMode::Ceiling,
+                    Primitive::Floor => RoundMode::Floor,
+                    Primitive::Round => RoundMode::Nearest,
+                    _ => RoundMode::Truncate,
+                };
+                let v = stry!(stry!(rcv.downcast::<Double>(self)).round(self, mode));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::RoundTo => {
+                let v = self.stack.pop();
+                let v = stry!(stry!(rcv.downcast::<Double>(self)).round_to(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::ShallowCopy => {
+                let v = stry!(rcv.shallow_copy(self));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Shl => {
                 let v = self.stack.pop();
                 let v = stry!(rcv.shl(self, v));
                 self.stack.push(v);
                 SendReturn::Val
             }
-            Primitive::Shr => todo!(),
+            Primitive::Shr => {
+                let v = self.stack.pop();
+                let v = stry!(rcv.shr(self, v));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Sin => todo!(),
+            Primitive::Size => {
+                let len = if let Some(set) = rcv.try_downcast::<Set>(self) {
+                    set.len()
+                } else {
+                    stry!(rcv.downcast::<Dictionary>(self)).len()
+                };
+                let v = stry!(Val::from_usize(self, len));
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Sqrt => {
                 let v = stry!(rcv.sqrt(self));
                 self.stack.push(v);
@@ -748,21 +1874,54 @@ impl VM {
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Substrings => {
+                let v = stry!(rcv.downcast::<String_>(self)).substrings(self);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Superclass => {
                 let cls: &Class = stry!(rcv.downcast(self));
                 let v = cls.supercls(self);
                 self.stack.push(v);
                 SendReturn::Val
             }
+            Primitive::Ticks | Primitive::Time => {
+                let elapsed = self.start.elapsed();
+                let t = if matches!(prim, Primitive::Ticks) {
+                    elapsed.as_micros()
+                } else {
+                    elapsed.as_millis()
+                };
+                // `from_usize` boxes values which are too big to be tagged. On a 32 bit machine,
+                // `ticks` outgrows a `usize` after about 70 minutes.
+                let v = match usize::try_from(t) {
+                    Ok(t) => stry!(Val::from_usize(self, t)),
+                    Err(_) => stry!(ArbInt::new(self, BigInt::from(t))),
+                };
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Tokenize => {
+                let sep = self.stack.pop();
+                let v = stry!(stry!(rcv.downcast::<String_>(self)).tokenize(self, sep));
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Value(0) if rcv.try_downcast::<WeakRef>(self).is_some() => {
+                let v = rcv.downcast::<WeakRef>(self).unwrap().value(self);
+                self.stack.push(v);
+                SendReturn::Val
+            }
             Primitive::Value(nargs) => {
                 let rcv_blk: &Block = stry!(rcv.downcast(self));
                 let (num_vars, bytecode_off, max_stack) = {
                     let blkinfo = &self.blockinfos[rcv_blk.blockinfo_off];
                     (blkinfo.num_vars, blkinfo.bytecode_off, blkinfo.max_stack)
                 };
-                if self.stack.remaining_capacity() < max_stack {
-                    panic!("Not enough stack space to execute block.");
+                if let Some(e) = self.check_call_depth() {
+                    return SendReturn::Err(e);
                 }
+                self.stack.reserve(max_stack);
                 let frame = Frame::new(
                     self,
                     false,
@@ -780,6 +1939,313 @@ impl VM {
                 self.frame_pop();
                 r
             }
+            Primitive::WhileFalse | Primitive::WhileTrue => {
+                // We loop here, rather than recursing or restarting a SOM method, so that loops
+                // of any length run in constant native stack.
+                let body = self.stack.pop();
+                let until = match prim {
+                    Primitive::WhileFalse => self.true_.clone(),
+                    _ => self.false_.clone(),
+                };
+                loop {
+                    let c = sstry!(self.send_internal(rcv.clone(), "value", vec![]));
+                    if c.bit_eq(&until) {
+                        break;
+                    } else if !c.bit_eq(&self.true_) && !c.bit_eq(&self.false_) {
+                        let got = c.dyn_objtype(self);
+                        return SendReturn::Err(VMError::new(
+                            self,
+                            VMErrorKind::NotABoolean { got },
+                        ));
+                    }
+                    sstry!(self.send_internal(body.clone(), "value", vec![]));
+                }
+                let v = self.nil.clone();
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::With(n) => {
+                let mut elems = Vec::with_capacity(usize::from(n));
+                for _ in 0..n {
+                    elems.push(self.stack.pop());
+                }
+                elems.reverse();
+                let v = Array::from_vec(self, elems);
+                self.stack.push(v);
+                SendReturn::Val
+            }
+            Primitive::Yourself => {
+                self.stack.push(rcv);
+                SendReturn::Val
+            }
+        }
+    }
+
+    /// Send the message `msg` to `rcv` with arguments `args` on behalf of a primitive, returning
+    /// the result. If the send does not return normally, the `SendReturn` is returned as an error,
+    /// and must be passed on unchanged by the primitive.
+    fn send_internal(&mut self, rcv: Val, msg: &str, args: Vec<Val>) -> Result<Val, SendReturn> {
+        let cls_val = rcv.get_class(self);
+        self.send_internal_cls(rcv, cls_val, msg, args)
+    }
+
+    /// As [`VM::send_internal`], but look the method up in `cls_val` rather than `rcv`'s class.
+    fn send_internal_cls(
+        &mut self,
+        rcv: Val,
+        cls_val: Val,
+        msg: &str,
+        args: Vec<Val>,
+    ) -> Result<Val, SendReturn> {
+        let meth = match cls_val
+            .downcast::<Class>(self)
+            .and_then(|cls| cls.get_method(self, msg))
+        {
+            Ok(m) => m,
+            Err(e) => return self.dnu(rcv, msg, &args, e).map_err(SendReturn::Err),
+        };
+        let nargs = args.len();
+        self.stack.reserve(nargs);
+        for a in args {
+            self.stack.push(a);
+        }
+        match self.send_args_on_stack(rcv, meth, nargs) {
+            SendReturn::Val => Ok(self.stack.pop()),
+            r => Err(r),
+        }
+    }
+
+    /// Send the message named by the symbol `sel` to `rcv` with arguments `args` on behalf of the
+    /// `perform:` primitives, looking the method up in `cls_val` if it is `Some`. Primitives
+    /// assume that they have been sent exactly as many arguments as their selector has colons,
+    /// so the number of arguments is checked against `sel`'s arity first.
+    fn som_perform(
+        &mut self,
+        rcv: Val,
+        sel: Val,
+        args: Vec<Val>,
+        cls_val: Option<Val>,
+    ) -> Result<Val, SendReturn> {
+        let sel: &String_ = sel.downcast(self).map_err(SendReturn::Err)?;
+        let expected = selector_arity(sel.as_str());
+        if args.len() != expected {
+            return Err(SendReturn::Err(VMError::new(
+                self,
+                VMErrorKind::WrongNumberOfArgs {
+                    expected,
+                    got: args.len(),
+                },
+            )));
+        }
+        let cls_val = match cls_val {
+            Some(c) => c,
+            None => rcv.get_class(self),
+        };
+        self.send_internal_cls(rcv, cls_val, sel.as_str(), args)
+    }
+
+    /// Return the SOM `hashcode` of `v`.
+    fn som_hashcode(&mut self, v: &Val) -> Result<isize, SendReturn> {
+        let hash = self.send_internal(v.clone(), "hashcode", vec![])?;
+        match hash.as_isize(self) {
+            Some(h) => Ok(h),
+            None => {
+                let got = hash.dyn_objtype(self);
+                Err(SendReturn::Err(VMError::new(
+                    self,
+                    VMErrorKind::TypeError {
+                        expected: Int::static_objtype(),
+                        got,
+                    },
+                )))
+            }
+        }
+    }
+
+    /// Return the SOM `asString` of `v` as a Rust string.
+    fn som_as_string(&mut self, v: &Val) -> Result<String, SendReturn> {
+        let s = self.send_internal(v.clone(), "asString", vec![])?;
+        match s.downcast::<String_>(self) {
+            Ok(s) => Ok(s.as_str().to_owned()),
+            Err(e) => Err(SendReturn::Err(e)),
+        }
+    }
+
+    /// Return the SOM `printString` of `v` as a Rust string.
+    fn som_print_string(&mut self, v: &Val) -> Result<String, SendReturn> {
+        let s = self.send_internal(v.clone(), "printString", vec![])?;
+        match s.downcast::<String_>(self) {
+            Ok(s) => Ok(s.as_str().to_owned()),
+            Err(e) => Err(SendReturn::Err(e)),
+        }
+    }
+
+    /// Describe `v` for `Object>>inspect`: its class, identity hash, and the name and `asString`
+    /// of each of its instance variables. Objects other than instances of user classes are
+    /// described by their `asString` alone.
+    fn som_inspect(&mut self, v: Val) -> Result<String, SendReturn> {
+        if v.dyn_objtype(self) != ObjType::Inst {
+            return self.som_as_string(&v);
+        }
+        let cls_val = v.get_class(self);
+        let cls = match cls_val.downcast::<Class>(self) {
+            Ok(cls) => cls,
+            Err(e) => return Err(SendReturn::Err(e)),
+        };
+        let names = cls.inst_var_names.clone();
+        let cls_name = self.som_as_string(&cls_val)?;
+        let inst = v.tobj(self).unwrap();
+        let mut s = format!(
+            "instance of {} (identity {})",
+            cls_name,
+            gc::identity(v.obj_addr())
+        );
+        for (i, name) in names.iter().enumerate() {
+            let iv = inst.inst_var_lookup(i);
+            // Instance variables which have never been assigned to are `nil` as far as SOM code
+            // is concerned.
+            let iv_str = if iv.valkind() == ValKind::ILLEGAL {
+                "nil".to_owned()
+            } else {
+                self.som_as_string(&iv)?
+            };
+            s.push_str(&format!("\n  {}: {}", name, iv_str));
+        }
+        Ok(s)
+    }
+
+    /// Is the array `a` equal to `b`, comparing corresponding elements with SOM `=`? `visited`
+    /// holds the pairs of arrays whose comparison is in progress: meeting one of those pairs again
+    /// means that the arrays are cyclic, and we treat the pair as equal so that the comparison
+    /// terminates.
+    fn som_array_equals(
+        &mut self,
+        a: &Val,
+        b: &Val,
+        visited: &mut Vec<(usize, usize)>,
+    ) -> Result<bool, SendReturn> {
+        if a.bit_eq(b) {
+            return Ok(true);
+        }
+        let a_elems = a.downcast::<Array>(self).unwrap().to_vec();
+        let b_elems = match b.try_downcast::<Array>(self) {
+            Some(arr) => arr.to_vec(),
+            None => return Ok(false),
+        };
+        if a_elems.len() != b_elems.len() {
+            return Ok(false);
+        }
+        let pair = (a.obj_addr(), b.obj_addr());
+        if visited.contains(&pair) {
+            return Ok(true);
+        }
+        visited.push(pair);
+        for (x, y) in a_elems.iter().zip(b_elems.iter()) {
+            // Nested arrays are compared directly, rather than by sending `=`, so that they share
+            // `visited`.
+            let eq = if x.try_downcast::<Array>(self).is_some() {
+                self.som_array_equals(x, y, visited)?
+            } else {
+                let r = self.send_internal(x.clone(), "=", vec![y.clone()])?;
+                r.bit_eq(&self.true_)
+            };
+            if !eq {
+                visited.pop();
+                return Ok(false);
+            }
+        }
+        visited.pop();
+        Ok(true)
+    }
+
+    /// Return the index of the first element of `candidates` which is SOM `=` to `v`.
+    fn som_find(&mut self, v: &Val, candidates: Vec<Val>) -> Result<Option<usize>, SendReturn> {
+        for (i, c) in candidates.into_iter().enumerate() {
+            let r = self.send_internal(v.clone(), "=", vec![c])?;
+            if r.as_bool(self).map_err(SendReturn::Err)? {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Find the key `k` in the dictionary `dict`, returning a pair `(hash, found)` where `hash` is
+    /// `k`'s hashcode and `found` is `Some(stored key)` if a key equal to `k` is present.
+    fn dict_find(&mut self, dict: &Val, k: &Val) -> Result<(isize, Option<Val>), SendReturn> {
+        if let Err(e) = dict.downcast::<Dictionary>(self) {
+            return Err(SendReturn::Err(e));
+        }
+        let hash = self.som_hashcode(k)?;
+        let keys = dict.downcast::<Dictionary>(self).unwrap().keys(hash);
+        let found = self.som_find(k, keys.clone())?.map(|i| keys[i].clone());
+        // `hashcode` and `=` can run arbitrary code, which may have removed the key `=` matched.
+        let keys = dict.downcast::<Dictionary>(self).unwrap().keys(hash);
+        Ok((hash, found.filter(|f| keys.iter().any(|k| k.bit_eq(f)))))
+    }
+
+    /// Find `v` in the set `set`, returning a pair `(hash, found)` where `hash` is `v`'s hashcode
+    /// and `found` is `Some(stored element)` if an element equal to `v` is present.
+    fn set_find(&mut self, set: &Val, v: &Val) -> Result<(isize, Option<Val>), SendReturn> {
+        if let Err(e) = set.downcast::<Set>(self) {
+            return Err(SendReturn::Err(e));
+        }
+        let hash = self.som_hashcode(v)?;
+        let elems = set.downcast::<Set>(self).unwrap().bucket(hash);
+        let found = self.som_find(v, elems.clone())?.map(|i| elems[i].clone());
+        // `hashcode` and `=` can run arbitrary code, which may have removed the element `=`
+        // matched.
+        let elems = set.downcast::<Set>(self).unwrap().bucket(hash);
+        Ok((hash, found.filter(|f| elems.iter().any(|e| e.bit_eq(f)))))
+    }
+
+    /// Convert the SOM (one-based) instance variable index `idx` into a zero-based index into
+    /// `rcv`'s instance variables, checking that it is in bounds.
+    fn inst_var_index(&mut self, rcv: &Val, idx: Val) -> Result<usize, Box<VMError>> {
+        let i = match idx.as_isize(self) {
+            Some(i) => i,
+            None => {
+                let got = idx.dyn_objtype(self);
+                return Err(VMError::new(
+                    self,
+                    VMErrorKind::TypeError {
+                        expected: Int::static_objtype(),
+                        got,
+                    },
+                ));
+            }
+        };
+        let cls_val = rcv.get_class(self);
+        let len = cls_val.downcast::<Class>(self)?.num_inst_vars;
+        if i > 0 && (i as usize) <= len {
+            Ok(i as usize - 1)
+        } else {
+            Err(VMError::new(
+                self,
+                VMErrorKind::IndexOutOfBounds { idx: i, len },
+            ))
+        }
+    }
+
+    /// Write `s` to the VM's output, unless doing so would exceed `max_output_bytes`.
+    fn write_output(&mut self, s: &str) -> Result<(), Box<VMError>> {
+        if let Some(max) = self.max_output_bytes {
+            if self.output_bytes + s.len() > max {
+                return Err(VMError::new(self, VMErrorKind::OutputLimitExceeded(max)));
+            }
+        }
+        self.output_bytes += s.len();
+        write!(self.output, "{}", s).unwrap();
+        Ok(())
+    }
+
+    /// If pushing another frame would exceed the maximum call depth, return an appropriate
+    /// error.
+    fn check_call_depth(&self) -> Option<Box<VMError>> {
+        match self.max_call_depth {
+            Some(d) if self.frames.len() >= d => {
+                Some(VMError::new(self, VMErrorKind::CallDepthExceeded(d)))
+            }
+            _ => None,
         }
     }
 
@@ -804,6 +2270,216 @@ impl VM {
         len
     }
 
+    /// Check that the bytecode of `meth`, a method of `cls`, is well-formed (see
+    /// [`Class::verify`]).
+    pub(crate) fn verify_method(&self, cls: &Class, meth: &Method) -> Result<(), Box<VMError>> {
+        match meth.body {
+            MethodBody::User {
+                num_vars,
+                bytecode_off,
+                max_stack,
+                ..
+            } => self
+                .verify_body(
+                    cls,
+                    meth,
+                    bytecode_off,
+                    bytecode_off,
+                    max_stack,
+                    &mut vec![num_vars],
+                )
+                .map(|_| ()),
+            MethodBody::Primitive(_) => Ok(()),
+        }
+    }
+
+    /// Verify the method or block body starting at `start` within `meth`, whose bytecode starts
+    /// at `meth_start`. `num_vars` holds the number of variables in each enclosing closure, the
+    /// innermost last. If successful, returns the largest offset reachable within the body.
+    fn verify_body(
+        &self,
+        cls: &Class,
+        meth: &Method,
+        meth_start: usize,
+        start: usize,
+        max_stack: usize,
+        num_vars: &mut Vec<usize>,
+    ) -> Result<usize, Box<VMError>> {
+        let err = |pc: usize, reason: &str| {
+            VMError::new(
+                self,
+                VMErrorKind::VerificationFailed {
+                    method: meth.name.clone(),
+                    instr_offset: pc - meth_start,
+                    reason: reason.to_owned(),
+                },
+            )
+        };
+        let mut last = start;
+        // The stack depth on entry to each instruction reached so far: where control flow merges,
+        // every path must agree on the depth.
+        let mut depths = HashMap::new();
+        let mut todo = vec![(start, 0)];
+        while let Some((mut pc, mut depth)) = todo.pop() {
+            loop {
+                if pc >= self.instrs.len() {
+                    return Err(err(pc, "execution runs off the end of the bytecode"));
+                }
+                if let Some(&d) = depths.get(&pc) {
+                    if d != depth {
+                        return Err(err(
+                            pc,
+                            "inconsistent stack depth where control flow merges",
+                        ));
+                    }
+                    break;
+                }
+                depths.insert(pc, depth);
+                last = last.max(pc);
+                // How many values does this instruction pop and push?
+                let (pops, pushes) = match self.instrs[pc] {
+                    Instr::ArbInt(i) => {
+                        if i >= self.arbints.len() {
+                            return Err(err(pc, "integer constant out of range"));
+                        }
+                        (0, 1)
+                    }
+                    Instr::Array(n) => (n, 1),
+                    Instr::Block(i) => {
+                        if i >= self.blockinfos.len() {
+                            return Err(err(pc, "block out of range"));
+                        }
+                        let blkinfo = &self.blockinfos[i];
+                        if blkinfo.bytecode_off != pc + 1 {
+                            return Err(err(
+                                pc,
+                                "block's bytecode doesn't follow its Block instruction",
+                            ));
+                        }
+                        if blkinfo.bytecode_end <= blkinfo.bytecode_off
+                            || blkinfo.bytecode_end > self.instrs.len()
+                        {
+                            return Err(err(pc, "block's end is outside the bytecode"));
+                        }
+                        num_vars.push(blkinfo.num_vars);
+                        let last = self.verify_body(
+                            cls,
+                            meth,
+                            meth_start,
+                            blkinfo.bytecode_off,
+                            blkinfo.max_stack,
+                            num_vars,
+                        )?;
+                        num_vars.pop();
+                        if last >= blkinfo.bytecode_end {
+                            return Err(err(pc, "block's body runs past its end"));
+                        }
+                        if depth + 1 > max_stack {
+                            return Err(err(pc, "stack exceeds the maximum stack size"));
+                        }
+                        depth += 1;
+                        pc = blkinfo.bytecode_end;
+                        continue;
+                    }
+                    Instr::ClassVarLookup(n) | Instr::ClassVarSet(n) => {
+                        let metacls = cls.metacls(self);
+                        if n >= metacls.downcast::<Class>(self)?.num_inst_vars {
+                            return Err(err(pc, "class variable out of range"));
+                        }
+                        match self.instrs[pc] {
+                            Instr::ClassVarLookup(_) => (0, 1),
+                            _ => (1, 1),
+                        }
+                    }
+                    Instr::ClosureReturn(d) => {
+                        if d == 0 || d >= num_vars.len() {
+                            return Err(err(pc, "closure return from a nonexistent closure"));
+                        }
+                        (1, 0)
+                    }
+                    Instr::Double(_) | Instr::Int(_) => (0, 1),
+                    Instr::Dup => (1, 2),
+                    Instr::GlobalLookup(i) => {
+                        if i >= self.globals.len() {
+                            return Err(err(pc, "global out of range"));
+                        }
+                        (0, 1)
+                    }
+                    Instr::InstVarLookup(n) | Instr::InstVarSet(n) => {
+                        if n >= cls.num_inst_vars {
+                            return Err(err(pc, "instance variable out of range"));
+                        }
+                        match self.instrs[pc] {
+                            Instr::InstVarLookup(_) => (0, 1),
+                            _ => (1, 1),
+                        }
+                    }
+                    Instr::Jump(target) | Instr::JumpIfFalse(target) => {
+                        if target < start || target >= self.instrs.len() {
+                            return Err(err(pc, "jump target outside the body"));
+                        }
+                        match self.instrs[pc] {
+                            Instr::Jump(_) => (0, 0),
+                            _ => (1, 0),
+                        }
+                    }
+                    Instr::Pop => (1, 0),
+                    Instr::Return => (1, 0),
+                    Instr::Send(send_idx, cache_idx) => {
+                        if send_idx >= self.sends.len() || cache_idx >= self.inline_caches.len() {
+                            return Err(err(pc, "send out of range"));
+                        }
+                        (self.sends[send_idx].1 + 1, 1)
+                    }
+                    Instr::String(i) => {
+                        if i >= self.strings.len() {
+                            return Err(err(pc, "string constant out of range"));
+                        }
+                        (0, 1)
+                    }
+                    Instr::Symbol(i) => {
+                        if i >= self.symbols.len() {
+                            return Err(err(pc, "symbol constant out of range"));
+                        }
+                        (0, 1)
+                    }
+                    Instr::VarLookup(d, n) | Instr::VarSet(d, n) => {
+                        if d >= num_vars.len() || n >= num_vars[num_vars.len() - 1 - d] {
+                            return Err(err(pc, "variable out of range"));
+                        }
+                        match self.instrs[pc] {
+                            Instr::VarLookup(..) => (0, 1),
+                            _ => (1, 1),
+                        }
+                    }
+                };
+                if depth < pops {
+                    return Err(err(pc, "stack underflow"));
+                }
+                depth = depth - pops + pushes;
+                if depth > max_stack {
+                    return Err(err(pc, "stack exceeds the maximum stack size"));
+                }
+                match self.instrs[pc] {
+                    Instr::ClosureReturn(_) | Instr::Return => break,
+                    Instr::Jump(target) => pc = target,
+                    Instr::JumpIfFalse(target) => {
+                        todo.push((target, depth));
+                        pc += 1;
+                    }
+                    _ => pc += 1,
+                }
+            }
+        }
+        Ok(last)
+    }
+
+    /// Replace the instruction at offset `pc` with `instr`. The compiler uses this to fill in jump
+    /// targets once they are known.
+    pub fn set_instr(&mut self, pc: usize, instr: Instr) {
+        self.instrs[pc] = instr;
+    }
+
     /// Update the `BlockInfo` at index `idx` to `blkinfo`.
     pub fn set_blockinfo(&mut self, idx: usize, blkinfo: BlockInfo) {
         self.blockinfos[idx] = blkinfo;
@@ -842,6 +2518,50 @@ impl VM {
         self.instrs.len()
     }
 
+    /// Return the instruction at offset `pc`.
+    pub fn instr(&self, pc: usize) -> Instr {
+        self.instrs[pc]
+    }
+
+    /// Return the span associated with the instruction at offset `pc`.
+    pub(crate) fn instr_span(&self, pc: usize) -> Span {
+        self.instr_spans[pc]
+    }
+
+    /// Return the `BlockInfo` at index `idx`.
+    pub(crate) fn blockinfo(&self, idx: usize) -> &BlockInfo {
+        &self.blockinfos[idx]
+    }
+
+    /// Return the integer literal at index `idx`.
+    pub(crate) fn arbint(&self, idx: usize) -> &BigInt {
+        self.arbints[idx].downcast::<ArbInt>(self).unwrap().bigint()
+    }
+
+    /// Return the name of the global at index `idx`.
+    pub(crate) fn global_name(&self, idx: usize) -> &str {
+        // XXX O(n) lookup!
+        self.reverse_globals
+            .iter()
+            .find(|(_, j)| **j == idx)
+            .map(|(n, _)| n)
+            .unwrap()
+    }
+
+    /// Return the send at index `idx` as a (method name, number of arguments) pair.
+    pub(crate) fn send(&self, idx: usize) -> (&str, usize) {
+        let (sym, nargs) = self.sends[idx];
+        (self.symbol_str(sym), nargs)
+    }
+
+    /// Return the string literal at index `idx`.
+    pub(crate) fn string_str(&self, idx: usize) -> &str {
+        self.strings[idx]
+            .downcast::<String_>(self)
+            .unwrap()
+            .as_str()
+    }
+
     /// Push `instr` to the end of the current vector of instructions, associating `span` with it
     /// for the purposes of backtraces.
     pub fn instrs_push(&mut self, instr: Instr, span: Span) {
@@ -853,21 +2573,177 @@ impl VM {
     /// Add the send `send` to the VM, returning its index. Note that sends are reused, so indexes
     /// are also reused.
     pub fn add_send(&mut self, send: (String, usize)) -> usize {
-        // We want to avoid `clone`ing `send` in the (hopefully common) case of a cache hit, hence
-        // this slightly laborious dance and double-lookup.
-        let send = (Rc::new(send.0), send.1);
+        let send = (self.intern_symbol(&send.0), send.1);
         if let Some(i) = self.reverse_sends.get(&send) {
             *i
         } else {
             let len = self.sends.len();
-            self.reverse_sends.insert(send.clone(), len);
+            self.reverse_sends.insert(send, len);
             self.sends.push(send);
             len
         }
     }
 
+    /// Make `System`'s printing primitives write to `output`, returning the previous output sink.
+    /// By default, a VM writes to stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) -> Box<dyn Write> {
+        mem::replace(&mut self.output, output)
+    }
+
+    /// Log every instruction executed to `trace` (or, if `None`, turn tracing off), returning the
+    /// previous trace sink.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) -> Option<Box<dyn Write>> {
+        mem::replace(&mut self.trace, trace)
+    }
+
+    /// Call `f` before every instruction is executed (or, if `None`, stop doing so), returning the
+    /// previous hook. From within `f`, [`VM::current_frame_snapshot`] describes the instruction
+    /// about to be executed, which is enough to implement breakpoints. `f` is not called while it
+    /// is already running, and if `f` sets a new step hook, that replaces `f` when `f` returns.
+    pub fn set_step_hook(&mut self, f: Option<StepHook>) -> Option<StepHook> {
+        mem::replace(&mut self.step_hook, f)
+    }
+
+    /// Call the step hook (repeatedly, for as long as it returns `StepAction::Pause`), making the
+    /// instruction at offset `off` within `method` visible to [`VM::current_frame_snapshot`].
+    #[cold]
+    fn call_step_hook(
+        &mut self,
+        method: &Gc<Method>,
+        off: usize,
+        stack_start: usize,
+    ) -> Result<(), Box<VMError>> {
+        let mut f = self.step_hook.take().unwrap();
+        let action = loop {
+            self.hook_pos = Some((Gc::clone(method), off, stack_start));
+            let action = f(self);
+            self.hook_pos = None;
+            if action != StepAction::Pause || self.step_hook.is_some() {
+                break action;
+            }
+        };
+        if self.step_hook.is_none() {
+            self.step_hook = Some(f);
+        }
+        match action {
+            StepAction::Abort => Err(VMError::new(self, VMErrorKind::Aborted)),
+            StepAction::Continue | StepAction::Pause => Ok(()),
+        }
+    }
+
+    /// Call `f` when a message is sent to an object which has no method for it (or, if `None`,
+    /// raise an `UnknownMethod` error), returning the previous handler. `f`'s result is used as the
+    /// result of the send. While `f` is running, unknown methods raise errors as normal.
+    pub fn set_dnu_handler(&mut self, f: Option<DnuHandler>) -> Option<DnuHandler> {
+        mem::replace(&mut self.dnu_handler, f)
+    }
+
+    /// Method lookup for `msg` failed with `err`: call the handler set with
+    /// [`VM::set_dnu_handler`] if there is one and `err` is an `UnknownMethod` error, or return
+    /// `err` otherwise.
+    #[cold]
+    fn dnu(
+        &mut self,
+        rcv: Val,
+        msg: &str,
+        args: &[Val],
+        err: Box<VMError>,
+    ) -> Result<Val, Box<VMError>> {
+        if !matches!(err.kind, VMErrorKind::UnknownMethod(_)) {
+            return Err(err);
+        }
+        let mut f = match self.dnu_handler.take() {
+            Some(f) => f,
+            None => return Err(err),
+        };
+        let r = f(self, rcv, msg, args);
+        if self.dnu_handler.is_none() {
+            self.dnu_handler = Some(f);
+        }
+        r
+    }
+
+    /// Return a snapshot of the current frame, or `None` if called other than from a step hook
+    /// (see [`VM::set_step_hook`]).
+    pub fn current_frame_snapshot(&self) -> Option<FrameSnapshot> {
+        let (method, instr_offset, stack_start) = self.hook_pos.as_ref()?;
+        let locals = unsafe { &*self.frames.last().unwrap().closure.vars.0.get() }.clone();
+        let stack = (*stack_start..self.stack.len())
+            .map(|i| self.stack.peek_at(i))
+            .collect();
+        Some(FrameSnapshot {
+            method_name: method.name.clone(),
+            instr_offset: *instr_offset,
+            locals,
+            stack,
+        })
+    }
+
+    /// Write a line describing `instr` (at offset `off` within `method`) to the trace sink.
+    #[cold]
+    fn trace_instr(&mut self, method: &Method, off: usize, instr: Instr, stack_start: usize) {
+        let depth = self.stack.len() - stack_start;
+        let top = if depth > 0 {
+            self.stack.peek().dyn_objtype(self).as_str()
+        } else {
+            "-"
+        };
+        let line = format!(
+            "[{}] {}:{} {:?} (stack: {}, top: {})",
+            self.frames_len(),
+            method.name,
+            off,
+            instr,
+            depth,
+            top
+        );
+        writeln!(self.trace.as_mut().unwrap(), "{}", line).unwrap();
+    }
+
+    /// Set the program arguments returned by `System arguments`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// What happens when integer arithmetic overflows?
+    pub fn overflow_mode(&self) -> OverflowMode {
+        self.overflow_mode
+    }
+
+    /// Change what happens when integer arithmetic overflows.
+    pub fn set_overflow_mode(&mut self, overflow_mode: OverflowMode) {
+        self.overflow_mode = overflow_mode;
+    }
+
+    /// The maximum number of bytes the heap may occupy (see
+    /// [`VMConfig::max_heap_bytes`](crate::vm::VMConfig::max_heap_bytes)).
+    pub fn max_heap_bytes(&self) -> Option<usize> {
+        self.max_heap_bytes
+    }
+
+    /// Change the maximum number of bytes the heap may occupy.
+    pub fn set_max_heap_bytes(&mut self, max_heap_bytes: Option<usize>) {
+        self.max_heap_bytes = max_heap_bytes;
+        self.heap_exhausted = false;
+    }
+
+    /// Add the integer literal `i` to the VM, returning its index. As with strings, identical
+    /// integer literals share a single index (integers are immutable, so this is unobservable).
+    pub fn add_arbint(&mut self, i: BigInt) -> usize {
+        if let Some(j) = self.reverse_arbints.get(&i) {
+            *j
+        } else {
+            let len = self.arbints.len();
+            self.reverse_arbints.insert(i.clone(), len);
+            let v = ArbInt::new(self, i).unwrap();
+            self.arbints.push(v);
+            len
+        }
+    }
+
     /// Add the string `s` to the VM, returning its index. Note that strings are reused, so indexes
-    /// are also reused.
+    /// are also reused, and identical string literals (even in different classes) share a single
+    /// `String_`.
     pub fn add_string(&mut self, s: String) -> usize {
         // We want to avoid `clone`ing `s` in the (hopefully common) case of a cache hit, hence
         // this slightly laborious dance and double-lookup.
@@ -882,6 +2758,42 @@ impl VM {
         }
     }
 
+    /// Register `f` as the implementation of the primitive `name`: SOM methods called `name` which
+    /// are compiled after this call and whose body is `primitive` will call `f`. Registering a
+    /// name a second time replaces the previous implementation, including for methods which have
+    /// already been compiled. Built-in primitives always take precedence over registered ones.
+    pub fn register_primitive(&mut self, name: &str, f: PrimitiveFn) {
+        if let Some(i) = self.reverse_primitives.get(name) {
+            self.primitives[*i] = f;
+        } else {
+            self.reverse_primitives
+                .insert(name.to_owned(), self.primitives.len());
+            self.primitives.push(f);
+        }
+    }
+
+    /// If a primitive called `name` has been registered, return its index.
+    pub fn primitive_idx(&self, name: &str) -> Option<usize> {
+        self.reverse_primitives.get(name).cloned()
+    }
+
+    /// Register a new object type called `name`, returning a unique `ObjType` which objects of that
+    /// type should return from [`Obj::dyn_objtype`](crate::vm::objects::Obj::dyn_objtype). Every
+    /// call returns a different `ObjType`, even if `name` has been registered before.
+    pub fn register_objtype(&mut self, name: &str) -> ObjType {
+        let id = u32::try_from(self.custom_objtypes.len()).unwrap();
+        self.custom_objtypes.push(name.to_owned());
+        ObjType::Custom(id)
+    }
+
+    /// Return the name of `objtype`, including for custom object types.
+    pub fn objtype_name(&self, objtype: ObjType) -> &str {
+        match objtype {
+            ObjType::Custom(id) => &self.custom_objtypes[id as usize],
+            _ => objtype.as_str(),
+        }
+    }
+
     /// Add the symbol `s` to the VM, returning its index. Note that symbols are reused, so indexes
     /// are also reused.
     pub fn add_symbol(&mut self, s: String) -> usize {
@@ -898,6 +2810,32 @@ impl VM {
         }
     }
 
+    /// Return the `SymId` of the symbol `s`, adding `s` to the VM's symbols if necessary.
+    pub fn intern_symbol(&mut self, s: &str) -> SymId {
+        match self.symbol_id(s) {
+            Some(id) => id,
+            None => SymId(self.add_symbol(s.to_owned())),
+        }
+    }
+
+    /// Return the `SymId` of the symbol `s`, or `None` if `s` has never been interned.
+    pub fn symbol_id(&self, s: &str) -> Option<SymId> {
+        self.reverse_symbols.get(s).map(|i| SymId(*i))
+    }
+
+    /// Return the name of the symbol `id`.
+    pub fn symbol_str(&self, id: SymId) -> &str {
+        self.symbols[id.0]
+            .downcast::<String_>(self)
+            .unwrap()
+            .as_str()
+    }
+
+    /// Return the (unique) `Symbol` object for the symbol `id`.
+    pub fn symbol_val(&self, id: SymId) -> Val {
+        self.symbols[id.0].clone()
+    }
+
     /// Add the global `n` to the VM, returning its index. Note that global names (like strings)
     /// are reused, so indexes are also reused.
     pub fn add_global(&mut self, s: String) -> usize {
@@ -913,6 +2851,18 @@ impl VM {
         }
     }
 
+    /// Lookup the global `name`: if it has not been added, or has been added but not set, then
+    /// this will return `Err(...)`.
+    pub fn get_global(&self, name: &str) -> Result<Val, Box<VMError>> {
+        match self.reverse_globals.get(name) {
+            Some(i) => self.get_legal_global(*i),
+            None => Err(VMError::new(
+                self,
+                VMErrorKind::UnknownGlobal(name.to_owned()),
+            )),
+        }
+    }
+
     /// Lookup the global `name`: if it has not been added, or has been added but not set, then
     /// `self.nil` will be returned.
     pub fn get_global_or_nil(&self, name: &str) -> Val {
@@ -945,6 +2895,102 @@ impl VM {
         ))
     }
 
+    /// Called after an object has been allocated. If the heap has grown beyond `max_heap_bytes`,
+    /// the VM's caches are dropped (see [`VM::drop_caches`]) and, if that doesn't free enough
+    /// memory, the next instruction executed raises [`VMErrorKind::OutOfMemory`].
+    pub(crate) fn check_heap_limit(&mut self) {
+        if let Some(max) = self.max_heap_bytes {
+            if gc::live().1 > max {
+                self.drop_caches();
+                if gc::live().1 > max {
+                    self.heap_exhausted = true;
+                }
+            }
+        }
+    }
+
+    /// Drop the references the VM holds purely as an optimisation (currently, those in inline
+    /// caches), so that any objects only they keep alive are freed. This is not a garbage
+    /// collection: objects are freed as soon as their last reference is dropped, so there is
+    /// nothing else the VM can free, and objects in reference cycles are never freed (see
+    /// [`gc`](crate::vm::gc)).
+    pub fn drop_caches(&mut self) {
+        self.clear_inline_caches();
+    }
+
+    /// Empty every inline cache, so that the next execution of each send looks its method up
+    /// afresh.
+    pub(crate) fn clear_inline_caches(&mut self) {
+        for ic in &mut self.inline_caches {
+            *ic = None;
+        }
+    }
+
+    /// Return the classes currently held in globals as a tree, one class per line, with each class
+    /// indented two spaces further than its superclass and siblings sorted by name. Classes whose
+    /// superclass is `nil` (or is not itself in globals) are at the root. Metaclasses are not
+    /// shown, since each mirrors the position of its class.
+    pub fn class_hierarchy_string(&self) -> String {
+        // Each class's name, the class itself, and its superclass.
+        let mut classes = Vec::new();
+        for v in &self.globals {
+            if v.valkind() == ValKind::ILLEGAL {
+                continue;
+            }
+            if let Some(cls) = v.try_downcast::<Class>(self) {
+                if cls.metacls(self).bit_eq(&self.metacls_cls) {
+                    continue;
+                }
+                let name = cls.name.downcast::<String_>(self).unwrap().as_str();
+                classes.push((name.to_owned(), v.clone(), cls.supercls(self)));
+            }
+        }
+        classes.sort_by(|a, b| a.0.cmp(&b.0));
+
+        fn render(classes: &[(String, Val, Val)], i: usize, depth: usize, s: &mut String) {
+            s.push_str(&"  ".repeat(depth));
+            s.push_str(&classes[i].0);
+            s.push('\n');
+            for (j, (_, _, supercls)) in classes.iter().enumerate() {
+                if supercls.bit_eq(&classes[i].1) {
+                    render(classes, j, depth + 1, s);
+                }
+            }
+        }
+
+        let mut s = String::new();
+        for (i, (_, _, supercls)) in classes.iter().enumerate() {
+            if !classes.iter().any(|(_, cls, _)| cls.bit_eq(supercls)) {
+                render(&classes, i, 0, &mut s);
+            }
+        }
+        s
+    }
+
+    /// Return the number of objects, and the number of bytes they use, currently live on this
+    /// thread. Objects are freed as soon as their last reference is dropped, so these numbers fall
+    /// as soon as objects become unreachable: there is no collection to trigger or wait for (but
+    /// objects in reference cycles are never freed; see [`gc`](crate::vm::gc)).
+    pub fn gc_stats(&self) -> GCStats {
+        let (live_objects, live_bytes) = gc::live();
+        GCStats {
+            live_objects,
+            live_bytes,
+        }
+    }
+
+    /// Return the number of objects currently live.
+    pub fn live_object_count(&self) -> usize {
+        gc::live().0
+    }
+
+    /// Call `f` on each object currently live, in no particular order. As with
+    /// [`VM::live_object_count`], this includes objects held only by the embedder (or by another
+    /// VM on the same thread). Objects freed by `f` before it reaches them are skipped.
+    pub fn for_each_live_object(&self, f: impl FnMut(&ThinObj)) {
+        gc::for_each_live(f)
+    }
+
     /// Set the global `name` to the value `v`, overwriting the previous value (if any).
     pub fn set_global(&mut self, name: &str, v: Val) {
         debug_assert_eq!(self.globals.len(), self.reverse_globals.len());
@@ -960,12 +3006,52 @@ impl VM {
     }
 }
 
+/// Return the number of arguments taken by a message with selector `sel`: one per colon for keyword
+/// selectors, one for binary selectors, and none for unary selectors.
+pub(crate) fn selector_arity(sel: &str) -> usize {
+    if sel.ends_with(':') {
+        sel.matches(':').count()
+    } else if sel.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        0
+    } else {
+        1
+    }
+}
+
+/// Counters collected while profiling is turned on (see [`VM::set_profiling`]).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileReport {
+    /// How many bytecode instructions have been executed?
+    pub instrs: u64,
+    /// How many message sends (including those to primitives) have been performed?
+    pub sends: u64,
+    /// How many times has each primitive been invoked, keyed by selector?
+    pub primitives: HashMap<String, u64>,
+}
+
+/// A copy of the state of a frame, taken by [`VM::current_frame_snapshot`].
+#[derive(Debug)]
+pub struct FrameSnapshot {
+    /// The name of the method being executed. Blocks report the name of the method they are
+    /// defined in.
+    pub method_name: String,
+    /// The offset, within the method, of the instruction about to be executed.
+    pub instr_offset: usize,
+    /// The frame's variables. In a method, these are `self`, then the method's arguments, then its
+    /// temporaries; in a block, the block's arguments then its temporaries.
+    pub locals: Vec<Val>,
+    /// The frame's operand stack, from bottom to top.
+    pub stack: Vec<Val>,
+}
+
 #[derive(Debug)]
 pub struct Frame {
     /// Stack pointer. Note that this is updated lazily (i.e. it might not be accurate at all
     /// points, but it is guaranteed to be correct over function calls).
     sp: usize,
     closure: Gc<Closure>,
+    /// Has a block been created which refers to this frame's closure?
+    captured: bool,
 }
 
 impl Frame {
@@ -1000,6 +3086,7 @@ impl Frame {
         Frame {
             sp: 0,
             closure: Gc::new(Closure::new(parent_closure, vars)),
+            captured: false,
         }
     }
 
@@ -1031,6 +3118,13 @@ impl Frame {
     fn set_sp(&mut self, sp: usize) {
         self.sp = sp;
     }
+
+    /// Can this frame be replaced by that of a tail call? Only method frames can be, and only if
+    /// no block refers to them, since a non-local return from such a block must be able to find
+    /// this frame.
+    fn reusable(&self) -> bool {
+        self.closure.parent.is_none() && !self.captured
+    }
 }
 
 #[derive(Debug)]
@@ -1057,6 +3151,16 @@ impl Closure {
     fn set_var(&self, var: usize, val: Val) {
         unsafe { *(&mut *self.vars.0.get()).get_unchecked_mut(var) = val };
     }
+
+    /// Call `visitor` on each variable in this closure and its parents.
+    pub(crate) fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        for v in unsafe { &*self.vars.0.get() } {
+            visitor(v);
+        }
+        if let Some(p) = &self.parent {
+            p.trace(visitor);
+        }
+    }
 }
 
 impl GcLayout for Closure {
@@ -1076,25 +3180,33 @@ impl VM {
     pub fn new_no_bootstrap() -> Self {
         VM {
             classpath: vec![],
+            array_cls: Val::illegal(),
             block_cls: Val::illegal(),
             block2_cls: Val::illegal(),
             block3_cls: Val::illegal(),
             bool_cls: Val::illegal(),
+            char_cls: Val::illegal(),
             cls_cls: Val::illegal(),
+            dict_cls: Val::illegal(),
             double_cls: Val::illegal(),
             false_cls: Val::illegal(),
+            fraction_cls: Val::illegal(),
             int_cls: Val::illegal(),
             metacls_cls: Val::illegal(),
             obj_cls: Val::illegal(),
             nil_cls: Val::illegal(),
+            set_cls: Val::illegal(),
             str_cls: Val::illegal(),
             sym_cls: Val::illegal(),
             system_cls: Val::illegal(),
             true_cls: Val::illegal(),
+            weakref_cls: Val::illegal(),
             false_: Val::illegal(),
             nil: Val::illegal(),
             system: Val::illegal(),
             true_: Val::illegal(),
+            arbints: Vec::new(),
+            reverse_arbints: HashMap::new(),
             blockinfos: Vec::new(),
             globals: Vec::new(),
             reverse_globals: HashMap::new(),
@@ -1103,31 +3215,38 @@ impl VM {
             instr_spans: Vec::new(),
             sends: Vec::new(),
             reverse_sends: HashMap::new(),
-            stack: SOMStack::new(),
+            stack: SOMStack::new(SOM_STACK_LEN),
             strings: Vec::new(),
             reverse_strings: HashMap::new(),
             symbols: Vec::new(),
             reverse_symbols: HashMap::new(),
             frames: Vec::new(),
+            primitives: Vec::new(),
+            reverse_primitives: HashMap::new(),
+            custom_objtypes: Vec::new(),
+            output: Box::new(io::stdout()),
+            trace: None,
+            step_hook: None,
+            dnu_handler: None,
+            hook_pos: None,
+            instrs_executed: 0,
+            profiling: false,
+            profile: ProfileReport::default(),
+            start: Instant::now(),
+            args: vec![],
+            overflow_mode: OverflowMode::Promote,
+            max_call_depth: None,
+            max_heap_bytes: None,
+            heap_exhausted: false,
+            verify_bytecode: false,
+            max_instrs: None,
+            max_output_bytes: None,
+            output_bytes: 0,
+            allow_fs: true,
+            tail_calls: false,
         }
     }
 }
 
 #[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_frame() {
-        let mut vm = VM::new_no_bootstrap();
-        let selfv = Val::from_isize(&mut vm, 42).unwrap();
-        let v = Val::from_isize(&mut vm, 43).unwrap();
-        vm.stack.push(v);
-        let v = Val::from_isize(&mut vm, 44).unwrap();
-        vm.stack.push(v);
-        let f = Frame::new(&mut vm, true, selfv, None, 3, 2);
-        assert_eq!(f.var_lookup(0, 0).as_isize(&mut vm).unwrap(), 42);
-        assert_eq!(f.var_lookup(0, 1).as_isize(&mut vm).unwrap(), 43);
-        assert_eq!(f.var_lookup(0, 2).as_isize(&mut vm).unwrap(), 44);
-    }
-}
+mod tests;
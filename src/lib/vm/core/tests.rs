@@ -0,0 +1,1544 @@
+use super::*;
+use crate::{compiler::compile_file, vm::config::VMBuilder};
+use std::{
+    cell::RefCell,
+    env, fs, process,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// An output sink which can be inspected after it has been handed to a `VM`.
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write the class `src` to a fresh temporary file, compile it, and return the resulting
+/// class.
+fn compile_tmp(vm: &mut VM, src: &str) -> Val {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let mut p = env::temp_dir();
+    p.push(format!(
+        "yksom_{}_{}",
+        process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    p.set_extension(SOM_EXTENSION);
+    fs::write(&p, src).unwrap();
+    let cls = vm.compile(&p, true);
+    fs::remove_file(&p).ok();
+    cls
+}
+
+/// Return a human readable version of the instructions of the method `name` in `cls`, up to
+/// and including the first `Return`. Jump targets are given relative to the method's start.
+fn method_instrs(vm: &VM, cls: &Val, name: &str) -> Vec<String> {
+    let meth = cls
+        .downcast::<Class>(vm)
+        .unwrap()
+        .get_method(vm, name)
+        .unwrap();
+    let start = match meth.body {
+        MethodBody::User { bytecode_off, .. } => bytecode_off,
+        MethodBody::Primitive(_) => panic!(),
+    };
+    let mut pc = start;
+    let mut instrs = Vec::new();
+    loop {
+        let instr = vm.instrs[pc];
+        instrs.push(match instr {
+            Instr::Jump(target) => format!("Jump({})", target - start),
+            Instr::JumpIfFalse(target) => format!("JumpIfFalse({})", target - start),
+            Instr::Send(send_idx, _) => {
+                format!("Send({})", vm.symbol_str(vm.sends[send_idx].0))
+            }
+            _ => format!("{:?}", instr),
+        });
+        if let Instr::Return = instr {
+            return instrs;
+        }
+        pc += 1;
+    }
+}
+
+#[test]
+fn test_frame() {
+    let mut vm = VM::new_no_bootstrap();
+    let selfv = Val::from_isize(&mut vm, 42).unwrap();
+    let v = Val::from_isize(&mut vm, 43).unwrap();
+    vm.stack.push(v);
+    let v = Val::from_isize(&mut vm, 44).unwrap();
+    vm.stack.push(v);
+    let f = Frame::new(&mut vm, true, selfv, None, 3, 2);
+    assert_eq!(f.var_lookup(0, 0).as_isize(&mut vm).unwrap(), 42);
+    assert_eq!(f.var_lookup(0, 1).as_isize(&mut vm).unwrap(), 43);
+    assert_eq!(f.var_lookup(0, 2).as_isize(&mut vm).unwrap(), 44);
+}
+
+#[test]
+fn test_negative_literals() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "negative_literals = (
+            neg = ( ^-5 )
+            neg_ws = ( ^- 5 )
+            sub = ( |a| ^a - 5 )
+            sub_neg = ( ^3--4 )
+            sub_neg_double = ( ^3.0--4.5 )
+        )",
+    );
+    assert_eq!(method_instrs(&vm, &cls, "neg"), vec!["Int(-5)", "Return"]);
+    assert_eq!(
+        method_instrs(&vm, &cls, "neg_ws"),
+        vec!["Int(-5)", "Return"]
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "sub"),
+        vec!["VarLookup(0, 1)", "Int(5)", "Send(-)", "Return"]
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "sub_neg"),
+        vec!["Int(7)", "Return"]
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "sub_neg_double"),
+        vec!["Double(3.0)", "Double(-4.5)", "Send(-)", "Return"]
+    );
+}
+
+#[test]
+fn test_load_class() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let mut p = env::temp_dir();
+    p.push(format!("yksom_load_class_{}", process::id()));
+    p.set_extension(SOM_EXTENSION);
+
+    fs::write(&p, "Loaded = ( answer = ( ^42 ) )").unwrap();
+    let cls = vm.load_class(&p).unwrap();
+    assert!(vm.get_global_or_nil("Loaded").bit_eq(&cls));
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst, "answer", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 42);
+
+    // Reloading a class with the same name replaces the old definition.
+    fs::write(&p, "Loaded = ( answer = ( ^43 ) )").unwrap();
+    let cls2 = vm.load_class(&p).unwrap();
+    fs::remove_file(&p).ok();
+    assert!(!cls2.bit_eq(&cls));
+    assert!(vm.get_global_or_nil("Loaded").bit_eq(&cls2));
+    let inst = Inst::new(&mut vm, cls2);
+    let v = vm.top_level_send(inst, "answer", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 43);
+
+    assert_eq!(
+        vm.load_class(&p).unwrap_err().kind,
+        VMErrorKind::CantLoadClass(p.clone())
+    );
+
+    // A syntax error is reported as an error rather than exiting.
+    fs::write(&p, "Loaded = ( answer = ( ^ ) )").unwrap();
+    let e = vm.load_class(&p).unwrap_err();
+    fs::remove_file(&p).ok();
+    assert!(matches!(e.kind, VMErrorKind::CompileError(_)));
+    assert!(vm.get_global_or_nil("Loaded").bit_eq(&cls2));
+}
+
+#[test]
+fn test_compiled_class_round_trip() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let mut p = env::temp_dir();
+    p.push(format!("yksom_compile_file_{}", process::id()));
+    p.set_extension(SOM_EXTENSION);
+    fs::write(
+        &p,
+        "Precompiled = (
+            | str |
+            setUp = ( str := 'a string' )
+            sum = ( | total | total := 0. 1 to: 10 do: [:i | total := total + i]. ^total )
+            describe = (
+                ^str, ' ', #aSymbol asString, ' ', 100000000000000000000 printString, ' ',
+                    2.5 printString, ' ', (str length > 3 ifTrue: [ 'long' ] ifFalse: [ 'short' ])
+            )
+            ----
+            | made |
+            make = ( made := 7. ^self new setUp )
+            made = ( ^made )
+        )",
+    )
+    .unwrap();
+    let ccls = compile_file(&mut vm, &p).unwrap();
+    fs::remove_file(&p).ok();
+    assert!(vm.get_global_or_nil("Precompiled").is_nil(&vm));
+    let bytes = ccls.to_bytes();
+    assert_eq!(cobjects::Class::from_bytes(&bytes).unwrap(), ccls);
+    assert_eq!(ccls.to_bytes(), bytes);
+
+    // Load the class into a different VM to the one it was compiled in.
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = vm.load_compiled_class(&bytes).unwrap();
+    assert!(vm.get_global_or_nil("Precompiled").bit_eq(&cls));
+    let inst = vm.top_level_send(cls.clone(), "make", vec![]).unwrap();
+    let v = vm.top_level_send(cls, "made", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 7);
+    let v = vm.top_level_send(inst.clone(), "sum", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 55);
+    let v = vm.top_level_send(inst, "describe", vec![]).unwrap();
+    assert_eq!(
+        v.downcast::<String_>(&vm).unwrap().as_str(),
+        "a string aSymbol 100000000000000000000 2.5 long"
+    );
+
+    // Bytes which aren't a complete serialised class are rejected.
+    for b in &[&bytes[..bytes.len() - 1], &b"YKSOMC"[..], &b""[..]] {
+        assert_eq!(
+            vm.load_compiled_class(b).unwrap_err().kind,
+            VMErrorKind::InvalidCompiledClass
+        );
+    }
+    let mut ccls = ccls;
+    ccls.instrs.push(Instr::String(ccls.strings.len()));
+    ccls.instr_spans.push(Span::new(0, 0));
+    assert_eq!(
+        vm.load_compiled_class(&ccls.to_bytes()).unwrap_err().kind,
+        VMErrorKind::InvalidCompiledClass
+    );
+}
+
+#[test]
+fn test_get_global() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    assert!(vm.get_global("Integer").unwrap().bit_eq(&vm.int_cls));
+    assert_eq!(
+        vm.get_global("NoSuchGlobal").unwrap_err().kind,
+        VMErrorKind::UnknownGlobal("NoSuchGlobal".to_owned())
+    );
+
+    // Globals referenced from SOM code are resolved against the same set of globals.
+    let cls = compile_tmp(
+        &mut vm,
+        "get_global = (
+            int = ( ^Integer )
+            later = ( ^Later )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst.clone(), "int", vec![]).unwrap();
+    assert!(v.bit_eq(&vm.int_cls));
+    // `Later` has been referenced (so the compiler knows about it) but not yet set.
+    assert!(vm.get_global("Later").is_err());
+    let v = Val::from_isize(&mut vm, 42).unwrap();
+    vm.set_global("Later", v);
+    let v = vm.top_level_send(inst, "later", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 42);
+}
+
+#[test]
+fn test_constant_folding() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "constant_folding = (
+            add = ( ^3 + 4 )
+            nested = ( ^(2 * 3) - 10 )
+            overflow = ( ^9223372036854775807 + 1 )
+            other_op = ( ^7 / 2 )
+        )",
+    );
+    assert_eq!(method_instrs(&vm, &cls, "add"), vec!["Int(7)", "Return"]);
+    assert_eq!(
+        method_instrs(&vm, &cls, "nested"),
+        vec!["Int(-4)", "Return"]
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "overflow"),
+        vec!["Int(9223372036854775807)", "Int(1)", "Send(+)", "Return"]
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "other_op"),
+        vec!["Int(7)", "Int(2)", "Send(/)", "Return"]
+    );
+}
+
+#[test]
+fn test_dead_code() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let instrs_len = vm.instrs_len();
+    let blkinfo_idx = vm.blockinfos.len();
+    let cls = compile_tmp(
+        &mut vm,
+        "dead_code = (
+            m = ( ^1 )
+            n = ( ^[ ^2 ] )
+        )",
+    );
+    // Nothing should be emitted after a `^` (neither the method's implicit `^self` nor the
+    // block's implicit return of its last value).
+    assert_eq!(
+        vm.instrs[instrs_len..]
+            .iter()
+            .map(|i| format!("{:?}", i))
+            .collect::<Vec<_>>(),
+        vec![
+            "Int(1)".to_owned(),
+            "Return".to_owned(),
+            format!("Block({})", blkinfo_idx),
+            "Int(2)".to_owned(),
+            "ClosureReturn(1)".to_owned(),
+            "Return".to_owned()
+        ]
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst, "m", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 1);
+}
+
+#[test]
+fn test_discarded_expressions() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "discarded_expressions = (
+            run = ( 1 + 2. 3. ^4 )
+        )",
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "run"),
+        vec!["Int(3)", "Pop", "Int(3)", "Pop", "Int(4)", "Return"]
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst, "run", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 4);
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_cascade() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "cascade = (
+            | log |
+            add: x = ( log := log concatenate: x asString. ^x )
+            cascade = ( ^self add: 1; add: 2; yourself )
+            last = ( ^self add: 3; add: 4 )
+            run = ( log := ''. self cascade. self last. ^log )
+        )",
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "cascade"),
+        vec![
+            "VarLookup(0, 0)",
+            "Dup",
+            "Int(1)",
+            "Send(add:)",
+            "Pop",
+            "Dup",
+            "Int(2)",
+            "Send(add:)",
+            "Pop",
+            "Send(yourself)",
+            "Return"
+        ]
+    );
+    let meth = cls
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "cascade")
+        .unwrap();
+    match meth.body {
+        MethodBody::User { max_stack, .. } => assert_eq!(max_stack, 3),
+        MethodBody::Primitive(_) => panic!(),
+    }
+
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst.clone(), "cascade", vec![]);
+    assert!(v.unwrap().bit_eq(&inst));
+    let v = vm.top_level_send(inst.clone(), "last", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 4);
+    let v = vm.top_level_send(inst, "run", vec![]).unwrap();
+    assert_eq!(v.downcast::<String_>(&vm).unwrap().as_str(), "1234");
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_array_literal() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "array_literal = (
+            literal = ( ^#(1 2 3) )
+            squares = ( ^#(1 2 3) collect: [:x | x * x] )
+            sum = ( ^#(1 2 3) inject: 0 into: [:acc :x | acc + x] )
+        )",
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "literal"),
+        vec!["Int(1)", "Int(2)", "Int(3)", "Array(3)", "Return"]
+    );
+
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst.clone(), "squares", vec![]).unwrap();
+    let squares = v.downcast::<Array>(&vm).unwrap().to_vec();
+    let squares = squares
+        .iter()
+        .map(|x| x.as_isize(&mut vm).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(squares, vec![1, 4, 9]);
+    let v = vm.top_level_send(inst, "sum", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 6);
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_eval() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let v = vm.eval("3 + 4").unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 7);
+    let v = vm.eval("'a' , 'b'").unwrap();
+    assert_eq!(v.downcast::<String_>(&vm).unwrap().as_str(), "ab");
+    let v = vm.eval("| x | x := 2. x * x").unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 4);
+    vm.eval("system global: #Answer put: 42").unwrap();
+    let v = vm.eval("system global: #Answer").unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 42);
+    // A global with a lowercase name can be referred to directly once it has been set.
+    assert!(vm.eval("answer").is_err());
+    vm.eval("system global: #answer put: 43").unwrap();
+    let v = vm.eval("answer").unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 43);
+    assert!(vm.stack.is_empty());
+
+    match vm.eval("3 +").unwrap_err().kind {
+        VMErrorKind::CompileError(errs) => assert!(!errs.is_empty()),
+        _ => panic!(),
+    }
+    match vm.eval("1 / 0").unwrap_err().kind {
+        VMErrorKind::DivisionByZero => (),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_eval_verified() {
+    // `eval` verifies the code it compiles, so the class it wraps the code in must be verifiable.
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .verify_bytecode(true)
+        .build();
+    let v = vm
+        .eval("| x | x := 0. #(1 2 3) do: [:e | x := x + e]. x")
+        .unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 6);
+    let v = vm
+        .eval("#(1 2 3) do: [:e | e = 2 ifTrue: [ ^e ]]. 0")
+        .unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 2);
+}
+
+#[test]
+fn test_registered_primitive() {
+    fn double(vm: &mut VM, _: Val, args: &[Val]) -> Result<Val, Box<VMError>> {
+        let two = Val::from_isize(vm, 2)?;
+        args[0].mul(vm, two)
+    }
+
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    vm.register_primitive("double:", double);
+    let cls = compile_tmp(
+        &mut vm,
+        "registered_primitive = (
+            double: x = primitive
+            run = ( ^self double: 21 )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst, "run", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 42);
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_run() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(&mut vm, "run_exit = ( run = ( system exit: 3. ^1 / 0 ) )");
+    assert_eq!(vm.run(cls).unwrap(), 3);
+    let cls = compile_tmp(&mut vm, "run_return = ( run = ( ^1 ) )");
+    assert_eq!(vm.run(cls).unwrap(), 0);
+    let cls = compile_tmp(&mut vm, "run_error = ( run = ( ^1 / 0 ) )");
+    match vm.run(cls).unwrap_err().kind {
+        VMErrorKind::DivisionByZero => (),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_run_timed() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "run_timed = (
+            bench = (
+                | sum |
+                sum := 0.
+                1 to: 1000 do: [:i | sum := sum + i].
+                ^sum
+            )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let before = vm.instrs_executed();
+    let (r, d) = vm.run_timed(inst, "bench");
+    assert_eq!(r.unwrap().as_isize(&mut vm).unwrap(), 500500);
+    assert!(d > Duration::from_secs(0));
+    assert!(vm.instrs_executed() - before > 1000);
+}
+
+#[test]
+fn test_args() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "args = (
+            args = ( ^system arguments )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst.clone(), "args", vec![]).unwrap();
+    assert_eq!(v.downcast::<Array>(&vm).unwrap().length(), 0);
+
+    vm.set_args(vec!["a".to_owned(), "bc".to_owned(), "".to_owned()]);
+    let v = vm.top_level_send(inst, "args", vec![]).unwrap();
+    let args = v
+        .downcast::<Array>(&vm)
+        .unwrap()
+        .to_vec()
+        .iter()
+        .map(|a| a.downcast::<String_>(&vm).unwrap().as_str().to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(args, vec!["a", "bc", ""]);
+}
+
+#[test]
+fn test_locals() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "locals = (
+            m: a = ( | x y | x := a. y := x + 1. ^y )
+            n = ( | x y | ^y )
+        )",
+    );
+    // Variable 0 is `self`, followed by the arguments, and then the locals.
+    assert_eq!(
+        method_instrs(&vm, &cls, "m:"),
+        vec![
+            "VarLookup(0, 1)",
+            "VarSet(0, 2)",
+            "Pop",
+            "VarLookup(0, 2)",
+            "Int(1)",
+            "Send(+)",
+            "VarSet(0, 3)",
+            "Pop",
+            "VarLookup(0, 3)",
+            "Return"
+        ]
+    );
+    let meth = cls
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "m:")
+        .unwrap();
+    match meth.body {
+        MethodBody::User { num_vars, .. } => assert_eq!(num_vars, 4),
+        MethodBody::Primitive(_) => panic!(),
+    }
+
+    let inst = Inst::new(&mut vm, cls);
+    let a = Val::from_isize(&mut vm, 41).unwrap();
+    let v = vm.top_level_send(inst.clone(), "m:", vec![a]).unwrap();
+    assert_eq!(v.as_isize(&mut vm).unwrap(), 42);
+    // Locals are initialised to nil.
+    let v = vm.top_level_send(inst, "n", vec![]).unwrap();
+    assert!(v.is_nil(&vm));
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_wrong_number_of_args() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(&mut vm, "wrong_number_of_args = ( m: a = ( ^a ) )");
+    let inst = Inst::new(&mut vm, cls);
+    let args = vec![
+        Val::from_isize(&mut vm, 1).unwrap(),
+        Val::from_isize(&mut vm, 2).unwrap(),
+    ];
+    match vm.top_level_send(inst, "m:", args).unwrap_err().kind {
+        VMErrorKind::WrongNumberOfArgs {
+            expected: 1,
+            got: 2,
+        } => (),
+        _ => panic!(),
+    }
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn test_custom_objtype() {
+    use crate::vm::objects::Obj;
+    use abgc_derive::GcLayout;
+
+    #[derive(Debug, GcLayout)]
+    struct Custom {
+        objtype: ObjType,
+    }
+
+    impl Obj for Custom {
+        fn dyn_objtype(&self) -> ObjType {
+            self.objtype
+        }
+
+        fn get_class(&self, vm: &mut VM) -> Val {
+            vm.obj_cls.clone()
+        }
+    }
+
+    impl Drop for Custom {
+        fn drop(&mut self) {
+            gc::track_free(self);
+        }
+    }
+
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let t1 = vm.register_objtype("Custom1");
+    let t2 = vm.register_objtype("Custom2");
+    assert_ne!(t1, t2);
+    assert_eq!(vm.objtype_name(t1), "Custom1");
+    assert_eq!(vm.objtype_name(t2), "Custom2");
+    assert_eq!(vm.objtype_name(ObjType::Array), "Array");
+
+    let v = Val::from_obj(&mut vm, Custom { objtype: t2 });
+    assert_eq!(v.dyn_objtype(&mut vm), t2);
+    let e = VMError::new(
+        &vm,
+        VMErrorKind::TypeError {
+            expected: t1,
+            got: v.dyn_objtype(&mut vm),
+        },
+    );
+    assert_eq!(
+        e.kind.to_string(&vm),
+        "Expected object of type 'Custom1' but got type 'Custom2'"
+    );
+}
+
+#[test]
+fn test_frame_snapshot() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "frame_snapshot = (
+            m: a = ( | x | x := a + 1. ^x * 2 )
+        )",
+    );
+    assert!(vm.current_frame_snapshot().is_none());
+    let snapshots = Rc::new(RefCell::new(Vec::new()));
+    let snapshots_cl = Rc::clone(&snapshots);
+    vm.set_step_hook(Some(Box::new(move |vm: &mut VM| {
+        let s = vm.current_frame_snapshot().unwrap();
+        if s.method_name == "m:" {
+            snapshots_cl.borrow_mut().push(s);
+        }
+        StepAction::Continue
+    })));
+    let inst = Inst::new(&mut vm, cls);
+    let three = Val::from_isize(&mut vm, 3).unwrap();
+    let r = vm.top_level_send(inst, "m:", vec![three.clone()]).unwrap();
+    vm.set_step_hook(None);
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 8);
+
+    let snapshots = snapshots.borrow();
+    let first = &snapshots[0];
+    assert_eq!(first.instr_offset, 0);
+    assert!(first.stack.is_empty());
+    assert_eq!(first.locals[1], three);
+    assert!(first.locals[2].is_nil(&vm));
+    // The last instruction is the `Return`, when `x * 2` is on the stack.
+    let last = snapshots.last().unwrap();
+    assert_eq!(last.instr_offset, snapshots.len() - 1);
+    assert_eq!(last.stack.len(), 1);
+    assert_eq!(last.stack[0].as_isize(&mut vm).unwrap(), 8);
+    assert_eq!(last.locals[2].as_isize(&mut vm).unwrap(), 4);
+}
+
+#[test]
+fn test_dnu_handler() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "dnu_handler = (
+            m = ( ^(self frobnicate: 40) + (self perform: #frobnicate: withArguments: #(1)) )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let e = vm.top_level_send(inst.clone(), "m", vec![]).unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::UnknownMethod("frobnicate:".to_owned()));
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let seen_cl = Rc::clone(&seen);
+    vm.set_dnu_handler(Some(Box::new(
+        move |vm: &mut VM, _: Val, msg: &str, args: &[Val]| {
+            seen_cl.borrow_mut().push(msg.to_owned());
+            let i = args[0].as_isize(vm).unwrap();
+            Val::from_isize(vm, i + 1)
+        },
+    )));
+    let r = vm.top_level_send(inst.clone(), "m", vec![]).unwrap();
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 43);
+    let two = Val::from_isize(&mut vm, 2).unwrap();
+    let r = vm.top_level_send(inst, "frobnicate:", vec![two]).unwrap();
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 3);
+    assert_eq!(seen.borrow().len(), 3);
+    assert!(seen.borrow().iter().all(|s| s == "frobnicate:"));
+    assert!(vm.set_dnu_handler(None).is_some());
+}
+
+#[test]
+fn test_step_hook() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "step_hook = (
+            sum = ( | s | s := 0. 1 to: 10 do: [:i | s := s + i]. ^s )
+        )",
+    );
+    // Pause 3 times at the 20th instruction, checking that the VM hasn't moved on.
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let calls_cl = Rc::clone(&calls);
+    vm.set_step_hook(Some(Box::new(move |vm: &mut VM| {
+        let s = vm.current_frame_snapshot().unwrap();
+        let mut calls = calls_cl.borrow_mut();
+        calls.push((s.method_name, s.instr_offset, s.stack.len()));
+        if calls.len() >= 20 && calls.len() < 23 {
+            StepAction::Pause
+        } else {
+            StepAction::Continue
+        }
+    })));
+    let inst = Inst::new(&mut vm, cls);
+    let before = vm.instrs_executed();
+    let r = vm.top_level_send(inst.clone(), "sum", vec![]).unwrap();
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 55);
+    let calls = calls.borrow();
+    assert_eq!(calls[19], calls[20]);
+    assert_eq!(calls[19], calls[22]);
+    assert_eq!(vm.instrs_executed() - before, calls.len() as u64 - 3);
+
+    // Abort at the first instruction of a block.
+    vm.set_step_hook(Some(Box::new(|vm: &mut VM| {
+        if vm.current_frame_snapshot().unwrap().locals.len() == 1 {
+            StepAction::Abort
+        } else {
+            StepAction::Continue
+        }
+    })));
+    let e = vm.top_level_send(inst, "sum", vec![]).unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::Aborted);
+    vm.set_step_hook(None);
+}
+
+#[test]
+fn test_inline_accessors() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "inline_accessors = (
+            | x |
+            x = ( ^x )
+            one = ( ^1 )
+            plus_one = ( ^x + 1 )
+            at: i = ( ^0 )
+            run = ( | s | x := 2. s := 0. 1 to: 100 do: [:i | s := s + self x + self one]. ^s )
+        )",
+    );
+    let meth = cls
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "x")
+        .unwrap();
+    assert!(matches!(
+        meth.body,
+        MethodBody::User {
+            inline: Some(Instr::InstVarLookup(_)),
+            ..
+        }
+    ));
+    let meth = cls
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "plus_one")
+        .unwrap();
+    assert!(matches!(meth.body, MethodBody::User { inline: None, .. }));
+    // Inlining would leave the argument on the caller's stack.
+    let meth = cls
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "at:")
+        .unwrap();
+    assert!(matches!(meth.body, MethodBody::User { inline: None, .. }));
+
+    let inst = Inst::new(&mut vm, cls);
+    // A step hook disables inlining.
+    vm.set_step_hook(Some(Box::new(|_: &mut VM| StepAction::Continue)));
+    let before = vm.instrs_executed();
+    let r = vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 300);
+    let not_inlined = vm.instrs_executed() - before;
+    vm.set_step_hook(None);
+    let before = vm.instrs_executed();
+    let r = vm.top_level_send(inst, "run", vec![]).unwrap();
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 300);
+    let inlined = vm.instrs_executed() - before;
+    // Each of the 200 accessor sends saves its two instructions.
+    assert_eq!(not_inlined - inlined, 400);
+}
+
+#[test]
+fn test_inline_redefined() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let caller = compile_tmp(&mut vm, "inline_caller = ( get: o = ( ^o get ) )");
+    let caller = Inst::new(&mut vm, caller);
+    // Redefining a class creates a new class with new methods, and the inline cache at the
+    // send site in `get:` is keyed on the receiver's class, so sends to instances of the new
+    // class must not reuse the old class's inlined method.
+    let old = compile_tmp(&mut vm, "inline_redefined = ( get = ( ^1 ) )");
+    let old = Inst::new(&mut vm, old);
+    let new = compile_tmp(&mut vm, "inline_redefined = ( get = ( ^'two' ) )");
+    let new = Inst::new(&mut vm, new);
+    let r = vm
+        .top_level_send(caller.clone(), "get:", vec![old])
+        .unwrap();
+    assert_eq!(r.as_isize(&mut vm).unwrap(), 1);
+    let r = vm.top_level_send(caller, "get:", vec![new]).unwrap();
+    assert_eq!(r.downcast::<String_>(&vm).unwrap().as_str(), "two");
+}
+
+#[test]
+fn test_malformed_bytecode() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "malformed_bytecode = (
+            m = ( ^'abc' )
+            n = ( ^1 abs )
+        )",
+    );
+    for (name, instr) in &[
+        ("m", Instr::String(vm.strings.len())),
+        ("n", Instr::Send(vm.sends.len(), 0)),
+    ] {
+        let meth = cls
+            .downcast::<Class>(&vm)
+            .unwrap()
+            .get_method(&vm, name)
+            .unwrap();
+        let off = match meth.body {
+            MethodBody::User { bytecode_off, .. } => bytecode_off,
+            MethodBody::Primitive(_) => panic!(),
+        };
+        // Corrupt the last instruction before the method's `Return`.
+        let mut pc = off;
+        while !matches!(vm.instrs[pc + 1], Instr::Return) {
+            pc += 1;
+        }
+        vm.instrs[pc] = *instr;
+        let inst = Inst::new(&mut vm, cls.clone());
+        match vm.top_level_send(inst, name, vec![]).unwrap_err().kind {
+            VMErrorKind::MalformedBytecode { instr_offset } => {
+                assert_eq!(instr_offset, pc - off)
+            }
+            _ => panic!(),
+        }
+    }
+}
+
+#[test]
+fn test_shared_symbols() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls1 = compile_tmp(&mut vm, "shared_symbols1 = ( m = ( ^#printString ) )");
+    let cls2 = compile_tmp(&mut vm, "shared_symbols2 = ( m = ( ^1 printString ) )");
+    let id = vm.symbol_id("printString").unwrap();
+    assert_eq!(vm.intern_symbol("printString"), id);
+    assert_eq!(vm.symbol_str(id), "printString");
+
+    let off1 = match cls1
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "m")
+        .unwrap()
+        .body
+    {
+        MethodBody::User { bytecode_off, .. } => bytecode_off,
+        MethodBody::Primitive(_) => panic!(),
+    };
+    match vm.instrs[off1] {
+        Instr::Symbol(i) => assert_eq!(SymId(i), id),
+        _ => panic!(),
+    }
+    let off2 = match cls2
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .get_method(&vm, "m")
+        .unwrap()
+        .body
+    {
+        MethodBody::User { bytecode_off, .. } => bytecode_off,
+        MethodBody::Primitive(_) => panic!(),
+    };
+    match vm.instrs[off2 + 1] {
+        Instr::Send(send_idx, _) => assert_eq!(vm.sends[send_idx].0, id),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn test_method_names() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "method_names = (
+            c = ( ^3 )
+            a = ( ^1 )
+            b = ( ^2 )
+        )",
+    );
+    let cls: &Class = cls.downcast(&vm).unwrap();
+    assert_eq!(cls.method_names(&vm), vec!["a", "b", "c"]);
+    let all = cls.all_method_names(&vm);
+    for n in &["a", "b", "c", "println", "=="] {
+        assert!(all.iter().any(|x| x == n));
+    }
+}
+
+#[test]
+fn test_interned_strings() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls1 = compile_tmp(&mut vm, "interned1 = ( s = ( ^'abc' ) )");
+    let cls2 = compile_tmp(&mut vm, "interned2 = ( s = ( ^'abc' ) t = ( ^'abd' ) )");
+    let inst1 = Inst::new(&mut vm, cls1);
+    let inst2 = Inst::new(&mut vm, cls2);
+    let s1 = vm.top_level_send(inst1, "s", vec![]).unwrap();
+    let s2 = vm.top_level_send(inst2.clone(), "s", vec![]).unwrap();
+    let t = vm.top_level_send(inst2, "t", vec![]).unwrap();
+    assert_eq!(s1, s2);
+    assert_ne!(s1, t);
+}
+
+#[test]
+fn test_trace() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(&mut vm, "trace = ( m = ( ^1 ) )");
+    let inst = Inst::new(&mut vm, cls);
+    let buf = SharedBuf::default();
+    vm.set_trace(Some(Box::new(buf.clone())));
+    vm.top_level_send(inst.clone(), "m", vec![]).unwrap();
+    assert_eq!(
+        String::from_utf8(buf.0.borrow().clone()).unwrap(),
+        "[1] m:0 Int(1) (stack: 0, top: -)\n[1] m:1 Return (stack: 1, top: Int)\n"
+    );
+
+    // Once tracing is turned off, nothing else should be logged.
+    vm.set_trace(None);
+    buf.0.borrow_mut().clear();
+    vm.top_level_send(inst, "m", vec![]).unwrap();
+    assert!(buf.0.borrow().is_empty());
+}
+
+#[test]
+fn test_builder() {
+    let buf = SharedBuf::default();
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .output(Box::new(buf.clone()))
+        .overflow_mode(OverflowMode::Error)
+        .max_call_depth(100)
+        .build();
+    assert_eq!(vm.overflow_mode(), OverflowMode::Error);
+    let cls = compile_tmp(
+        &mut vm,
+        "builder = (
+            run = ( 'hello' println )
+            recurse: n = ( ^self recurse: n + 1 )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+    assert_eq!(&*buf.0.borrow(), b"hello\n");
+
+    let v = Val::from_isize(&mut vm, 0).unwrap();
+    let e = vm.top_level_send(inst, "recurse:", vec![v]).unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::CallDepthExceeded(100));
+    // The VM must still be usable after the error has unwound the stack.
+    assert_eq!(vm.frames_len(), 0);
+}
+
+#[test]
+fn test_max_heap_bytes() {
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .max_heap_bytes(usize::MAX)
+        .build();
+    assert_eq!(vm.max_heap_bytes(), Some(usize::MAX));
+    let cls = compile_tmp(
+        &mut vm,
+        "max_heap_bytes = (
+            grow = ( | l a | [ true ] whileTrue: [ a := Array new: 1. a at: 1 put: l. l := a ] )
+            add: x = ( ^x + 1 )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    // The limit covers the whole heap, so it must leave room for what bootstrapping allocated.
+    vm.drop_caches();
+    let limit = vm.gc_stats().live_bytes + 64 * 1024;
+    vm.set_max_heap_bytes(Some(limit));
+    let e = vm.top_level_send(inst.clone(), "grow", vec![]).unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::OutOfMemory);
+    assert_eq!(vm.frames_len(), 0);
+    // Unwinding frees the arrays `grow` allocated, so the VM is usable again.
+    drop(e);
+    vm.drop_caches();
+    assert!(vm.gc_stats().live_bytes <= limit);
+    let v = Val::from_isize(&mut vm, 41).unwrap();
+    let r = vm.top_level_send(inst, "add:", vec![v]).unwrap();
+    assert_eq!(r.as_isize(&mut vm), Some(42));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Unbalanced stack on return from bad at offset 2")]
+fn test_unbalanced_stack() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    // A method which leaves two values on the stack rather than one.
+    let bytecode_off = vm.instrs_len();
+    vm.instrs_push(Instr::Int(1), Span::new(0, 0));
+    vm.instrs_push(Instr::Int(2), Span::new(0, 0));
+    vm.instrs_push(Instr::Return, Span::new(0, 0));
+    let meth = Gc::new(Method::new(
+        &vm,
+        "bad".to_owned(),
+        MethodBody::User {
+            num_params: 0,
+            num_vars: 1,
+            bytecode_off,
+            max_stack: 2,
+            inline: None,
+        },
+    ));
+    let rcv = vm.nil.clone();
+    vm.stack.reserve(2);
+    let frame = Frame::new(&mut vm, true, rcv.clone(), None, 1, 0);
+    vm.frames.push(frame);
+    vm.exec_user(rcv, meth, bytecode_off);
+}
+
+#[test]
+fn test_verify() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls_val = compile_tmp(
+        &mut vm,
+        "verify = (
+            | x |
+            empty = ( )
+            m: a = ( | y | y := #(1 2) collect: [:e | e + a + x]. ^y size; yourself )
+            n = ( #(1 2) do: [:e | ^e] )
+            ----
+            | count |
+            incr = ( count := count + 1 )
+        )",
+    );
+    let cls = cls_val.downcast::<Class>(&vm).unwrap();
+    assert!(cls.verify(&vm).is_ok());
+    let metacls = cls.metacls(&vm);
+    assert!(metacls.downcast::<Class>(&vm).unwrap().verify(&vm).is_ok());
+    // The builtin classes should also verify.
+    let obj_cls = vm.obj_cls.clone();
+    assert!(obj_cls.downcast::<Class>(&vm).unwrap().verify(&vm).is_ok());
+    let int_cls = vm.int_cls.clone();
+    assert!(int_cls.downcast::<Class>(&vm).unwrap().verify(&vm).is_ok());
+
+    // Make the last block compiled (the one in `n`) end beyond the VM's instructions.
+    let idx = vm.blockinfos.len() - 1;
+    let blkinfo = &vm.blockinfos[idx];
+    let blkinfo = BlockInfo {
+        bytecode_end: vm.instrs.len() + 10,
+        ..*blkinfo
+    };
+    vm.set_blockinfo(idx, blkinfo);
+    let cls = cls_val.downcast::<Class>(&vm).unwrap();
+    match cls.verify(&vm).unwrap_err().kind {
+        VMErrorKind::VerificationFailed { method, reason, .. } => {
+            assert_eq!(method, "n");
+            assert_eq!(reason, "block's end is outside the bytecode");
+        }
+        e => panic!("{:?}", e),
+    }
+}
+
+#[test]
+fn test_with_config() {
+    let vm = VM::with_config(VMConfig {
+        classpath: vec!["lib/SOM".to_owned()],
+        stack_len: 100,
+        overflow_mode: OverflowMode::Promote,
+        max_call_depth: None,
+        max_heap_bytes: None,
+        verify_bytecode: false,
+        max_instrs: None,
+        max_output_bytes: None,
+        allow_fs: true,
+        tail_calls: false,
+    });
+    assert_eq!(vm.classpath, vec!["lib/SOM".to_owned()]);
+    assert_eq!(vm.stack.remaining_capacity(), 100);
+    assert!(vm.get_global_or_nil("Integer").bit_eq(&vm.int_cls));
+}
+
+#[test]
+fn test_config_limits() {
+    let buf = SharedBuf::default();
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .output(Box::new(buf.clone()))
+        .max_output_bytes(6)
+        .allow_fs(false)
+        .build();
+    vm.eval("'hello' println").unwrap();
+    let e = vm.eval("'!' println").unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::OutputLimitExceeded(6));
+    // A print which would exceed the limit writes nothing at all.
+    assert_eq!(&*buf.0.borrow(), b"hello\n");
+    let e = vm.eval("system load: #NoSuchClass").unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::FilesystemAccessDenied);
+
+    // A freshly built VM has executed the same number of instructions as any other, so we can
+    // use one VM to work out a limit which will stop another part way through the same code.
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()]).build();
+    let before = vm.instrs_executed();
+    vm.eval("1 to: 1000 do: [:i | i ]").unwrap();
+    let max = before + (vm.instrs_executed() - before) / 2;
+    assert!(vm.eval("system load: #NoSuchClass").unwrap().is_nil(&vm));
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .max_instrs(max)
+        .build();
+    let e = vm.eval("1 to: 1000 do: [:i | i ]").unwrap_err();
+    assert_eq!(e.kind, VMErrorKind::InstructionLimitExceeded(max));
+    assert_eq!(vm.frames_len(), 0);
+}
+
+#[test]
+fn test_zero_stack_len() {
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .stack_len(0)
+        .build();
+    assert_eq!(vm.eval("3 + 4").unwrap().as_isize(&mut vm), Some(7));
+}
+
+#[test]
+fn test_stack_reuse() {
+    let mut vm = VM::with_config(VMConfig {
+        classpath: vec!["lib/SOM".to_owned()],
+        stack_len: 1,
+        ..Default::default()
+    });
+    let cls = compile_tmp(
+        &mut vm,
+        "stack_reuse = (
+            id: x = ( ^x )
+            run = ( 1 to: 1000 do: [:i | self id: i ] )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    // The first send forces the stack to grow...
+    vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+    let capacity = vm.stack.capacity();
+    assert!(capacity > 1);
+    assert!(vm.stack.is_empty());
+    // ...but, since the stack is reused by each frame, subsequent sends should not need to
+    // grow it further.
+    for _ in 0..10 {
+        vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+        assert_eq!(vm.stack.capacity(), capacity);
+    }
+}
+
+#[test]
+fn test_gc_stats() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let before = vm.gc_stats();
+    let mut strs = Vec::new();
+    for i in 0..1000 {
+        strs.push(String_::new(&mut vm, i.to_string(), true));
+    }
+    let during = vm.gc_stats();
+    assert_eq!(during.live_objects, before.live_objects + 1000);
+    assert!(during.live_bytes > before.live_bytes);
+    drop(strs);
+    assert_eq!(vm.gc_stats(), before);
+}
+
+#[test]
+fn test_drop_caches() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let caller = vm
+        .load_source("CacheCaller = ( send: x = ( ^x isNil ) )")
+        .unwrap();
+    // A class without methods isn't kept alive by a cycle through its methods.
+    let held = vm.load_source("CacheHeld = ( )").unwrap();
+    let inst = Inst::new(&mut vm, held);
+    let caller = Inst::new(&mut vm, caller);
+    vm.top_level_send(caller, "send:", vec![inst]).unwrap();
+    // Now only the inline cache of the `isNil` send refers to `CacheHeld`.
+    let nil = vm.nil.clone();
+    vm.set_global("CacheHeld", nil);
+    let before = vm.live_object_count();
+    vm.drop_caches();
+    assert!(vm.live_object_count() < before);
+}
+
+#[test]
+fn test_gc_object_count() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "gc_object_count = (
+            count = ( ^system gcObjectCount )
+            scoped = ( | o | o := Object new. ^system gcObjectCount )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    // Run `scoped` once first so that anything it caches is already allocated.
+    vm.top_level_send(inst.clone(), "scoped", vec![]).unwrap();
+    vm.drop_caches();
+    let before = vm.top_level_send(inst.clone(), "count", vec![]).unwrap();
+    let before = before.as_usize(&mut vm).unwrap();
+    assert_eq!(before, vm.live_object_count());
+    let during = vm.top_level_send(inst.clone(), "scoped", vec![]).unwrap();
+    assert_eq!(during.as_usize(&mut vm), Some(before + 1));
+    vm.drop_caches();
+    let after = vm.top_level_send(inst, "count", vec![]).unwrap();
+    assert_eq!(after.as_usize(&mut vm), Some(before));
+}
+
+#[test]
+fn test_for_each_live_object() {
+    use crate::vm::objects::Obj;
+
+    fn histogram(vm: &VM) -> HashMap<ObjType, usize> {
+        let mut h = HashMap::new();
+        vm.for_each_live_object(|o| *h.entry(o.dyn_objtype()).or_insert(0) += 1);
+        h
+    }
+
+    let mut vm = VM::new_no_bootstrap();
+    let before = histogram(&vm);
+    assert_eq!(before.values().sum::<usize>(), vm.live_object_count());
+    let s = String_::new(&mut vm, "s".to_owned(), true);
+    let arr = Array::from_vec(&mut vm, vec![s.clone(), s]);
+    let d = Double::new(&mut vm, 1.5);
+    let during = histogram(&vm);
+    assert_eq!(during.values().sum::<usize>(), vm.live_object_count());
+    for &(t, n) in &[
+        (ObjType::Array, 1),
+        (ObjType::Double, 1),
+        (ObjType::String_, 1),
+    ] {
+        assert_eq!(during[&t], before.get(&t).unwrap_or(&0) + n);
+    }
+    drop(arr);
+    drop(d);
+    vm.drop_caches();
+    assert_eq!(histogram(&vm), before);
+}
+
+#[test]
+fn test_profile_report() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "profile = (
+            run = ( ^(self double: 3) + (self double: 4) )
+            double: x = ( ^x * 2 )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls.clone());
+    // Nothing is counted until profiling is turned on.
+    vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+    assert_eq!(vm.profile_report(), ProfileReport::default());
+
+    vm.set_profiling(true);
+    let v = vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm), Some(14));
+    let report = vm.profile_report();
+    // Neither method contains jumps, so each executes all its instructions exactly once.
+    let instrs =
+        method_instrs(&vm, &cls, "run").len() + 2 * method_instrs(&vm, &cls, "double:").len();
+    assert_eq!(report.instrs, instrs as u64);
+    // 2 `double:`s, 2 `*`s, and 1 `+`.
+    assert_eq!(report.sends, 5);
+    assert_eq!(report.primitives.len(), 2);
+    assert_eq!(report.primitives["*"], 2);
+    assert_eq!(report.primitives["+"], 1);
+
+    // Turning profiling off freezes the counters; turning it back on resets them.
+    vm.set_profiling(false);
+    vm.top_level_send(inst.clone(), "run", vec![]).unwrap();
+    assert_eq!(vm.profile_report(), report);
+    vm.set_profiling(true);
+    assert_eq!(vm.profile_report(), ProfileReport::default());
+}
+
+#[test]
+fn test_tail_calls() {
+    let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+        .tail_calls(true)
+        .build();
+    let cls = compile_tmp(
+        &mut vm,
+        "tail_calls = (
+            countdown: n = ( n = 0 ifTrue: [ ^n ]. ^self countdown: n - 1 )
+            escape = ( ^self call: [ ^1 ] )
+            call: blk = ( blk value. ^2 )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    // Without tail calls, this would need a million native frames.
+    let n = Val::from_isize(&mut vm, 1_000_000).unwrap();
+    let v = vm
+        .top_level_send(inst.clone(), "countdown:", vec![n])
+        .unwrap();
+    assert_eq!(v.as_isize(&mut vm), Some(0));
+    assert_eq!(vm.frames_len(), 0);
+    // `escape`'s frame is referenced by a block, so its tail call can't reuse it, or the
+    // block's non-local return would have nowhere to return to.
+    let v = vm.top_level_send(inst, "escape", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm), Some(1));
+}
+
+#[test]
+fn test_tail_calls_backtrace() {
+    let src = "tail_calls_backtrace = (
+        countdown: n = ( self check: n. ^self countdown: n - 1 )
+        check: n = ( ^10 / n )
+    )";
+    let countdowns = |tail_calls| {
+        let mut vm = VMBuilder::new(vec!["lib/SOM".to_owned()])
+            .tail_calls(tail_calls)
+            .build();
+        let cls = compile_tmp(&mut vm, src);
+        let inst = Inst::new(&mut vm, cls);
+        let n = Val::from_isize(&mut vm, 3).unwrap();
+        let e = vm.top_level_send(inst, "countdown:", vec![n]).unwrap_err();
+        assert_eq!(e.kind, VMErrorKind::DivisionByZero);
+        e.backtrace
+            .iter()
+            .filter(|(meth, _)| meth.name == "countdown:")
+            .count()
+    };
+    // By default every recursive call appears in the backtrace...
+    assert_eq!(countdowns(false), 4);
+    // ...but with tail calls the single reused frame stands in for all of them.
+    assert_eq!(countdowns(true), 1);
+}
+
+#[test]
+fn test_selector_arity() {
+    assert_eq!(selector_arity("a:b:c:d:"), 4);
+    assert_eq!(selector_arity("+"), 1);
+    assert_eq!(selector_arity("value"), 0);
+}
+
+#[test]
+fn test_class_hierarchy_string() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let hier_a = compile_tmp(&mut vm, "hier_a = Boolean ( )");
+    let hier_b = compile_tmp(&mut vm, "hier_b = ( )");
+    hier_b
+        .downcast::<Class>(&vm)
+        .unwrap()
+        .set_supercls(&vm, hier_a.clone());
+    vm.set_global("HierA", hier_a);
+    vm.set_global("HierB", hier_b);
+    let s = vm.class_hierarchy_string();
+    assert!(s.lines().any(|l| l == "Object"));
+    assert!(s.contains("\n  Boolean\n    False\n    True\n    hier_a\n      hier_b\n"));
+    assert!(!s.contains(" class"));
+}
+
+#[test]
+fn test_literal_dedup() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let (strings, arbints) = (vm.strings.len(), vm.arbints.len());
+    let cls = compile_tmp(
+        &mut vm,
+        "literal_dedup = (
+            strs = ( ^'dedup literal' , 'dedup literal' )
+            ints = ( ^100000000000000000000 + 100000000000000000000 )
+            arrs = ( ^#(1 2) == #(1 2) )
+        )",
+    );
+    // Each literal is stored once, with both uses referring to the same entry.
+    assert_eq!(vm.strings.len(), strings + 1);
+    assert_eq!(vm.arbints.len(), arbints + 1);
+    let s = format!("String({})", strings);
+    assert_eq!(
+        method_instrs(&vm, &cls, "strs"),
+        vec![s.as_str(), s.as_str(), "Send(,)", "Return"]
+    );
+    let a = format!("ArbInt({})", arbints);
+    assert_eq!(
+        method_instrs(&vm, &cls, "ints"),
+        vec![a.as_str(), a.as_str(), "Send(+)", "Return"]
+    );
+    // Array literals are mutable, so each evaluation creates a distinct array.
+    let inst = Inst::new(&mut vm, cls);
+    let v = vm.top_level_send(inst, "arrs", vec![]).unwrap();
+    assert!(v.bit_eq(&vm.false_));
+}
+
+#[test]
+fn test_compile_method() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "compile_method = (
+            m = ( ^1 )
+            ----
+            | cv |
+        )",
+    );
+    // A method which doesn't compile leaves the class unchanged.
+    let e = vm.compile_method(&cls, "m = (").unwrap_err();
+    assert!(matches!(e.kind, VMErrorKind::CompileError(_)));
+    let inst = Inst::new(&mut vm, cls.clone());
+    let v = vm.top_level_send(inst, "m", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm), Some(1));
+
+    // Methods added to a metaclass are class-side methods, with access to class-side
+    // variables.
+    let metacls = cls.downcast::<Class>(&vm).unwrap().metacls(&vm);
+    vm.compile_method(&metacls, "k = ( cv := 7. ^cv )").unwrap();
+    let v = vm.top_level_send(cls, "k", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm), Some(7));
+}
+
+#[test]
+fn test_drop_inst_vars() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "drop_inst_vars = (
+            | x |
+            x: v = ( x := v )
+        )",
+    );
+    let inst = Inst::new(&mut vm, cls);
+    let before = vm.gc_stats().live_objects;
+    let s = String_::new(&mut vm, "s".to_owned(), true);
+    // After this, `inst` holds the only reference to the string.
+    vm.top_level_send(inst.clone(), "x:", vec![s]).unwrap();
+    assert_eq!(vm.gc_stats().live_objects, before + 1);
+    drop(inst);
+    vm.drop_caches();
+    // Both the instance and the string have been freed.
+    assert_eq!(vm.gc_stats().live_objects, before - 1);
+}
+
+#[test]
+fn test_new_instance() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "new_instance = (
+            | x y |
+            sum = ( ^x + y )
+        )",
+    );
+    let inst = vm.new_instance(cls).unwrap();
+    {
+        let i: &Inst = inst.downcast(&vm).unwrap();
+        assert!(i.get_field(&vm, "x").unwrap().is_nil(&vm));
+        assert!(i.get_field(&vm, "y").unwrap().is_nil(&vm));
+        let v = Val::from_isize(&mut vm, 2).unwrap();
+        i.set_field(&vm, "x", v).unwrap();
+        let v = Val::from_isize(&mut vm, 3).unwrap();
+        i.set_field(&vm, "y", v).unwrap();
+        assert_eq!(
+            i.get_field(&vm, "z").unwrap_err().kind,
+            VMErrorKind::UnknownField("z".to_owned())
+        );
+    }
+    let v = vm.top_level_send(inst, "sum", vec![]).unwrap();
+    assert_eq!(v.as_isize(&mut vm), Some(5));
+
+    let v = Val::from_isize(&mut vm, 1).unwrap();
+    assert_eq!(
+        vm.new_instance(v).unwrap_err().kind,
+        VMErrorKind::TypeError {
+            expected: ObjType::Class,
+            got: ObjType::Int
+        }
+    );
+}
+
+#[test]
+fn test_inlined_conditionals() {
+    let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+    let cls = compile_tmp(
+        &mut vm,
+        "inlined_conditionals = (
+            inlined: b = ( ^b ifTrue: [ 1 ] ifFalse: [ 2 ] )
+            sent: b = ( | blk | blk := [ 1 ]. ^b ifTrue: blk ifFalse: [ 2 ] )
+            early: b = ( ^3 + (b ifTrue: [ ^1 ]) )
+            count = ( | i | i := 0. [ i < 10 ] whileTrue: [ i := i + 1 ]. ^i )
+        )",
+    );
+    assert_eq!(
+        method_instrs(&vm, &cls, "inlined:"),
+        vec![
+            "VarLookup(0, 1)",
+            "JumpIfFalse(4)",
+            "Int(1)",
+            "Jump(5)",
+            "Int(2)",
+            "Return"
+        ]
+    );
+    // A conditional whose arguments aren't all literal blocks is sent as normal.
+    let sent = method_instrs(&vm, &cls, "sent:");
+    assert!(sent.iter().any(|x| x == "Send(ifTrue:ifFalse:)"));
+    assert!(!sent.iter().any(|x| x.starts_with("Jump")));
+    assert!(cls.downcast::<Class>(&vm).unwrap().verify(&vm).is_ok());
+
+    let inst = Inst::new(&mut vm, cls);
+    for (b, expected) in &[(vm.true_.clone(), 1), (vm.false_.clone(), 2)] {
+        for name in &["inlined:", "sent:"] {
+            let v = vm.top_level_send(inst.clone(), name, vec![b.clone()]);
+            assert_eq!(v.unwrap().as_isize(&mut vm), Some(*expected));
+        }
+    }
+    let v = vm.top_level_send(inst.clone(), "early:", vec![vm.true_.clone()]);
+    assert_eq!(v.unwrap().as_isize(&mut vm), Some(1));
+    let e = vm.top_level_send(inst.clone(), "early:", vec![vm.false_.clone()]);
+    assert!(e.is_err());
+    let v = vm.top_level_send(inst.clone(), "count", vec![]);
+    assert_eq!(v.unwrap().as_isize(&mut vm), Some(10));
+    let e = vm
+        .top_level_send(inst, "inlined:", vec![vm.nil.clone()])
+        .unwrap_err();
+    assert!(matches!(e.kind, VMErrorKind::NotABoolean { .. }));
+}
@@ -1,12 +1,15 @@
-use std::{fs::read_to_string, io::stderr};
+use std::{fmt, fs::read_to_string, io::stderr, path::PathBuf};
 
 use abgc::Gc;
 use lrpar::Span;
 use termion::{is_tty, style};
 
-use crate::vm::{
-    core::VM,
-    objects::{Class, Method, ObjType},
+use crate::{
+    compiler::CompileError,
+    vm::{
+        core::VM,
+        objects::{Class, Method, ObjType},
+    },
 };
 
 #[derive(Debug)]
@@ -22,6 +25,13 @@ impl VMError {
         Box::new(VMError { kind, backtrace })
     }
 
+    /// Render this error's message for users. Unlike this error's `Display` implementation (which
+    /// has no access to the VM and thus uses the names of the VM's internal types), types are
+    /// referred to by the names of the SOM classes they correspond to.
+    pub fn render(&self, vm: &VM) -> String {
+        self.kind.to_string(vm)
+    }
+
     pub fn console_print(&self, vm: &VM) {
         eprintln!("Traceback (most recent call at bottom):");
         for (method, span) in self.backtrace.iter().rev() {
@@ -101,7 +111,7 @@ impl VMError {
                 eprintln!("File {}:", cls_path);
             }
         }
-        eprintln!("{}.", self.kind.to_string(vm));
+        eprintln!("{}.", self.render(vm));
     }
 
     fn newlines(&self, d: &str) -> Vec<usize> {
@@ -127,26 +137,74 @@ impl VMError {
 
 #[derive(Debug, PartialEq)]
 pub enum VMErrorKind {
+    /// Execution was stopped by a step hook returning
+    /// [`StepAction::Abort`](crate::vm::StepAction::Abort).
+    Aborted,
+    /// The call stack has grown deeper than the VM's maximum call depth.
+    CallDepthExceeded(usize),
+    /// Tried to copy an object whose type doesn't support copying.
+    CantCopy {
+        got: ObjType,
+    },
+    /// A class file which doesn't exist or can't be read.
+    CantLoadClass(PathBuf),
     /// A value which can't be represented in an `f64`.
     CantRepresentAsDouble,
     /// A value which can't be represented in an `isize`.
     CantRepresentAsIsize,
     /// A value which can't be represented in an `usize`.
     CantRepresentAsUsize,
+    /// Source code passed to [`VM::eval`] which could not be compiled.
+    CompileError(Vec<CompileError>),
     DivisionByZero,
     /// A value which is mathematically undefined.
     DomainError,
-    /// The VM is trying to exit.
-    Exit,
+    /// The VM is trying to exit with status `code`. This is not really an error: it is used to
+    /// unwind the call stack so that the embedder can decide how to exit.
+    Exit {
+        code: i32,
+    },
+    /// SOM code tried to read from the filesystem when the VM's configuration forbids it (see
+    /// [`VMConfig::allow_fs`](crate::vm::VMConfig::allow_fs)).
+    FilesystemAccessDenied,
+    /// Tried to index a collection of length `len` with `idx` (which is one-based).
+    IndexOutOfBounds {
+        idx: isize,
+        len: usize,
+    },
+    /// The VM has executed more instructions than its instruction limit.
+    InstructionLimitExceeded(u64),
+    /// An integer operation overflowed (only when the VM's overflow mode is
+    /// [`OverflowMode::Error`](crate::vm::OverflowMode::Error)).
+    IntegerOverflow,
+    /// Bytes passed to [`VM::load_compiled_class`](crate::vm::VM::load_compiled_class) which are
+    /// not a serialised class.
+    InvalidCompiledClass,
     /// Tried to access a global before it being initialised.
     InvalidSymbol,
+    /// Tried to access a key which isn't present in a dictionary.
+    KeyNotFound,
+    /// An instruction (at offset `instr_offset` within its method) refers to a constant, send, or
+    /// similar which does not exist.
+    MalformedBytecode {
+        instr_offset: usize,
+    },
     /// Tried to do a shl or shr with a value below zero.
     NegativeShift,
+    /// A specialised version of TypeError, because SOM's booleans are instances of the `True` and
+    /// `False` classes rather than having an `ObjType` of their own.
+    NotABoolean {
+        got: ObjType,
+    },
     /// A specialised version of TypeError, because SOM has more than one number type (and casts
     /// between them as necessary) so the `expected` field of `TypeError` doesn't quite work.
     NotANumber {
         got: ObjType,
     },
+    /// The heap has grown beyond the VM's maximum heap size.
+    OutOfMemory,
+    /// A print would take the VM's output beyond its output limit (in bytes).
+    OutputLimitExceeded(usize),
     /// Something went wrong when trying to execute a primitive.
     PrimitiveError,
     /// Tried to do a shl that would overflow memory and/or not fit in the required integer size.
@@ -156,15 +214,53 @@ pub enum VMErrorKind {
         expected: ObjType,
         got: ObjType,
     },
+    /// An object has no instance variable with this name.
+    UnknownField(String),
     /// An unknown global.
     UnknownGlobal(String),
     /// An unknown method.
     UnknownMethod(String),
+    /// The bytecode of `method` failed verification at `instr_offset` (see
+    /// [`Class::verify`](crate::vm::objects::Class::verify)) for the human readable `reason`.
+    VerificationFailed {
+        method: String,
+        instr_offset: usize,
+        reason: String,
+    },
+    /// A method taking `expected` arguments was called with `got` arguments.
+    WrongNumberOfArgs {
+        expected: usize,
+        got: usize,
+    },
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind.to_string_with(|t| t.as_str().to_owned()))
+    }
 }
 
 impl VMErrorKind {
-    fn to_string(&self, _: &VM) -> String {
+    pub(crate) fn to_string(&self, vm: &VM) -> String {
+        self.to_string_with(|t| match vm.objtype_cls(t) {
+            Some(cls_val) => cls_val
+                .downcast::<Class>(vm)
+                .unwrap()
+                .name_str(vm)
+                .to_owned(),
+            None => vm.objtype_name(t).to_owned(),
+        })
+    }
+
+    /// Produce this error's message, using `type_name` to convert `ObjType`s into names.
+    fn to_string_with<F: Fn(ObjType) -> String>(&self, type_name: F) -> String {
         match self {
+            VMErrorKind::Aborted => "Execution aborted".to_owned(),
+            VMErrorKind::CallDepthExceeded(d) => format!("Maximum call depth ({}) exceeded", d),
+            VMErrorKind::CantCopy { got } => {
+                format!("Can't copy an object of type '{}'", type_name(*got))
+            }
+            VMErrorKind::CantLoadClass(p) => format!("Can't load class from '{}'", p.display()),
             VMErrorKind::CantRepresentAsDouble => "Can't represent as double".to_owned(),
             VMErrorKind::CantRepresentAsIsize => {
                 "Can't represent as signed machine integer".to_owned()
@@ -172,23 +268,88 @@ impl VMErrorKind {
             VMErrorKind::CantRepresentAsUsize => {
                 "Can't represent as unsigned machine integer".to_owned()
             }
+            VMErrorKind::CompileError(errs) => errs
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
             VMErrorKind::DivisionByZero => "Division by zero".to_owned(),
             VMErrorKind::DomainError => "Domain error".to_owned(),
-            VMErrorKind::Exit => "Exit".to_owned(),
+            VMErrorKind::Exit { code } => format!("Exit with status {}", code),
+            VMErrorKind::FilesystemAccessDenied => "Filesystem access denied".to_owned(),
+            VMErrorKind::IndexOutOfBounds { idx, len } => {
+                format!("Index {} not valid for a collection of length {}", idx, len)
+            }
+            VMErrorKind::InstructionLimitExceeded(n) => {
+                format!("Instruction limit ({}) exceeded", n)
+            }
+            VMErrorKind::IntegerOverflow => "Integer overflow".to_owned(),
+            VMErrorKind::InvalidCompiledClass => "Invalid compiled class".to_owned(),
             VMErrorKind::InvalidSymbol => "Invalid symbol".to_owned(),
+            VMErrorKind::KeyNotFound => "Key not found".to_owned(),
+            VMErrorKind::MalformedBytecode { instr_offset } => {
+                format!("Malformed bytecode at instruction {}", instr_offset)
+            }
             VMErrorKind::NegativeShift => "Negative shift".to_owned(),
+            VMErrorKind::NotABoolean { got } => {
+                format!("Expected a boolean but got type '{}'", type_name(*got))
+            }
             VMErrorKind::NotANumber { got } => {
-                format!("Expected a numeric type but got type '{}'", got.as_str())
+                format!("Expected a numeric type but got type '{}'", type_name(*got))
             }
+            VMErrorKind::OutOfMemory => "Out of memory".to_owned(),
+            VMErrorKind::OutputLimitExceeded(n) => format!("Output limit ({} bytes) exceeded", n),
             VMErrorKind::PrimitiveError => "Primitive Error".to_owned(),
             VMErrorKind::ShiftTooBig => "Shift too big".to_owned(),
             VMErrorKind::TypeError { expected, got } => format!(
                 "Expected object of type '{}' but got type '{}'",
-                expected.as_str(),
-                got.as_str()
+                type_name(*expected),
+                type_name(*got)
             ),
+            VMErrorKind::UnknownField(name) => format!("Unknown field '{}'", name),
             VMErrorKind::UnknownGlobal(name) => format!("Unknown global '{}'", name),
             VMErrorKind::UnknownMethod(name) => format!("Unknown method '{}'", name),
+            VMErrorKind::VerificationFailed {
+                method,
+                instr_offset,
+                reason,
+            } => format!(
+                "Method '{}' failed verification at instruction {}: {}",
+                method, instr_offset, reason
+            ),
+            VMErrorKind::WrongNumberOfArgs { expected, got } => format!(
+                "Wrong number of arguments: expected {} but got {}",
+                expected, got
+            ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let vm = VM::new(vec!["lib/SOM".to_owned()]);
+        let e = VMError::new(
+            &vm,
+            VMErrorKind::TypeError {
+                expected: ObjType::Int,
+                got: ObjType::String_,
+            },
+        );
+        assert_eq!(
+            e.render(&vm),
+            "Expected object of type 'Integer' but got type 'String'"
+        );
+        assert_eq!(
+            e.to_string(),
+            "Expected object of type 'Int' but got type 'String_'"
+        );
+
+        let e = VMError::new(&vm, VMErrorKind::UnknownMethod("frobnicate".to_owned()));
+        assert_eq!(e.render(&vm), "Unknown method 'frobnicate'");
+        assert_eq!(e.to_string(), "Unknown method 'frobnicate'");
+    }
+}
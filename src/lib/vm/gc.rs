@@ -0,0 +1,227 @@
+//! Garbage collection accounting. yksom's objects are managed by abgc, which frees an object as
+//! soon as the last `Gc` (or `Val`) referencing it is dropped. The VM thus has no collector of its
+//! own, and objects in reference cycles (e.g. every class with methods, since each method refers
+//! back to its class) are never freed. The best the VM can do to encourage objects to be freed is
+//! to drop any references it is holding on to purely as an optimisation (see
+//! [`VM::drop_caches`](crate::vm::VM::drop_caches)).
+//! [`mark`] can find every object reachable from a set of roots by following each object's
+//! [`Obj::trace`](crate::vm::objects::Obj::trace), but it is not used to free objects.
+//!
+//! To allow users to observe the heap, every object created by
+//! [`Val::from_obj`](crate::vm::val::Val::from_obj) is recorded by [`track_alloc`], and every
+//! object type must call [`track_free`] when it is dropped. The objects recorded in between can be
+//! visited with [`for_each_live`]. Note that, since objects can be freed
+//! without reference to a VM, this accounting is per-thread rather than per-VM.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    mem::{size_of, size_of_val},
+    ptr::NonNull,
+    rc::Rc,
+};
+
+use abgc::Gc;
+
+use crate::vm::{objects::ThinObj, val::Val};
+
+thread_local! {
+    /// The (number of objects, number of bytes) currently live on this thread.
+    static LIVE: Cell<(usize, usize)> = Cell::new((0, 0));
+    /// Maps the address of each object currently live on this thread to its `ThinObj`.
+    static OBJECTS: RefCell<HashMap<usize, *const ThinObj>> = RefCell::new(HashMap::new());
+    /// Maps the address of each weakly referenced object to the `alive` flags of the `WeakRef`s
+    /// which refer to it.
+    static WEAK: RefCell<HashMap<usize, Vec<Rc<Cell<bool>>>>> = RefCell::new(HashMap::new());
+    /// Maps the address of each object which has been asked for its identity to that identity.
+    static IDENTITIES: RefCell<HashMap<usize, usize>> = RefCell::new(HashMap::new());
+    /// The identity that will be given to the next object which asks for one.
+    static NEXT_IDENTITY: Cell<usize> = Cell::new(0);
+}
+
+/// A snapshot of the garbage collector's state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GCStats {
+    /// How many objects are currently live?
+    pub live_objects: usize,
+    /// How many bytes are used by the currently live objects? Note that this only includes the
+    /// size of the object itself, not any memory it separately allocates (e.g. the contents of a
+    /// string).
+    pub live_bytes: usize,
+}
+
+/// Record that an object of type `T` has been allocated at `addr`, boxed in `tobj`.
+pub(crate) fn track_alloc<T>(addr: usize, tobj: *const ThinObj) {
+    LIVE.with(|l| {
+        let (objs, bytes) = l.get();
+        l.set((objs + 1, bytes + size_of::<T>()));
+    });
+    OBJECTS.with(|o| o.borrow_mut().insert(addr, tobj));
+}
+
+/// Record that `obj` is about to be freed, clearing any weak references to it and forgetting its
+/// identity.
+pub(crate) fn track_free<T>(obj: &T) {
+    LIVE.with(|l| {
+        let (objs, bytes) = l.get();
+        l.set((objs - 1, bytes - size_of_val(obj)));
+    });
+    let addr = obj as *const T as *const u8 as usize;
+    OBJECTS.with(|o| o.borrow_mut().remove(&addr));
+    WEAK.with(|w| {
+        let mut w = w.borrow_mut();
+        if !w.is_empty() {
+            if let Some(alives) = w.remove(&addr) {
+                for a in alives {
+                    a.set(false);
+                }
+            }
+        }
+    });
+    IDENTITIES.with(|ids| {
+        let mut ids = ids.borrow_mut();
+        if !ids.is_empty() {
+            ids.remove(&addr);
+        }
+    });
+}
+
+/// Return the identity of the object at `addr`, assigning it a fresh one if it has not been asked
+/// for one before. Unlike the object's address, an object's identity never changes, even if the
+/// object is moved, and is never reused for another object on this thread.
+pub(crate) fn identity(addr: usize) -> usize {
+    IDENTITIES.with(|ids| {
+        *ids.borrow_mut().entry(addr).or_insert_with(|| {
+            NEXT_IDENTITY.with(|n| {
+                let id = n.get();
+                n.set(id + 1);
+                id
+            })
+        })
+    })
+}
+
+/// Record that `alive` should be set to `false` when the object at `addr` is freed.
+pub(crate) fn register_weak(addr: usize, alive: Rc<Cell<bool>>) {
+    WEAK.with(|w| w.borrow_mut().entry(addr).or_default().push(alive));
+}
+
+/// Undo a previous call of `register_weak(addr, alive)`.
+pub(crate) fn unregister_weak(addr: usize, alive: &Rc<Cell<bool>>) {
+    WEAK.with(|w| {
+        let mut w = w.borrow_mut();
+        if let Some(alives) = w.get_mut(&addr) {
+            alives.retain(|a| !Rc::ptr_eq(a, alive));
+            if alives.is_empty() {
+                w.remove(&addr);
+            }
+        }
+    });
+}
+
+/// Return the (number of objects, number of bytes) currently live on this thread.
+pub(crate) fn live() -> (usize, usize) {
+    LIVE.with(|l| l.get())
+}
+
+/// Call `f` on each object currently live on this thread. Each object is kept alive while `f` is
+/// called on it. Objects freed before `f` reaches them are skipped, and objects allocated by `f`
+/// may or may not be visited.
+pub(crate) fn for_each_live<F: FnMut(&ThinObj)>(mut f: F) {
+    let addrs = OBJECTS.with(|o| o.borrow().keys().copied().collect::<Vec<_>>());
+    for addr in addrs {
+        let tobj = match OBJECTS.with(|o| o.borrow().get(&addr).copied()) {
+            Some(t) => t,
+            None => continue,
+        };
+        // Every object in `OBJECTS` has a non-zero reference count, so it is safe to take another
+        // reference to it.
+        let tobj = unsafe { Gc::clone_from_raw(NonNull::new_unchecked(tobj as *mut ThinObj)) };
+        f(&tobj);
+    }
+}
+
+/// Return every boxed object reachable from `roots`, each exactly once, in the order they were
+/// first visited. Objects' children are found with [`Val::trace`].
+pub fn mark(roots: &[Val]) -> Vec<Val> {
+    let mut seen = HashSet::new();
+    let mut marked = Vec::new();
+    let mut todo = roots.to_vec();
+    while let Some(v) = todo.pop() {
+        if !v.is_gcbox() || !seen.insert(v.val) {
+            continue;
+        }
+        v.trace(&mut |c| todo.push(c.clone()));
+        marked.push(v);
+    }
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::{core::VM, objects::Array};
+
+    #[test]
+    fn test_mark_cycle() {
+        let mut vm = VM::new_no_bootstrap();
+        let one = Val::from_isize(&mut vm, 1).unwrap();
+        let two = Val::from_isize(&mut vm, 2).unwrap();
+        let a1 = Array::from_vec(&mut vm, vec![one.clone(), one.clone()]);
+        let a2 = Array::from_vec(&mut vm, vec![one.clone(), a1.clone()]);
+        // a1 -> a1, a1 -> a2, a2 -> a1.
+        let arr = a1.downcast::<Array>(&vm).unwrap();
+        arr.at_put(&mut vm, one.clone(), a1.clone()).unwrap();
+        arr.at_put(&mut vm, two.clone(), a2.clone()).unwrap();
+
+        let marked = mark(&[a2.clone(), a1.clone(), a2.clone()]);
+        assert_eq!(marked.len(), 2);
+        assert!(marked.contains(&a1));
+        assert!(marked.contains(&a2));
+
+        // Break the cycle so that both arrays can be freed.
+        arr.at_put(&mut vm, one.clone(), one.clone()).unwrap();
+        arr.at_put(&mut vm, two, one).unwrap();
+    }
+
+    #[test]
+    fn test_identity() {
+        let mut vm = VM::new_no_bootstrap();
+        let a1 = Array::new(&mut vm, 0);
+        let a2 = Array::new(&mut vm, 0);
+        let id1 = a1
+            .identity_hash(&mut vm)
+            .unwrap()
+            .as_usize(&mut vm)
+            .unwrap();
+        let id2 = a2
+            .identity_hash(&mut vm)
+            .unwrap()
+            .as_usize(&mut vm)
+            .unwrap();
+        assert_ne!(id1, id2);
+        vm.drop_caches();
+        assert_eq!(
+            a1.identity_hash(&mut vm)
+                .unwrap()
+                .as_usize(&mut vm)
+                .unwrap(),
+            id1
+        );
+        assert_eq!(
+            a1.hashcode(&mut vm).unwrap().as_usize(&mut vm).unwrap(),
+            id1
+        );
+
+        // A new object which reuses a freed object's address must not reuse its identity.
+        drop(a2);
+        let a3 = Array::new(&mut vm, 0);
+        let id3 = a3
+            .identity_hash(&mut vm)
+            .unwrap()
+            .as_usize(&mut vm)
+            .unwrap();
+        assert_ne!(id3, id1);
+        assert_ne!(id3, id2);
+    }
+}
@@ -8,13 +8,19 @@
 //! [`Val::downcast`](vm::val::Val::downcast) (or
 //! [`Val::try_downcast`](vm::val::Val::try_downcast)) it to a concrete implementation of `Obj`.
 
+pub mod config;
 pub mod core;
 pub mod error;
+pub mod gc;
 pub mod objects;
 pub mod somstack;
 pub mod val;
 
 pub use crate::vm::{
-    core::VM,
+    config::{OverflowMode, VMBuilder, VMConfig},
+    core::{
+        DnuHandler, FrameSnapshot, PrimitiveFn, ProfileReport, StepAction, StepHook, SymId, VM,
+    },
     error::{VMError, VMErrorKind},
+    gc::GCStats,
 };
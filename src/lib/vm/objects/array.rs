@@ -0,0 +1,170 @@
+#![allow(clippy::new_ret_no_self)]
+
+use std::cell::UnsafeCell;
+
+use abgc_derive::GcLayout;
+
+use crate::vm::{
+    core::VM,
+    error::{VMError, VMErrorKind},
+    gc,
+    objects::{Int, Obj, ObjType, StaticObjType, String_},
+    val::{NotUnboxable, Val},
+};
+
+/// A SOM `Array`: a fixed-length sequence of values, indexed from 1.
+#[derive(Debug, GcLayout)]
+pub struct Array {
+    store: UnsafeCell<Vec<Val>>,
+}
+
+impl Obj for Array {
+    fn dyn_objtype(&self) -> ObjType {
+        ObjType::Array
+    }
+
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        for v in unsafe { &*self.store.get() } {
+            visitor(v);
+        }
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Array::from_vec(vm, self.to_vec()))
+    }
+
+    fn get_class(&self, vm: &mut VM) -> Val {
+        vm.array_cls.clone()
+    }
+}
+
+impl NotUnboxable for Array {}
+
+impl StaticObjType for Array {
+    fn static_objtype() -> ObjType {
+        ObjType::Array
+    }
+}
+
+impl Drop for Array {
+    fn drop(&mut self) {
+        // Each element of `store` is dropped (and thus has its reference count decremented)
+        // exactly once by `store`'s own drop glue, which runs after this function.
+        gc::track_free(self);
+    }
+}
+
+impl Array {
+    /// Create an array of `len` elements, each of which is `nil`.
+    pub fn new(vm: &mut VM, len: usize) -> Val {
+        let store = vec![vm.nil.clone(); len];
+        Array::from_vec(vm, store)
+    }
+
+    pub fn from_vec(vm: &mut VM, store: Vec<Val>) -> Val {
+        Val::from_obj(
+            vm,
+            Array {
+                store: UnsafeCell::new(store),
+            },
+        )
+    }
+
+    /// Return the element at (1-based) index `idx`.
+    pub fn at(&self, vm: &mut VM, idx: Val) -> Result<Val, Box<VMError>> {
+        let i = self.index(vm, idx)?;
+        let store = unsafe { &*self.store.get() };
+        Ok(store[i].clone())
+    }
+
+    /// Set the element at (1-based) index `idx` to `v`.
+    pub fn at_put(&self, vm: &mut VM, idx: Val, v: Val) -> Result<(), Box<VMError>> {
+        let i = self.index(vm, idx)?;
+        let store = unsafe { &mut *self.store.get() };
+        store[i] = v;
+        Ok(())
+    }
+
+    /// How many elements does this array contain?
+    pub fn length(&self) -> usize {
+        let store = unsafe { &*self.store.get() };
+        store.len()
+    }
+
+    /// Return a copy of this array's elements.
+    pub fn to_vec(&self) -> Vec<Val> {
+        let store = unsafe { &*self.store.get() };
+        store.clone()
+    }
+
+    /// Return a new array containing this array's elements followed by `v`.
+    pub fn copy_with(&self, vm: &mut VM, v: Val) -> Val {
+        let mut store = self.to_vec();
+        store.push(v);
+        Array::from_vec(vm, store)
+    }
+
+    /// Concatenate this array's elements, which must all be strings, separated by the string
+    /// `sep`.
+    pub fn join_using(&self, vm: &mut VM, sep: Val) -> Result<Val, Box<VMError>> {
+        let sep = sep.downcast::<String_>(vm)?.as_str();
+        let mut s = String::new();
+        for (i, e) in self.to_vec().iter().enumerate() {
+            if i > 0 {
+                s.push_str(sep);
+            }
+            s.push_str(e.downcast::<String_>(vm)?.as_str());
+        }
+        Ok(String_::new(vm, s, true))
+    }
+
+    /// Convert the SOM (1-based) index `idx` into a 0-based index into `store`, checking that it
+    /// is in bounds.
+    fn index(&self, vm: &mut VM, idx: Val) -> Result<usize, Box<VMError>> {
+        let i = match idx.as_isize(vm) {
+            Some(i) => i,
+            None => {
+                let got = idx.dyn_objtype(vm);
+                return Err(VMError::new(
+                    vm,
+                    VMErrorKind::TypeError {
+                        expected: Int::static_objtype(),
+                        got,
+                    },
+                ));
+            }
+        };
+        let len = self.length();
+        if i > 0 && (i as usize) <= len {
+            Ok(i as usize - 1)
+        } else {
+            Err(VMError::new(
+                vm,
+                VMErrorKind::IndexOutOfBounds { idx: i, len },
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::objects::String_;
+
+    #[test]
+    fn test_drop_elements() {
+        let mut vm = VM::new_no_bootstrap();
+        let (before, _) = gc::live();
+        let s = String_::new(&mut vm, "s".to_owned(), true);
+        // The array holds the only two references to `s`.
+        let arr = Array::from_vec(&mut vm, vec![s.clone(), s]);
+        assert_eq!(gc::live().0, before + 2);
+        let elems = arr.downcast::<Array>(&vm).unwrap().to_vec();
+        assert_eq!(elems[1].downcast::<String_>(&vm).unwrap().as_str(), "s");
+        drop(elems);
+        assert_eq!(gc::live().0, before + 2);
+        drop(arr);
+        vm.drop_caches();
+        assert_eq!(gc::live().0, before);
+    }
+}
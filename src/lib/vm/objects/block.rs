@@ -5,6 +5,7 @@ use abgc_derive::GcLayout;
 
 use crate::vm::{
     core::{Closure, VM},
+    gc,
     objects::{Method, Obj, ObjType, StaticObjType},
     val::{NotUnboxable, Val},
 };
@@ -36,6 +37,13 @@ impl Obj for Block {
         ObjType::Block
     }
 
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        visitor(&self.method.class());
+        visitor(&self.inst);
+        visitor(&self.blockn_cls);
+        self.parent_closure.trace(visitor);
+    }
+
     fn get_class(&self, _: &mut VM) -> Val {
         self.blockn_cls.clone()
     }
@@ -49,6 +57,12 @@ impl StaticObjType for Block {
     }
 }
 
+impl Drop for Block {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl Block {
     pub fn new(
         vm: &mut VM,
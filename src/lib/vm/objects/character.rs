@@ -0,0 +1,79 @@
+#![allow(clippy::new_ret_no_self)]
+
+use abgc_derive::GcLayout;
+
+use crate::vm::{
+    core::VM,
+    error::VMError,
+    gc,
+    objects::{Obj, ObjType, StaticObjType, String_},
+    val::{NotUnboxable, Val},
+};
+
+/// A single (Unicode) character.
+#[derive(Debug, GcLayout)]
+pub struct Character {
+    c: char,
+}
+
+impl Obj for Character {
+    fn dyn_objtype(&self) -> ObjType {
+        ObjType::Character
+    }
+
+    fn get_class(&self, vm: &mut VM) -> Val {
+        vm.char_cls.clone()
+    }
+
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Val::from_usize(vm, self.c as usize)
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Character::new(vm, self.c))
+    }
+
+    fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Val::from_usize(vm, self.c as usize)
+    }
+
+    fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(String_::new(vm, self.c.to_string(), true))
+    }
+
+    fn ref_equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        self.equals(vm, other)
+    }
+
+    fn equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        let b = match other.try_downcast::<Character>(vm) {
+            Some(other_chr) => self.c == other_chr.c,
+            None => false,
+        };
+        Ok(Val::from_bool(vm, b))
+    }
+}
+
+impl NotUnboxable for Character {}
+
+impl StaticObjType for Character {
+    fn static_objtype() -> ObjType {
+        ObjType::Character
+    }
+}
+
+impl Drop for Character {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
+impl Character {
+    pub fn new(vm: &mut VM, c: char) -> Val {
+        Val::from_obj(vm, Character { c })
+    }
+
+    pub fn as_char(&self) -> char {
+        self.c
+    }
+}
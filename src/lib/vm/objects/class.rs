@@ -1,14 +1,20 @@
 #![allow(clippy::new_ret_no_self)]
 
-use std::{cell::UnsafeCell, collections::HashMap, path::PathBuf, str};
+use std::{
+    cell::UnsafeCell,
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    str,
+};
 
 use abgc::Gc;
 use abgc_derive::GcLayout;
 
 use crate::vm::{
-    core::VM,
+    core::{SymId, VM},
     error::{VMError, VMErrorKind},
-    objects::{Method, Obj, ObjType, StaticObjType},
+    gc,
+    objects::{Method, Obj, ObjType, StaticObjType, String_},
     val::{NotUnboxable, Val, ValKind},
 };
 
@@ -21,7 +27,9 @@ pub struct Class {
     pub instrs_off: usize,
     supercls: UnsafeCell<Val>,
     pub num_inst_vars: usize,
-    pub methods: HashMap<String, Gc<Method>>,
+    /// The names of this class's instance variables, in the order they are stored.
+    pub inst_var_names: Vec<String>,
+    methods: UnsafeCell<HashMap<SymId, Gc<Method>>>,
     inst_vars: UnsafeCell<Vec<Val>>,
 }
 
@@ -30,6 +38,18 @@ impl Obj for Class {
         ObjType::Class
     }
 
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        visitor(unsafe { &*self.metacls.get() });
+        visitor(&self.name);
+        visitor(unsafe { &*self.supercls.get() });
+        for v in unsafe { &*self.inst_vars.get() } {
+            visitor(v);
+        }
+        for m in self.methods().values() {
+            visitor(&m.class());
+        }
+    }
+
     fn get_class(&self, _: &mut VM) -> Val {
         debug_assert!(unsafe { &*self.metacls.get() }.valkind() != ValKind::ILLEGAL);
         unsafe { &*self.metacls.get() }.clone()
@@ -54,6 +74,12 @@ impl StaticObjType for Class {
     }
 }
 
+impl Drop for Class {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl Class {
     pub fn new(
         vm: &VM,
@@ -62,8 +88,8 @@ impl Class {
         path: PathBuf,
         instrs_off: usize,
         supercls: Val,
-        num_inst_vars: usize,
-        methods: HashMap<String, Gc<Method>>,
+        inst_var_names: Vec<String>,
+        methods: HashMap<SymId, Gc<Method>>,
     ) -> Self {
         let cls = Class {
             metacls: UnsafeCell::new(metacls.clone()),
@@ -71,8 +97,9 @@ impl Class {
             path,
             instrs_off,
             supercls: UnsafeCell::new(supercls),
-            num_inst_vars,
-            methods,
+            num_inst_vars: inst_var_names.len(),
+            inst_var_names,
+            methods: UnsafeCell::new(methods),
             inst_vars: UnsafeCell::new(vec![]),
         };
         cls.set_metacls(vm, metacls);
@@ -83,20 +110,89 @@ impl Class {
         Ok(self.name.clone())
     }
 
+    /// Return this class's name as a Rust string.
+    pub fn name_str(&self, vm: &VM) -> &str {
+        self.name.downcast::<String_>(vm).unwrap().as_str()
+    }
+
     pub fn get_method(&self, vm: &VM, msg: &str) -> Result<Gc<Method>, Box<VMError>> {
-        self.methods
-            .get(msg)
+        match vm.symbol_id(msg) {
+            Some(id) => self.get_method_sym(vm, id),
+            // Every method's name is interned, so if `msg` hasn't been, no such method exists.
+            None => Err(VMError::new(vm, VMErrorKind::UnknownMethod(msg.to_owned()))),
+        }
+    }
+
+    /// Look up the method whose name is the symbol `id` in this class or its superclasses.
+    pub fn get_method_sym(&self, vm: &VM, id: SymId) -> Result<Gc<Method>, Box<VMError>> {
+        self.methods()
+            .get(&id)
             .map(|x| Ok(Gc::clone(x)))
             .unwrap_or_else(|| {
                 let supercls = self.supercls(vm);
-                if supercls != vm.nil {
-                    supercls.downcast::<Class>(vm)?.get_method(vm, msg)
+                if !supercls.is_nil(vm) {
+                    supercls.downcast::<Class>(vm)?.get_method_sym(vm, id)
                 } else {
-                    Err(VMError::new(vm, VMErrorKind::UnknownMethod(msg.to_owned())))
+                    Err(VMError::new(
+                        vm,
+                        VMErrorKind::UnknownMethod(vm.symbol_str(id).to_owned()),
+                    ))
                 }
             })
     }
 
+    /// The methods defined in this class, not including those it inherits. The reference returned
+    /// must not be held across a call to [`Class::add_method`].
+    pub(crate) fn methods(&self) -> &HashMap<SymId, Gc<Method>> {
+        unsafe { &*self.methods.get() }
+    }
+
+    /// Add `meth` to this class, replacing any existing method of the same name. Inline caches
+    /// may refer to the replaced method (or to one which this class, or its subclasses, previously
+    /// inherited) so all of them are cleared.
+    pub fn add_method(&self, vm: &mut VM, meth: Gc<Method>) {
+        meth.set_class(vm, Val::recover(self));
+        let id = vm.intern_symbol(&meth.name);
+        unsafe { &mut *self.methods.get() }.insert(id, meth);
+        vm.clear_inline_caches();
+    }
+
+    /// Return the sorted names of the methods defined in this class, not including those it
+    /// inherits.
+    pub fn method_names(&self, vm: &VM) -> Vec<String> {
+        let mut names = self
+            .methods()
+            .keys()
+            .map(|id| vm.symbol_str(*id).to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Return the sorted names of the methods that this class understands, including those it
+    /// inherits.
+    pub fn all_method_names(&self, vm: &VM) -> Vec<String> {
+        let mut ids = self.methods().keys().cloned().collect::<HashSet<_>>();
+        let mut supercls = self.supercls(vm);
+        while !supercls.is_nil(vm) {
+            let cls: &Class = supercls.downcast(vm).unwrap();
+            ids.extend(cls.methods().keys().cloned());
+            supercls = cls.supercls(vm);
+        }
+        let mut names = ids
+            .into_iter()
+            .map(|id| vm.symbol_str(id).to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Return the (zero-based) index of the instance variable `name`, or `None` if this class
+    /// has no such instance variable.
+    pub fn inst_var_index(&self, name: &str) -> Option<usize> {
+        self.inst_var_names.iter().position(|n| n == name)
+    }
+
     pub fn set_metacls(&self, vm: &VM, cls_val: Val) {
         // This method is called during VM bootstrapping when not all objects have valid
         // references.
@@ -109,6 +205,23 @@ impl Class {
         }
     }
 
+    /// Check that the bytecode of each of this class's methods is well-formed: that every operand
+    /// refers to an existing constant, send, variable, etc.; that the bytecode of each block lies
+    /// within the VM's instructions; that no method or block can run off the end of its bytecode;
+    /// and that the stack can neither underflow nor grow beyond the size the compiler calculated.
+    /// The compiler only generates well-formed bytecode, so this is mostly useful for catching
+    /// bugs (see [`VMConfig::verify_bytecode`](crate::vm::VMConfig::verify_bytecode)).
+    pub fn verify(&self, vm: &VM) -> Result<(), Box<VMError>> {
+        for meth in self.methods().values() {
+            vm.verify_method(self, meth)?;
+        }
+        Ok(())
+    }
+
+    pub fn metacls(&self, _: &VM) -> Val {
+        unsafe { &*self.metacls.get() }.clone()
+    }
+
     pub fn supercls(&self, _: &VM) -> Val {
         unsafe { &*self.supercls.get() }.clone()
     }
@@ -0,0 +1,135 @@
+#![allow(clippy::new_ret_no_self)]
+
+use std::{cell::UnsafeCell, collections::HashMap};
+
+use abgc_derive::GcLayout;
+
+use crate::vm::{
+    core::VM,
+    error::VMError,
+    gc,
+    objects::{Obj, ObjType, StaticObjType},
+    val::{NotUnboxable, Val},
+};
+
+/// A SOM `Dictionary`. Keys are grouped into buckets by their SOM `hashcode`, and keys within a
+/// bucket are distinguished with SOM `=`. Since both of those are arbitrary SOM methods, this
+/// struct can't search for keys itself: the VM finds the right bucket and the stored key within it
+/// (see `VM::dict_find`) and then uses the (`hash`, stored key) pair to access the `Dictionary`.
+/// Entries are identified by their stored key rather than their position because the SOM methods
+/// can themselves change the dictionary.
+#[derive(Debug, GcLayout)]
+pub struct Dictionary {
+    buckets: UnsafeCell<HashMap<isize, Vec<(Val, Val)>>>,
+}
+
+impl Obj for Dictionary {
+    fn dyn_objtype(&self) -> ObjType {
+        ObjType::Dictionary
+    }
+
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        for (k, v) in unsafe { &*self.buckets.get() }.values().flatten() {
+            visitor(k);
+            visitor(v);
+        }
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        let buckets = unsafe { &*self.buckets.get() };
+        let copy = Dictionary {
+            buckets: UnsafeCell::new(buckets.clone()),
+        };
+        Ok(Val::from_obj(vm, copy))
+    }
+
+    fn get_class(&self, vm: &mut VM) -> Val {
+        vm.dict_cls.clone()
+    }
+}
+
+impl NotUnboxable for Dictionary {}
+
+impl StaticObjType for Dictionary {
+    fn static_objtype() -> ObjType {
+        ObjType::Dictionary
+    }
+}
+
+impl Drop for Dictionary {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
+impl Dictionary {
+    pub fn new(vm: &mut VM) -> Val {
+        Val::from_obj(
+            vm,
+            Dictionary {
+                buckets: UnsafeCell::new(HashMap::new()),
+            },
+        )
+    }
+
+    /// Return the keys whose hashcode is `hash`.
+    pub fn keys(&self, hash: isize) -> Vec<Val> {
+        let buckets = unsafe { &*self.buckets.get() };
+        match buckets.get(&hash) {
+            Some(b) => b.iter().map(|(k, _)| k.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return the value of the entry in bucket `hash` whose key is (bit equal to) `k`, or `None`
+    /// if there is no such entry.
+    pub fn get(&self, hash: isize, k: &Val) -> Option<Val> {
+        let buckets = unsafe { &*self.buckets.get() };
+        let bucket = buckets.get(&hash)?;
+        bucket
+            .iter()
+            .find(|(ek, _)| ek.bit_eq(k))
+            .map(|(_, v)| v.clone())
+    }
+
+    /// Set the value of the entry in bucket `hash` whose key is (bit equal to) `k` to `v` or, if
+    /// there is no such entry, add a new entry `k: v` to bucket `hash`.
+    pub fn set(&self, hash: isize, k: Val, v: Val) {
+        let buckets = unsafe { &mut *self.buckets.get() };
+        let bucket = buckets.entry(hash).or_insert_with(Vec::new);
+        match bucket.iter_mut().find(|(ek, _)| ek.bit_eq(&k)) {
+            Some(e) => e.1 = v,
+            None => bucket.push((k, v)),
+        }
+    }
+
+    /// Remove the entry in bucket `hash` whose key is (bit equal to) `k`, returning its value, or
+    /// `None` if there is no such entry.
+    pub fn remove(&self, hash: isize, k: &Val) -> Option<Val> {
+        let buckets = unsafe { &mut *self.buckets.get() };
+        let bucket = buckets.get_mut(&hash)?;
+        let i = bucket.iter().position(|(ek, _)| ek.bit_eq(k))?;
+        let (_, v) = bucket.remove(i);
+        if bucket.is_empty() {
+            buckets.remove(&hash);
+        }
+        Some(v)
+    }
+
+    /// Return all of this dictionary's (key, value) entries (in no particular order).
+    pub fn entries(&self) -> Vec<(Val, Val)> {
+        let buckets = unsafe { &*self.buckets.get() };
+        buckets.values().flatten().cloned().collect()
+    }
+
+    /// How many entries does this dictionary contain?
+    pub fn len(&self) -> usize {
+        let buckets = unsafe { &*self.buckets.get() };
+        buckets.values().map(|b| b.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let buckets = unsafe { &*self.buckets.get() };
+        buckets.is_empty()
+    }
+}
@@ -1,15 +1,30 @@
 #![allow(clippy::new_ret_no_self)]
 
 use abgc_derive::GcLayout;
-use num_traits::{ToPrimitive, Zero};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
 
 use crate::vm::{
     core::VM,
     error::{VMError, VMErrorKind},
-    objects::{ArbInt, Obj, ObjType, StaticObjType, String_},
+    gc,
+    objects::{hash_bytes, ArbInt, Obj, ObjType, StaticObjType, String_},
     val::{NotUnboxable, Val},
 };
 
+/// The ways in which a double can be rounded to an integer.
+#[derive(Clone, Copy, Debug)]
+pub enum RoundMode {
+    /// Round towards positive infinity.
+    Ceiling,
+    /// Round towards negative infinity.
+    Floor,
+    /// Round to the nearest integer, with halves rounded away from zero.
+    Nearest,
+    /// Round towards zero.
+    Truncate,
+}
+
 #[derive(Debug, GcLayout)]
 /// A boxed Double (which is synonymous with a f64 in yksom).
 pub struct Double {
@@ -27,9 +42,37 @@ impl Obj for Double {
         vm.double_cls.clone()
     }
 
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        // 0.0 and -0.0 are equal, but have different bit patterns.
+        let val = if self.val == 0.0 { 0.0f64 } else { self.val };
+        hash_bytes(vm, &val.to_bits().to_le_bytes())
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Double::new(vm, self.val))
+    }
+
+    fn to_doubleval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Double::new(vm, self.val))
+    }
+
+    /// Truncate this double (i.e. round it towards zero) to an integer.
+    fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        let t = self.val.trunc();
+        // `isize::max_value() as f64` rounds up to 2^63 (on a 64-bit machine), which is why the
+        // upper bound is exclusive. NaN fails both comparisons.
+        if t >= isize::min_value() as f64 && t < isize::max_value() as f64 {
+            Val::from_isize(vm, t as isize)
+        } else {
+            Err(VMError::new(vm, VMErrorKind::CantRepresentAsIsize))
+        }
+    }
+
     fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        // 0.0 and -0.0 are equal, so we print them the same way.
+        let val = if self.val == 0.0 { 0.0f64 } else { self.val };
         let mut buf = ryu::Buffer::new();
-        Ok(String_::new(vm, buf.format(self.val).to_owned(), true))
+        Ok(String_::new(vm, buf.format(val).to_owned(), true))
     }
 
     fn add(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
@@ -109,7 +152,11 @@ impl Obj for Double {
     }
 
     fn sqrt(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
-        Ok(Double::new(vm, self.val.sqrt()))
+        if self.val < 0.0 {
+            Err(VMError::new(vm, VMErrorKind::DomainError))
+        } else {
+            Ok(Double::new(vm, self.val.sqrt()))
+        }
     }
 
     fn sub(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
@@ -179,6 +226,12 @@ impl StaticObjType for Double {
     }
 }
 
+impl Drop for Double {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl Double {
     pub fn new(vm: &mut VM, val: f64) -> Val {
         Val::from_obj(vm, Double { val })
@@ -187,4 +240,40 @@ impl Double {
     pub fn double(&self) -> f64 {
         self.val
     }
+
+    /// Round this double to an integer using `mode`.
+    pub fn round(&self, vm: &mut VM, mode: RoundMode) -> Result<Val, Box<VMError>> {
+        let r = match mode {
+            RoundMode::Ceiling => self.val.ceil(),
+            RoundMode::Floor => self.val.floor(),
+            RoundMode::Nearest => self.val.round(),
+            RoundMode::Truncate => self.val.trunc(),
+        };
+        // Infinities and NaN have no integer equivalent.
+        match BigInt::from_f64(r) {
+            Some(i) => ArbInt::new(vm, i),
+            None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsIsize)),
+        }
+    }
+
+    /// Round this double to the nearest multiple of the number `other`.
+    pub fn round_to(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        let rhs = if let Some(rhs) = other.as_isize(vm) {
+            rhs as f64
+        } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
+            rhs.val
+        } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+            match rhs.bigint().to_f64() {
+                Some(i) => i,
+                None => return Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
+            }
+        } else {
+            let got = other.dyn_objtype(vm);
+            return Err(VMError::new(vm, VMErrorKind::NotANumber { got }));
+        };
+        if rhs == 0.0 {
+            return Err(VMError::new(vm, VMErrorKind::DivisionByZero));
+        }
+        Ok(Double::new(vm, (self.val / rhs).round() * rhs))
+    }
 }
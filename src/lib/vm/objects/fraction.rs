@@ -0,0 +1,225 @@
+#![allow(clippy::new_ret_no_self)]
+
+use abgc_derive::GcLayout;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+
+use crate::vm::{
+    core::VM,
+    error::{VMError, VMErrorKind},
+    gc,
+    objects::{hash_bytes, ArbInt, Double, Obj, ObjType, StaticObjType, String_},
+    val::{NotUnboxable, Val},
+};
+
+/// The arithmetic operations that can be performed on fractions.
+#[derive(Clone, Copy, Debug)]
+pub enum FracOp {
+    Add,
+    Div,
+    Mul,
+    Sub,
+}
+
+/// An exact rational number. Fractions are always normalized: `den` is positive and greater than
+/// 1, and `num` and `den` have no common divisor. Any value which would normalize to a whole
+/// number is represented as an integer instead.
+#[derive(Debug, GcLayout)]
+pub struct Fraction {
+    num: BigInt,
+    den: BigInt,
+}
+
+impl Obj for Fraction {
+    fn dyn_objtype(&self) -> ObjType {
+        ObjType::Fraction
+    }
+
+    fn get_class(&self, vm: &mut VM) -> Val {
+        vm.fraction_cls.clone()
+    }
+
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        let mut bytes = self.num.to_signed_bytes_le();
+        bytes.extend(self.den.to_signed_bytes_le());
+        hash_bytes(vm, &bytes)
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Val::from_obj(
+            vm,
+            Fraction {
+                num: self.num.clone(),
+                den: self.den.clone(),
+            },
+        ))
+    }
+
+    fn to_doubleval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.to_f64() {
+            Some(d) => Ok(Double::new(vm, d)),
+            None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
+        }
+    }
+
+    fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(String_::new(vm, format!("{}/{}", self.num, self.den), true))
+    }
+
+    fn add(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        self.arith_val(vm, FracOp::Add, other)
+    }
+
+    fn div(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        self.arith_val(vm, FracOp::Div, other)
+    }
+
+    fn mul(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        self.arith_val(vm, FracOp::Mul, other)
+    }
+
+    fn sub(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        self.arith_val(vm, FracOp::Sub, other)
+    }
+
+    fn equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        // Since fractions are normalized, a fraction can never be equal to an integer.
+        let b = match other.try_downcast::<Fraction>(vm) {
+            Some(rhs) => self.num == rhs.num && self.den == rhs.den,
+            None => false,
+        };
+        Ok(Val::from_bool(vm, b))
+    }
+
+    fn not_equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        let b = match other.try_downcast::<Fraction>(vm) {
+            Some(rhs) => self.num != rhs.num || self.den != rhs.den,
+            None => true,
+        };
+        Ok(Val::from_bool(vm, b))
+    }
+}
+
+impl NotUnboxable for Fraction {}
+
+impl StaticObjType for Fraction {
+    fn static_objtype() -> ObjType {
+        ObjType::Fraction
+    }
+}
+
+impl Drop for Fraction {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
+impl Fraction {
+    /// Create a `Val` representing `num / den`. If that is a whole number, the result is an
+    /// integer rather than a fraction.
+    pub fn new(vm: &mut VM, num: BigInt, den: BigInt) -> Result<Val, Box<VMError>> {
+        if den.is_zero() {
+            return Err(VMError::new(vm, VMErrorKind::DivisionByZero));
+        }
+        let gcd = num.gcd(&den);
+        let (mut num, mut den) = (num / &gcd, den / gcd);
+        if den.is_negative() {
+            num = -num;
+            den = -den;
+        }
+        if den.is_one() {
+            ArbInt::new(vm, num)
+        } else {
+            Ok(Val::from_obj(vm, Fraction { num, den }))
+        }
+    }
+
+    /// Return this fraction's numerator and denominator.
+    pub fn parts(&self) -> (BigInt, BigInt) {
+        (self.num.clone(), self.den.clone())
+    }
+
+    /// Convert this fraction to the nearest `f64`, or `None` if it can't be represented as one.
+    pub fn to_f64(&self) -> Option<f64> {
+        Some(self.num.to_f64()? / self.den.to_f64()?)
+    }
+
+    /// Perform `lhs op rhs`, where `lhs` and `rhs` are (numerator, denominator) pairs.
+    pub fn arith(
+        vm: &mut VM,
+        op: FracOp,
+        (ln, ld): (BigInt, BigInt),
+        (rn, rd): (BigInt, BigInt),
+    ) -> Result<Val, Box<VMError>> {
+        match op {
+            FracOp::Add => Fraction::new(vm, ln * &rd + rn * &ld, ld * rd),
+            FracOp::Div => Fraction::new(vm, ln * rd, ld * rn),
+            FracOp::Mul => Fraction::new(vm, ln * rn, ld * rd),
+            FracOp::Sub => Fraction::new(vm, ln * &rd - rn * &ld, ld * rd),
+        }
+    }
+
+    /// Perform `self op other`, where `other` can be any kind of number.
+    fn arith_val(&self, vm: &mut VM, op: FracOp, other: Val) -> Result<Val, Box<VMError>> {
+        let rhs = if let Some(i) = other.as_isize(vm) {
+            (BigInt::from(i), BigInt::one())
+        } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+            (rhs.bigint().clone(), BigInt::one())
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            rhs.parts()
+        } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
+            let lhs = match self.to_f64() {
+                Some(d) => d,
+                None => return Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
+            };
+            let rhs = rhs.double();
+            let d = match op {
+                FracOp::Add => lhs + rhs,
+                FracOp::Div => {
+                    if rhs == 0f64 {
+                        return Err(VMError::new(vm, VMErrorKind::DivisionByZero));
+                    }
+                    lhs / rhs
+                }
+                FracOp::Mul => lhs * rhs,
+                FracOp::Sub => lhs - rhs,
+            };
+            return Ok(Double::new(vm, d));
+        } else {
+            let got = other.dyn_objtype(vm);
+            return Err(VMError::new(vm, VMErrorKind::NotANumber { got }));
+        };
+        Fraction::arith(vm, op, self.parts(), rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let mut vm = VM::new_no_bootstrap();
+        let v = Fraction::new(&mut vm, BigInt::from(4), BigInt::from(-6)).unwrap();
+        assert_eq!(
+            v.downcast::<Fraction>(&vm).unwrap().parts(),
+            (BigInt::from(-2), BigInt::from(3))
+        );
+        let v = Fraction::new(&mut vm, BigInt::from(6), BigInt::from(3)).unwrap();
+        assert_eq!(v.as_isize(&mut vm), Some(2));
+        assert!(Fraction::new(&mut vm, BigInt::from(1), BigInt::zero()).is_err());
+    }
+
+    #[test]
+    fn test_add() {
+        let mut vm = VM::new_no_bootstrap();
+        let third = (BigInt::from(1), BigInt::from(3));
+        let sixth = (BigInt::from(1), BigInt::from(6));
+        let v = Fraction::arith(&mut vm, FracOp::Add, third, sixth).unwrap();
+        assert_eq!(
+            v.downcast::<Fraction>(&vm).unwrap().parts(),
+            (BigInt::from(1), BigInt::from(2))
+        );
+    }
+}
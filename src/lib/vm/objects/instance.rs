@@ -6,6 +6,8 @@ use abgc_derive::GcLayout;
 
 use crate::vm::{
     core::VM,
+    error::{VMError, VMErrorKind},
+    gc,
     objects::{Class, Obj, ObjType, StaticObjType},
     val::{NotUnboxable, Val},
 };
@@ -22,6 +24,13 @@ impl Obj for Inst {
         ObjType::Inst
     }
 
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        visitor(&self.class);
+        for v in unsafe { &*self.inst_vars.get() } {
+            visitor(v);
+        }
+    }
+
     fn get_class(&self, _: &mut VM) -> Val {
         self.class.clone()
     }
@@ -35,6 +44,15 @@ impl Obj for Inst {
         let inst_vars = unsafe { &mut *self.inst_vars.get() };
         inst_vars[n] = v;
     }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        let inst_vars = unsafe { &*self.inst_vars.get() };
+        let inst = Inst {
+            class: self.class.clone(),
+            inst_vars: UnsafeCell::new(inst_vars.clone()),
+        };
+        Ok(Val::from_obj(vm, inst))
+    }
 }
 
 impl NotUnboxable for Inst {}
@@ -45,15 +63,42 @@ impl StaticObjType for Inst {
     }
 }
 
+impl Drop for Inst {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl Inst {
+    /// Create an instance of `class`, with each instance variable set to `nil`.
     pub fn new(vm: &mut VM, class: Val) -> Val {
         let cls: &Class = class.downcast(vm).unwrap();
         let mut inst_vars = Vec::with_capacity(cls.num_inst_vars);
-        inst_vars.resize(cls.num_inst_vars, Val::illegal());
+        inst_vars.resize(cls.num_inst_vars, vm.nil.clone());
         let inst = Inst {
             class,
             inst_vars: UnsafeCell::new(inst_vars),
         };
         Val::from_obj(vm, inst)
     }
+
+    /// Return the value of the instance variable `name`.
+    pub fn get_field(&self, vm: &VM, name: &str) -> Result<Val, Box<VMError>> {
+        let n = self.field_index(vm, name)?;
+        Ok(self.inst_var_lookup(n))
+    }
+
+    /// Set the instance variable `name` to `v`.
+    pub fn set_field(&self, vm: &VM, name: &str, v: Val) -> Result<(), Box<VMError>> {
+        let n = self.field_index(vm, name)?;
+        self.inst_var_set(n, v);
+        Ok(())
+    }
+
+    /// Return the (zero-based) index of the instance variable `name`.
+    fn field_index(&self, vm: &VM, name: &str) -> Result<usize, Box<VMError>> {
+        let cls: &Class = self.class.downcast(vm)?;
+        cls.inst_var_index(name)
+            .ok_or_else(|| VMError::new(vm, VMErrorKind::UnknownField(name.to_owned())))
+    }
 }
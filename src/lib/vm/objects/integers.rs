@@ -15,12 +15,15 @@ use std::convert::TryFrom;
 
 use abgc_derive::GcLayout;
 use num_bigint::BigInt;
-use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use num_integer::Integer;
+use num_traits::{FromPrimitive, One, Signed, ToPrimitive, Zero};
 
 use crate::vm::{
+    config::OverflowMode,
     core::VM,
     error::{VMError, VMErrorKind},
-    objects::{Double, Obj, ObjType, StaticObjType, String_},
+    gc,
+    objects::{hash_bytes, Double, FracOp, Fraction, Obj, ObjType, StaticObjType, String_},
     val::{NotUnboxable, Val},
 };
 
@@ -41,6 +44,30 @@ impl Obj for ArbInt {
         vm.int_cls.clone()
     }
 
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        hash_bytes(vm, &self.val.to_signed_bytes_le())
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Val::from_obj(
+            vm,
+            ArbInt {
+                val: self.val.clone(),
+            },
+        ))
+    }
+
+    fn to_doubleval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.val.to_f64() {
+            Some(i) => Ok(Double::new(vm, i)),
+            None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
+        }
+    }
+
+    fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        ArbInt::new(vm, self.val.clone())
+    }
+
     fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
         Ok(String_::new(vm, self.val.to_string(), true))
     }
@@ -55,6 +82,13 @@ impl Obj for ArbInt {
                 Some(i) => Ok(Double::new(vm, i + rhs.double())),
                 None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Add,
+                (self.val.clone(), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -77,13 +111,17 @@ impl Obj for ArbInt {
         if let Some(rhs) = other.as_isize(vm) {
             if rhs == 0 {
                 Err(VMError::new(vm, VMErrorKind::DivisionByZero))
-            } else {
+            } else if (&self.val % rhs).is_zero() {
                 ArbInt::new(vm, &self.val / rhs)
+            } else {
+                Fraction::new(vm, self.val.clone(), BigInt::from(rhs))
             }
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
-            match self.val.checked_div(&rhs.val) {
-                Some(i) => ArbInt::new(vm, i),
-                None => Err(VMError::new(vm, VMErrorKind::DivisionByZero)),
+            // `rhs` can't be zero, since zero always fits in a smaller integer type.
+            if (&self.val % &rhs.val).is_zero() {
+                ArbInt::new(vm, &self.val / &rhs.val)
+            } else {
+                Fraction::new(vm, self.val.clone(), rhs.val.clone())
             }
         } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
             if rhs.double() == 0f64 {
@@ -94,6 +132,13 @@ impl Obj for ArbInt {
                     None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
                 }
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Div,
+                (self.val.clone(), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -161,12 +206,31 @@ impl Obj for ArbInt {
                 Some(i) => Ok(Double::new(vm, i * rhs.double())),
                 None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Mul,
+                (self.val.clone(), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
         }
     }
 
+    fn or(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        if let Some(rhs) = other.as_isize(vm) {
+            ArbInt::new(vm, &self.val | BigInt::from_isize(rhs).unwrap())
+        } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+            ArbInt::new(vm, &self.val | &rhs.val)
+        } else {
+            let expected = self.dyn_objtype();
+            let got = other.dyn_objtype(vm);
+            Err(VMError::new(vm, VMErrorKind::TypeError { expected, got }))
+        }
+    }
+
     fn shl(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
         if let Some(rhs) = other.as_isize(vm) {
             if rhs < 0 {
@@ -184,6 +248,35 @@ impl Obj for ArbInt {
         }
     }
 
+    fn shr(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        // A shift of `bits()` or more shifts out every bit, leaving only the sign.
+        let all_bits = if self.val < Zero::zero() {
+            BigInt::from(-1)
+        } else {
+            BigInt::zero()
+        };
+        if let Some(rhs) = other.as_isize(vm) {
+            if rhs < 0 {
+                Err(VMError::new(vm, VMErrorKind::NegativeShift))
+            } else if rhs as usize >= self.val.bits() {
+                ArbInt::new(vm, all_bits)
+            } else {
+                // An arithmetic shift rounds negative numbers towards negative infinity, so we
+                // use `div_floor` rather than relying on `BigInt`'s `>>` to do so.
+                ArbInt::new(vm, self.val.div_floor(&(BigInt::one() << rhs as usize)))
+            }
+        } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+            if rhs.val < Zero::zero() {
+                Err(VMError::new(vm, VMErrorKind::NegativeShift))
+            } else {
+                ArbInt::new(vm, all_bits)
+            }
+        } else {
+            let got = other.dyn_objtype(vm);
+            Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
+        }
+    }
+
     fn sqrt(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
         if self.val < Zero::zero() {
             Err(VMError::new(vm, VMErrorKind::DomainError))
@@ -207,6 +300,13 @@ impl Obj for ArbInt {
                 Some(i) => Ok(Double::new(vm, i - rhs.double())),
                 None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Sub,
+                (self.val.clone(), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -240,6 +340,8 @@ impl Obj for ArbInt {
             false
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             self.val == rhs.val
+        } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
+            self.val.to_f64() == Some(rhs.double())
         } else {
             false
         };
@@ -252,6 +354,8 @@ impl Obj for ArbInt {
             true
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             self.val != rhs.val
+        } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
+            self.val.to_f64() != Some(rhs.double())
         } else {
             true
         };
@@ -259,9 +363,8 @@ impl Obj for ArbInt {
     }
 
     fn greater_than(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
-        let b = if other.dyn_objtype(vm) == ObjType::Int {
-            debug_assert!(self.val > BigInt::from_isize(other.as_isize(vm).unwrap()).unwrap());
-            true
+        let b = if let Some(rhs) = other.as_isize(vm) {
+            self.val > BigInt::from_isize(rhs).unwrap()
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             self.val > rhs.val
         } else {
@@ -272,9 +375,8 @@ impl Obj for ArbInt {
     }
 
     fn greater_than_equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
-        let b = if other.dyn_objtype(vm) == ObjType::Int {
-            debug_assert!(self.val >= BigInt::from_isize(other.as_isize(vm).unwrap()).unwrap());
-            true
+        let b = if let Some(rhs) = other.as_isize(vm) {
+            self.val >= BigInt::from_isize(rhs).unwrap()
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             self.val >= rhs.val
         } else {
@@ -285,9 +387,8 @@ impl Obj for ArbInt {
     }
 
     fn less_than(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
-        let b = if other.dyn_objtype(vm) == ObjType::Int {
-            debug_assert!(self.val < BigInt::from_isize(other.as_isize(vm).unwrap()).unwrap());
-            false
+        let b = if let Some(rhs) = other.as_isize(vm) {
+            self.val < BigInt::from_isize(rhs).unwrap()
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             self.val < rhs.val
         } else {
@@ -298,9 +399,8 @@ impl Obj for ArbInt {
     }
 
     fn less_than_equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
-        let b = if other.dyn_objtype(vm) == ObjType::Int {
-            debug_assert!(self.val <= BigInt::from_isize(other.as_isize(vm).unwrap()).unwrap());
-            false
+        let b = if let Some(rhs) = other.as_isize(vm) {
+            self.val <= BigInt::from_isize(rhs).unwrap()
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             self.val <= rhs.val
         } else {
@@ -317,6 +417,12 @@ impl StaticObjType for ArbInt {
     }
 }
 
+impl Drop for ArbInt {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl ArbInt {
     /// Create a `Val` representing the `BigInt` integer `val`. Note that this will create the most
     /// efficient integer representation that can represent `val` (i.e. this might create a tagged
@@ -350,6 +456,23 @@ impl Obj for Int {
         vm.int_cls.clone()
     }
 
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        // This must be consistent with tagged integers, whose hashcode is their value.
+        Val::from_isize(vm, self.val)
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Val::from_isize(vm, self.val)
+    }
+
+    fn to_doubleval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(Double::new(vm, self.val as f64))
+    }
+
+    fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Val::from_isize(vm, self.val)
+    }
+
     fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
         Ok(String_::new(vm, self.val.to_string(), true))
     }
@@ -358,7 +481,9 @@ impl Obj for Int {
         if let Some(rhs) = other.as_isize(vm) {
             match self.val.checked_add(rhs) {
                 Some(i) => Val::from_isize(vm, i),
-                None => ArbInt::new(vm, BigInt::from_isize(self.val).unwrap() + rhs),
+                None => overflowed(vm, self.val.wrapping_add(rhs), || {
+                    BigInt::from_isize(self.val).unwrap() + rhs
+                }),
             }
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             ArbInt::new(vm, self.val + &rhs.val)
@@ -367,6 +492,13 @@ impl Obj for Int {
                 Some(i) => Ok(Double::new(vm, i + rhs.double())),
                 None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Add,
+                (BigInt::from(self.val), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -377,14 +509,20 @@ impl Obj for Int {
         if let Some(rhs) = other.as_isize(vm) {
             if rhs == 0 {
                 Err(VMError::new(vm, VMErrorKind::DivisionByZero))
+            } else if self.val.wrapping_rem(rhs) != 0 {
+                Fraction::new(vm, BigInt::from(self.val), BigInt::from(rhs))
             } else {
-                Val::from_isize(vm, self.val / rhs)
+                match self.val.checked_div(rhs) {
+                    Some(i) => Val::from_isize(vm, i),
+                    None => overflowed(vm, self.val.wrapping_div(rhs), || {
+                        BigInt::from_isize(self.val).unwrap() / rhs
+                    }),
+                }
             }
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
-            match BigInt::from_isize(self.val).unwrap().checked_div(&rhs.val) {
-                Some(i) => ArbInt::new(vm, i),
-                None => Err(VMError::new(vm, VMErrorKind::DivisionByZero)),
-            }
+            // `rhs` can't be zero, and since it's bigger in magnitude than any `isize`, the
+            // division can only be exact if `self` is zero.
+            Fraction::new(vm, BigInt::from(self.val), rhs.val.clone())
         } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
             if rhs.double() == 0f64 {
                 Err(VMError::new(vm, VMErrorKind::DivisionByZero))
@@ -397,6 +535,13 @@ impl Obj for Int {
                     None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
                 }
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Div,
+                (BigInt::from(self.val), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -407,7 +552,9 @@ impl Obj for Int {
         if let Some(rhs) = other.as_isize(vm) {
             match self.val.checked_mul(rhs) {
                 Some(i) => Val::from_isize(vm, i),
-                None => ArbInt::new(vm, BigInt::from_isize(self.val).unwrap() * rhs),
+                None => overflowed(vm, self.val.wrapping_mul(rhs), || {
+                    BigInt::from_isize(self.val).unwrap() * rhs
+                }),
             }
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             ArbInt::new(vm, self.val * &rhs.val)
@@ -416,6 +563,13 @@ impl Obj for Int {
                 Some(i) => Ok(Double::new(vm, i * rhs.double())),
                 None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Mul,
+                (BigInt::from(self.val), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -426,7 +580,9 @@ impl Obj for Int {
         if let Some(rhs) = other.as_isize(vm) {
             match self.val.checked_sub(rhs) {
                 Some(i) => Val::from_isize(vm, i),
-                None => ArbInt::new(vm, BigInt::from_isize(self.val).unwrap() - rhs),
+                None => overflowed(vm, self.val.wrapping_sub(rhs), || {
+                    BigInt::from_isize(self.val).unwrap() - rhs
+                }),
             }
         } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
             ArbInt::new(vm, self.val - &rhs.val)
@@ -435,6 +591,13 @@ impl Obj for Int {
                 Some(i) => Ok(Double::new(vm, i - rhs.double())),
                 None => Err(VMError::new(vm, VMErrorKind::CantRepresentAsDouble)),
             }
+        } else if let Some(rhs) = other.try_downcast::<Fraction>(vm) {
+            Fraction::arith(
+                vm,
+                FracOp::Sub,
+                (BigInt::from(self.val), BigInt::one()),
+                rhs.parts(),
+            )
         } else {
             let got = other.dyn_objtype(vm);
             Err(VMError::new(vm, VMErrorKind::NotANumber { got }))
@@ -448,6 +611,12 @@ impl StaticObjType for Int {
     }
 }
 
+impl Drop for Int {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl Int {
     /// Create a `Val` representing the `usize` integer `i`. The `Val` is guaranteed to be boxed
     /// internally.
@@ -468,12 +637,166 @@ impl Int {
     }
 }
 
+/// Return the greatest common divisor (if `lcm` is `false`) or least common multiple (if `lcm` is
+/// `true`) of the integers `lhs` and `rhs`. The result is never negative: the GCD of `0` and `x` is
+/// `x`'s absolute value, and the LCM of `0` and anything is `0`.
+pub fn gcd_lcm(vm: &mut VM, lhs: Val, rhs: Val, lcm: bool) -> Result<Val, Box<VMError>> {
+    if let (Some(a), Some(b)) = (lhs.as_isize(vm), rhs.as_isize(vm)) {
+        // Neither the absolute values of `isize`s nor their LCM can overflow a `u128`.
+        let (a, b) = ((a as i128).abs() as u128, (b as i128).abs() as u128);
+        let g = euclid(a, b);
+        let r = if !lcm || g == 0 { g } else { a / g * b };
+        return match isize::try_from(r) {
+            Ok(i) => Val::from_isize(vm, i),
+            Err(_) => overflowed(vm, r as isize, || BigInt::from(r)),
+        };
+    }
+    let a = int_to_bigint(vm, &lhs)?.abs();
+    let b = int_to_bigint(vm, &rhs)?.abs();
+    let g = euclid(a.clone(), b.clone());
+    let r = if !lcm || g.is_zero() { g } else { a / &g * b };
+    ArbInt::new(vm, r)
+}
+
+/// Return the integer `v` as a string in base `radix`, using upper case letters for digits above
+/// 9. Negative numbers keep their leading `-`.
+pub fn to_radix_string(vm: &mut VM, v: Val, radix: Val) -> Result<Val, Box<VMError>> {
+    let radix = radix_arg(vm, &radix)?;
+    let s = int_to_bigint(vm, &v)?.to_str_radix(radix).to_uppercase();
+    Ok(String_::new(vm, s, true))
+}
+
+/// Check that `radix` is an integer from 2 to 36 (inclusive), raising a `DomainError` if it is
+/// not.
+pub fn radix_arg(vm: &mut VM, radix: &Val) -> Result<u32, Box<VMError>> {
+    match int_to_bigint(vm, radix)?.to_u32() {
+        Some(r) if (2..=36).contains(&r) => Ok(r),
+        _ => Err(VMError::new(vm, VMErrorKind::DomainError)),
+    }
+}
+
+/// Euclid's algorithm for the greatest common divisor of the non-negative integers `a` and `b`.
+fn euclid<T: Integer>(mut a: T, mut b: T) -> T {
+    while !b.is_zero() {
+        let r = a.mod_floor(&b);
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Convert the integer `v`, however it is represented, to a `BigInt`.
+fn int_to_bigint(vm: &mut VM, v: &Val) -> Result<BigInt, Box<VMError>> {
+    if let Some(i) = v.as_isize(vm) {
+        Ok(BigInt::from(i))
+    } else if let Some(i) = v.try_downcast::<ArbInt>(vm) {
+        Ok(i.val.clone())
+    } else {
+        let got = v.dyn_objtype(vm);
+        Err(VMError::new(
+            vm,
+            VMErrorKind::TypeError {
+                expected: Int::static_objtype(),
+                got,
+            },
+        ))
+    }
+}
+
+/// Deal with an `isize` operation which has overflowed according to the VM's
+/// [`OverflowMode`](OverflowMode): `wrapped` is the result of the wrapping version of the
+/// operation and `promote` calculates the mathematically correct result.
+fn overflowed<F>(vm: &mut VM, wrapped: isize, promote: F) -> Result<Val, Box<VMError>>
+where
+    F: FnOnce() -> BigInt,
+{
+    match vm.overflow_mode() {
+        OverflowMode::Error => Err(VMError::new(vm, VMErrorKind::IntegerOverflow)),
+        OverflowMode::Promote => ArbInt::new(vm, promote()),
+        OverflowMode::Wrap => Val::from_isize(vm, wrapped),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::vm::val::{ValKind, BITSIZE, TAG_BITSIZE};
     use std::str::FromStr;
 
+    #[test]
+    fn test_overflow_mode() {
+        let mut vm = VM::new_no_bootstrap();
+        let max = Val::from_isize(&mut vm, isize::max_value()).unwrap();
+        let min = Val::from_isize(&mut vm, isize::min_value()).unwrap();
+        let one = Val::from_isize(&mut vm, 1).unwrap();
+        let minus_one = Val::from_isize(&mut vm, -1).unwrap();
+
+        assert_eq!(vm.overflow_mode(), OverflowMode::Promote);
+        let v = max.add(&mut vm, one.clone()).unwrap();
+        assert_eq!(
+            v.downcast::<ArbInt>(&vm).unwrap().bigint(),
+            &(BigInt::from_isize(isize::max_value()).unwrap() + 1)
+        );
+        let v = min.div(&mut vm, minus_one.clone()).unwrap();
+        assert_eq!(
+            v.downcast::<ArbInt>(&vm).unwrap().bigint(),
+            &-BigInt::from_isize(isize::min_value()).unwrap()
+        );
+
+        vm.set_overflow_mode(OverflowMode::Wrap);
+        let v = max.add(&mut vm, one.clone()).unwrap();
+        assert_eq!(v.as_isize(&mut vm).unwrap(), isize::min_value());
+        let v = min.sub(&mut vm, one.clone()).unwrap();
+        assert_eq!(v.as_isize(&mut vm).unwrap(), isize::max_value());
+        let v = min.div(&mut vm, minus_one.clone()).unwrap();
+        assert_eq!(v.as_isize(&mut vm).unwrap(), isize::min_value());
+
+        vm.set_overflow_mode(OverflowMode::Error);
+        assert_eq!(
+            max.add(&mut vm, one.clone()).unwrap_err().kind,
+            VMErrorKind::IntegerOverflow
+        );
+        assert_eq!(
+            max.mul(&mut vm, max.clone()).unwrap_err().kind,
+            VMErrorKind::IntegerOverflow
+        );
+        // Operations which don't overflow are unaffected.
+        let v = max.sub(&mut vm, one).unwrap();
+        assert_eq!(v.as_isize(&mut vm).unwrap(), isize::max_value() - 1);
+    }
+
+    #[test]
+    fn test_gcd_lcm() {
+        let mut vm = VM::new_no_bootstrap();
+        let int = |vm: &mut VM, i| Val::from_isize(vm, i).unwrap();
+        let cases = [
+            (12, 18, 6, 36),
+            (4, 6, 2, 12),
+            (-4, 6, 2, 12),
+            (0, -7, 7, 0),
+            (0, 0, 0, 0),
+        ];
+        for &(a, b, gcd, lcm) in &cases {
+            let (x, y) = (int(&mut vm, a), int(&mut vm, b));
+            let v = gcd_lcm(&mut vm, x.clone(), y.clone(), false).unwrap();
+            assert_eq!(v.as_isize(&mut vm), Some(gcd));
+            let v = gcd_lcm(&mut vm, x, y, true).unwrap();
+            assert_eq!(v.as_isize(&mut vm), Some(lcm));
+        }
+
+        // |isize::min_value()| doesn't fit in an isize.
+        let (x, y) = (int(&mut vm, isize::min_value()), int(&mut vm, 0));
+        let v = gcd_lcm(&mut vm, x, y, false).unwrap();
+        assert_eq!(
+            v.downcast::<ArbInt>(&vm).unwrap().val,
+            -BigInt::from(isize::min_value())
+        );
+        let x = ArbInt::new(&mut vm, BigInt::from(isize::max_value()) * 6).unwrap();
+        let y = int(&mut vm, 4);
+        let v = gcd_lcm(&mut vm, x, y, false).unwrap();
+        assert_eq!(v.as_isize(&mut vm), Some(2));
+    }
+
     #[test]
     fn test_boxed_int() {
         let mut vm = VM::new_no_bootstrap();
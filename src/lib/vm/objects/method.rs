@@ -5,7 +5,7 @@ use std::cell::UnsafeCell;
 use abgc_derive::GcLayout;
 
 use crate::{
-    compiler::instrs::Primitive,
+    compiler::instrs::{Instr, Primitive},
     vm::{
         core::VM,
         objects::{Obj, ObjType, StaticObjType},
@@ -26,11 +26,19 @@ pub enum MethodBody {
     Primitive(Primitive),
     /// User bytecode.
     User {
+        /// How many parameters does this method take? This is determined by the method's
+        /// selector.
+        num_params: usize,
         /// How many variables does this method define?
         num_vars: usize,
         /// The offset of this method's bytecode in its parent class.
         bytecode_off: usize,
         max_stack: usize,
+        /// If this method takes no parameters and does nothing but return the value pushed by a
+        /// single instruction which only depends on the receiver (e.g. `^instVar` or `^1`), that
+        /// instruction. Sends of this method can execute the instruction directly rather than
+        /// creating a frame.
+        inline: Option<Instr>,
     },
 }
 
@@ -39,6 +47,10 @@ impl Obj for Method {
         ObjType::Method
     }
 
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        visitor(&self.class());
+    }
+
     fn get_class(&self, _: &mut VM) -> Val {
         unimplemented!();
     }
@@ -20,55 +20,104 @@
 //! Although this constraint is not enforced through the type system, it is not hard to obey: as
 //! soon as you create an `Obj` instance, pass it to `Val::from_obj`.
 
+mod array;
 mod block;
+mod character;
 mod class;
+mod dictionary;
 mod double;
+mod fraction;
 mod instance;
 mod integers;
 mod method;
+mod set;
 mod string_;
+mod weakref;
 
+pub use array::Array;
 pub use block::{Block, BlockInfo};
+pub use character::Character;
 pub use class::Class;
-pub use double::Double;
+pub use dictionary::Dictionary;
+pub use double::{Double, RoundMode};
+pub use fraction::{FracOp, Fraction};
 pub use instance::Inst;
-pub use integers::{ArbInt, Int};
+pub use integers::{gcd_lcm, radix_arg, to_radix_string, ArbInt, Int};
 pub use method::{Method, MethodBody};
+pub use set::Set;
 pub use string_::String_;
+pub use weakref::WeakRef;
 
 use abgc::{self, Gc};
 use natrob::narrowable_abgc;
 
-use crate::vm::{core::VM, error::VMError, val::Val};
+use crate::vm::{
+    core::VM,
+    error::{VMError, VMErrorKind},
+    gc,
+    val::{Val, TAG_BITSIZE},
+};
 
 /// The SOM type of objects.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum ObjType {
     ArbInt,
+    Array,
     Block,
+    Character,
     Class,
+    /// An object type defined outside yksom, whose id was obtained from
+    /// [`VM::register_objtype`](crate::vm::VM::register_objtype).
+    Custom(u32),
+    Dictionary,
     Double,
+    Fraction,
     Method,
     Inst,
     Int,
+    Set,
     String_,
+    WeakRef,
 }
 
 impl ObjType {
+    /// The name of this type. Custom types' names are held by the VM that registered them, so see
+    /// [`VM::objtype_name`](crate::vm::VM::objtype_name) for a more helpful alternative.
     pub fn as_str(&self) -> &'static str {
         match *self {
             ObjType::ArbInt => "ArbInt",
+            ObjType::Array => "Array",
             ObjType::Block => "Block",
+            ObjType::Character => "Character",
             ObjType::Class => "Class",
+            ObjType::Custom(_) => "Custom",
+            ObjType::Dictionary => "Dictionary",
             ObjType::Double => "Double",
+            ObjType::Fraction => "Fraction",
             ObjType::Method => "Method",
             ObjType::Inst => "Inst",
             ObjType::Int => "Int",
+            ObjType::Set => "Set",
             ObjType::String_ => "String_",
+            ObjType::WeakRef => "WeakRef",
         }
     }
 }
 
+/// Hash `bytes` with FNV-1a. The result is shifted right so that it fits in a (non-negative) tagged
+/// integer.
+pub(crate) fn hash_bytes(vm: &mut VM, bytes: &[u8]) -> Result<Val, Box<VMError>> {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x100_0000_01b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    Val::from_isize(vm, (hash >> (TAG_BITSIZE + 1)) as isize)
+}
+
 /// The main SOM Object trait. Notice that code should almost never call these functions directly:
 /// you should instead call the equivalent function in the `Val` struct.
 #[narrowable_abgc(ThinObj)]
@@ -78,6 +127,39 @@ pub trait Obj: std::fmt::Debug + abgc::GcLayout {
     /// What class is this object an instance of?
     fn get_class(&self, vm: &mut VM) -> Val;
 
+    /// Call `visitor` on each `Val` this object directly references. Objects which reference no
+    /// other `Val`s (e.g. strings and integers) need not override this.
+    fn trace(&self, _visitor: &mut dyn FnMut(&Val)) {}
+
+    /// Return a `Val` representing this object's hashcode. Objects which are equal must have equal
+    /// hashcodes. By default, objects are only equal to themselves, so the hashcode is the
+    /// object's identity (see [`Val::identity_hash`]).
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Val::from_usize(vm, gc::identity(self as *const Self as *const u8 as usize))
+    }
+
+    /// Convert this object to a `Val` that represents a SOM double. Objects which aren't numbers
+    /// need not override this: by default a `NotANumber` error is returned.
+    fn to_doubleval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
+    }
+
+    /// Convert this object to a `Val` that represents a SOM integer. Objects which aren't numbers
+    /// need not override this: by default a `NotANumber` error is returned.
+    fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
+    }
+
     /// Convert this object to a `Val` that represents a SOM string.
     fn to_strval(&self, _: &mut VM) -> Result<Val, Box<VMError>> {
         unimplemented!();
@@ -93,53 +175,138 @@ pub trait Obj: std::fmt::Debug + abgc::GcLayout {
         todo!();
     }
 
+    /// Produce a new object of the same type as this, whose contents are a shallow copy of this
+    /// object's contents. Objects which can't be copied need not override this: by default a
+    /// `CantCopy` error is returned.
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::CantCopy {
+                got: self.dyn_objtype(),
+            },
+        ))
+    }
+
+    // The arithmetic, bitwise, and ordering (but not equality) operations below only make sense
+    // for numbers, so by default they return a `NotANumber` error.
+
     /// Produce a new `Val` which adds `other` to this.
-    fn add(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn add(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which performs a bitwise and with `other` and this.
-    fn and(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn and(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which divides `other` from this.
-    fn div(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn div(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
-    fn double_div(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn double_div(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which performs a mod operation on this with `other`.
-    fn modulus(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn modulus(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which multiplies `other` to this.
-    fn mul(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn mul(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
+    }
+
+    /// Produce a new `Val` which performs a bitwise or with `other` and this.
+    fn or(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which shifts `self` `other` bits to the left.
-    fn shl(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn shl(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
+    }
+
+    /// Produce a new `Val` which arithmetically shifts `self` `other` bits to the right.
+    fn shr(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produces a new `Val` which is the square root of this.
-    fn sqrt(&self, _: &mut VM) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn sqrt(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which subtracts `other` from this.
-    fn sub(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn sub(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Produce a new `Val` which performs a bitwise xor with `other` and this
-    fn xor(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn xor(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Is this `Val` reference equality equal to `other`? Only number types are likely to want to
@@ -165,23 +332,43 @@ pub trait Obj: std::fmt::Debug + abgc::GcLayout {
     }
 
     /// Is this `Val` greater than `other`?
-    fn greater_than(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn greater_than(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Is this `Val` greater than or equal to `other`?
-    fn greater_than_equals(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn greater_than_equals(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Is this `Val` less than `other`?
-    fn less_than(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn less_than(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 
     /// Is this `Val` less than or equal to `other`?
-    fn less_than_equals(&self, _: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
-        unimplemented!();
+    fn less_than_equals(&self, vm: &mut VM, _: Val) -> Result<Val, Box<VMError>> {
+        Err(VMError::new(
+            vm,
+            VMErrorKind::NotANumber {
+                got: self.dyn_objtype(),
+            },
+        ))
     }
 }
 
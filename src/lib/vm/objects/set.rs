@@ -0,0 +1,120 @@
+#![allow(clippy::new_ret_no_self)]
+
+use std::{cell::UnsafeCell, collections::HashMap};
+
+use abgc_derive::GcLayout;
+
+use crate::vm::{
+    core::VM,
+    error::VMError,
+    gc,
+    objects::{Obj, ObjType, StaticObjType},
+    val::{NotUnboxable, Val},
+};
+
+/// A SOM `Set`. As with [`Dictionary`](crate::vm::objects::Dictionary), elements are grouped into
+/// buckets by their SOM `hashcode` and the VM is responsible for finding the stored element equal
+/// to a given value (see `VM::set_find`), which is then identified by its identity.
+#[derive(Debug, GcLayout)]
+pub struct Set {
+    buckets: UnsafeCell<HashMap<isize, Vec<Val>>>,
+}
+
+impl Obj for Set {
+    fn dyn_objtype(&self) -> ObjType {
+        ObjType::Set
+    }
+
+    fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        for v in unsafe { &*self.buckets.get() }.values().flatten() {
+            visitor(v);
+        }
+    }
+
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        let buckets = unsafe { &*self.buckets.get() };
+        let copy = Set {
+            buckets: UnsafeCell::new(buckets.clone()),
+        };
+        Ok(Val::from_obj(vm, copy))
+    }
+
+    fn get_class(&self, vm: &mut VM) -> Val {
+        vm.set_cls.clone()
+    }
+}
+
+impl NotUnboxable for Set {}
+
+impl StaticObjType for Set {
+    fn static_objtype() -> ObjType {
+        ObjType::Set
+    }
+}
+
+impl Drop for Set {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
+impl Set {
+    pub fn new(vm: &mut VM) -> Val {
+        Val::from_obj(
+            vm,
+            Set {
+                buckets: UnsafeCell::new(HashMap::new()),
+            },
+        )
+    }
+
+    /// Return the elements whose hashcode is `hash`.
+    pub fn bucket(&self, hash: isize) -> Vec<Val> {
+        let buckets = unsafe { &*self.buckets.get() };
+        match buckets.get(&hash) {
+            Some(b) => b.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Return all of this set's elements (in no particular order).
+    pub fn elements(&self) -> Vec<Val> {
+        let buckets = unsafe { &*self.buckets.get() };
+        buckets.values().flatten().cloned().collect()
+    }
+
+    /// Add `v` (whose hashcode is `hash`) to this set unless `v` itself (i.e. something bit
+    /// equal to it) is already present. The caller must already have checked that no element equal
+    /// to `v` is present.
+    pub fn add(&self, hash: isize, v: Val) {
+        let buckets = unsafe { &mut *self.buckets.get() };
+        let bucket = buckets.entry(hash).or_insert_with(Vec::new);
+        if !bucket.iter().any(|e| e.bit_eq(&v)) {
+            bucket.push(v);
+        }
+    }
+
+    /// Remove the element in bucket `hash` which is (bit equal to) `v`, returning it, or `None` if
+    /// there is no such element.
+    pub fn remove(&self, hash: isize, v: &Val) -> Option<Val> {
+        let buckets = unsafe { &mut *self.buckets.get() };
+        let bucket = buckets.get_mut(&hash)?;
+        let i = bucket.iter().position(|e| e.bit_eq(v))?;
+        let e = bucket.remove(i);
+        if bucket.is_empty() {
+            buckets.remove(&hash);
+        }
+        Some(e)
+    }
+
+    /// How many elements does this set contain?
+    pub fn len(&self) -> usize {
+        let buckets = unsafe { &*self.buckets.get() };
+        buckets.values().map(|b| b.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let buckets = unsafe { &*self.buckets.get() };
+        buckets.is_empty()
+    }
+}
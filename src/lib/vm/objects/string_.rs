@@ -3,11 +3,13 @@
 use std::str;
 
 use abgc_derive::GcLayout;
+use num_bigint::BigInt;
 
 use crate::vm::{
     core::VM,
-    error::VMError,
-    objects::{Obj, ObjType, StaticObjType},
+    error::{VMError, VMErrorKind},
+    gc,
+    objects::{hash_bytes, radix_arg, ArbInt, Array, Character, Int, Obj, ObjType, StaticObjType},
     val::{NotUnboxable, Val},
 };
 
@@ -31,17 +33,35 @@ impl Obj for String_ {
         }
     }
 
+    fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        hash_bytes(vm, self.s.as_bytes())
+    }
+
+    /// Parse the integer at the beginning of this string (ignoring leading whitespace), returning
+    /// `nil` if there isn't one.
+    fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        self.parse_int(vm, 10)
+    }
+
     fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
         Ok(String_::new(vm, self.s.to_string(), true))
     }
 
-    fn ref_equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
-        let other_str: &String_ = other.downcast(vm)?;
+    fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        Ok(String_::new(vm, self.s.clone(), self.is_str))
+    }
 
-        Ok(Val::from_bool(
-            vm,
-            (self.is_str == other_str.is_str) && (self.s == other_str.s),
-        ))
+    /// Strings are compared by value, so `=` is the same as `==`.
+    fn equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        self.ref_equals(vm, other)
+    }
+
+    fn ref_equals(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        let b = match other.try_downcast::<String_>(vm) {
+            Some(other_str) => (self.is_str == other_str.is_str) && (self.s == other_str.s),
+            None => false,
+        };
+        Ok(Val::from_bool(vm, b))
     }
 }
 
@@ -53,6 +73,12 @@ impl StaticObjType for String_ {
     }
 }
 
+impl Drop for String_ {
+    fn drop(&mut self) {
+        gc::track_free(self);
+    }
+}
+
 impl String_ {
     pub fn new(vm: &mut VM, s: String, is_str: bool) -> Val {
         Val::from_obj(vm, String_ { s, is_str })
@@ -62,6 +88,32 @@ impl String_ {
         &self.s
     }
 
+    /// Return the `Character` at (one-based) index `idx` of this string.
+    pub fn at(&self, vm: &mut VM, idx: Val) -> Result<Val, Box<VMError>> {
+        let i = int_arg(vm, &idx)?;
+        let c = if i > 0 {
+            self.s.chars().nth((i - 1) as usize)
+        } else {
+            None
+        };
+        match c {
+            Some(c) => Ok(Character::new(vm, c)),
+            None => Err(VMError::new(
+                vm,
+                VMErrorKind::IndexOutOfBounds {
+                    idx: i,
+                    len: self.s.chars().count(),
+                },
+            )),
+        }
+    }
+
+    /// Return an `Array` of this string's `Character`s.
+    pub fn to_array(&self, vm: &mut VM) -> Val {
+        let chars = self.s.chars().map(|c| Character::new(vm, c)).collect();
+        Array::from_vec(vm, chars)
+    }
+
     /// Concatenate this string with another string and return the result.
     pub fn concatenate(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
         let other_str: &String_ = other.downcast(vm)?;
@@ -80,7 +132,223 @@ impl String_ {
         Ok(String_::new(vm, new, true))
     }
 
+    /// Return the (one-based) index of the first occurrence of the `Character` `c` in this
+    /// string, or 0 if `c` does not occur.
+    pub fn index_of(&self, vm: &mut VM, c: Val) -> Result<usize, Box<VMError>> {
+        let c = c.downcast::<Character>(vm)?.as_char();
+        Ok(match self.s.chars().position(|x| x == c) {
+            Some(i) => i + 1,
+            None => 0,
+        })
+    }
+
+    /// Return a new string containing the characters from (one-based) index `start` to `end`
+    /// inclusive. If `start == end + 1`, the empty string is returned.
+    pub fn substring(&self, vm: &mut VM, start: Val, end: Val) -> Result<Val, Box<VMError>> {
+        let start = int_arg(vm, &start)?;
+        let end = int_arg(vm, &end)?;
+        let len = self.s.chars().count();
+        if start < 1 || start > end + 1 {
+            return Err(VMError::new(
+                vm,
+                VMErrorKind::IndexOutOfBounds { idx: start, len },
+            ));
+        }
+        if end > len as isize {
+            return Err(VMError::new(
+                vm,
+                VMErrorKind::IndexOutOfBounds { idx: end, len },
+            ));
+        }
+        let s = self
+            .s
+            .chars()
+            .skip(start as usize - 1)
+            .take((end - start + 1) as usize)
+            .collect();
+        Ok(String_::new(vm, s, true))
+    }
+
+    /// Return a new string with every occurrence of the string `old` replaced by the string
+    /// `new`. If `old` is empty, this string is returned unchanged.
+    pub fn replace_all(&self, vm: &mut VM, old: Val, new: Val) -> Result<Val, Box<VMError>> {
+        let old_str: &String_ = old.downcast(vm)?;
+        let new_str: &String_ = new.downcast(vm)?;
+        if old_str.s.is_empty() {
+            return Ok(Val::recover(self));
+        }
+        let s = self.s.replace(&old_str.s, &new_str.s);
+        Ok(String_::new(vm, s, true))
+    }
+
+    /// Return a new string with each placeholder `%n` replaced by `args[n - 1]`. Placeholders
+    /// with no corresponding argument are left as-is, and unused arguments are ignored.
+    pub fn format(&self, vm: &mut VM, args: &[String]) -> Val {
+        let mut s = String::with_capacity(self.s.len());
+        let mut rest = self.s.as_str();
+        while let Some(i) = rest.find('%') {
+            s.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            match rest[..digits].parse::<usize>() {
+                Ok(n) if n >= 1 && n <= args.len() => s.push_str(&args[n - 1]),
+                _ => {
+                    s.push('%');
+                    s.push_str(&rest[..digits]);
+                }
+            }
+            rest = &rest[digits..];
+        }
+        s.push_str(rest);
+        String_::new(vm, s, true)
+    }
+
+    /// Return an `Array` of the whitespace-separated words in this string.
+    pub fn substrings(&self, vm: &mut VM) -> Val {
+        let words = self
+            .s
+            .split_whitespace()
+            .map(|w| String_::new(vm, w.to_owned(), true))
+            .collect();
+        Array::from_vec(vm, words)
+    }
+
+    /// Return an `Array` of the parts of this string separated by the `Character` `sep`.
+    /// Consecutive separators lead to empty strings in the result, but the empty string has no
+    /// parts at all.
+    pub fn tokenize(&self, vm: &mut VM, sep: Val) -> Result<Val, Box<VMError>> {
+        let sep = sep.downcast::<Character>(vm)?.as_char();
+        let tokens = if self.s.is_empty() {
+            Vec::new()
+        } else {
+            self.s
+                .split(sep)
+                .map(|t| String_::new(vm, t.to_owned(), true))
+                .collect()
+        };
+        Ok(Array::from_vec(vm, tokens))
+    }
+
+    /// Return this string in quotes, escaped as it would be written in SOM source code.
+    pub fn quoted(&self, vm: &mut VM) -> Val {
+        let s = self.s.replace('\\', "\\\\").replace('\'', "\\'");
+        String_::new(vm, format!("'{}'", s), true)
+    }
+
+    pub fn to_lowercase(&self, vm: &mut VM) -> Val {
+        String_::new(vm, self.s.to_lowercase(), true)
+    }
+
+    pub fn to_uppercase(&self, vm: &mut VM) -> Val {
+        String_::new(vm, self.s.to_uppercase(), true)
+    }
+
+    /// Parse the integer in base `radix` at the beginning of this string (ignoring leading
+    /// whitespace), returning `nil` if there isn't one.
+    pub fn to_intval_radix(&self, vm: &mut VM, radix: Val) -> Result<Val, Box<VMError>> {
+        let radix = radix_arg(vm, &radix)?;
+        self.parse_int(vm, radix)
+    }
+
+    fn parse_int(&self, vm: &mut VM, radix: u32) -> Result<Val, Box<VMError>> {
+        let s = self.s.trim_start();
+        let digits_start = if s.starts_with('-') { 1 } else { 0 };
+        let end = s[digits_start..]
+            .find(|c: char| !c.is_digit(radix))
+            .map(|i| i + digits_start)
+            .unwrap_or_else(|| s.len());
+        match BigInt::parse_bytes(s[..end].as_bytes(), radix) {
+            Some(i) => ArbInt::new(vm, i),
+            None => Ok(vm.nil.clone()),
+        }
+    }
+
+    /// Return the interned `Symbol` with this string's characters, so that converting equal
+    /// strings always returns the same object.
     pub fn to_symbol(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
-        Ok(String_::new(vm, self.s.to_string(), false))
+        let id = vm.intern_symbol(&self.s);
+        Ok(vm.symbol_val(id))
+    }
+
+    /// Does this string match the glob pattern `pattern`? In the pattern, `*` matches any sequence
+    /// of characters (including the empty sequence); `#` and `?` match exactly one character; and
+    /// `\` causes the following character to be matched literally (e.g. `\*` matches only `*`).
+    /// All other characters match themselves, ignoring case if `ignore_case` is `true`.
+    pub fn match_pattern(&self, pattern: &str, ignore_case: bool) -> bool {
+        enum Pat {
+            Any,
+            One,
+            Char(char),
+        }
+
+        let mut pat = Vec::new();
+        let mut pat_chars = pattern.chars();
+        while let Some(c) = pat_chars.next() {
+            match c {
+                '*' => pat.push(Pat::Any),
+                '#' | '?' => pat.push(Pat::One),
+                // A trailing backslash has nothing to escape, so it matches itself.
+                '\\' => pat.push(Pat::Char(pat_chars.next().unwrap_or('\\'))),
+                _ => pat.push(Pat::Char(c)),
+            }
+        }
+
+        let char_eq =
+            |x: char, y: char| x == y || (ignore_case && x.to_lowercase().eq(y.to_lowercase()));
+
+        // A standard greedy matcher: when we encounter a `*` we initially match the empty
+        // sequence, but remember where we were so that, if we later fail to match, we can
+        // backtrack and have the `*` consume one more character.
+        let s = self.s.chars().collect::<Vec<_>>();
+        let (mut si, mut pi) = (0, 0);
+        let mut backtrack = None;
+        while si < s.len() {
+            match pat.get(pi) {
+                Some(Pat::Any) => {
+                    backtrack = Some((pi, si));
+                    pi += 1;
+                    continue;
+                }
+                Some(Pat::One) => {
+                    si += 1;
+                    pi += 1;
+                    continue;
+                }
+                Some(Pat::Char(c)) if char_eq(*c, s[si]) => {
+                    si += 1;
+                    pi += 1;
+                    continue;
+                }
+                _ => (),
+            }
+            match backtrack {
+                Some((any_pi, any_si)) => {
+                    backtrack = Some((any_pi, any_si + 1));
+                    pi = any_pi + 1;
+                    si = any_si + 1;
+                }
+                None => return false,
+            }
+        }
+        pat[pi..].iter().all(|p| matches!(p, Pat::Any))
+    }
+}
+
+/// Convert `v` to an `isize`, raising a `TypeError` if it isn't an integer.
+fn int_arg(vm: &mut VM, v: &Val) -> Result<isize, Box<VMError>> {
+    match v.as_isize(vm) {
+        Some(i) => Ok(i),
+        None => {
+            let got = v.dyn_objtype(vm);
+            Err(VMError::new(
+                vm,
+                VMErrorKind::TypeError {
+                    expected: Int::static_objtype(),
+                    got,
+                },
+            ))
+        }
     }
 }
@@ -0,0 +1,125 @@
+#![allow(clippy::new_ret_no_self)]
+
+use std::{cell::Cell, mem::ManuallyDrop, rc::Rc};
+
+use abgc_derive::GcLayout;
+
+use crate::vm::{
+    core::VM,
+    gc,
+    objects::{Obj, ObjType, StaticObjType},
+    val::{NotUnboxable, Val, ValKind},
+};
+
+/// A SOM `WeakRef`: a reference to a value which does not keep that value alive.
+#[derive(Debug, GcLayout)]
+pub struct WeakRef {
+    /// The bits of the referent's `Val`. This does not own a reference count, so it must only be
+    /// turned back into a `Val` while `alive` is `true`.
+    target: usize,
+    /// Set to `false` when the referent is freed. Unboxed referents can never be freed, so this is
+    /// `None` for them.
+    alive: Option<Rc<Cell<bool>>>,
+}
+
+impl Obj for WeakRef {
+    fn dyn_objtype(&self) -> ObjType {
+        ObjType::WeakRef
+    }
+
+    fn get_class(&self, vm: &mut VM) -> Val {
+        vm.weakref_cls.clone()
+    }
+}
+
+impl NotUnboxable for WeakRef {}
+
+impl StaticObjType for WeakRef {
+    fn static_objtype() -> ObjType {
+        ObjType::WeakRef
+    }
+}
+
+impl Drop for WeakRef {
+    fn drop(&mut self) {
+        if let Some(alive) = &self.alive {
+            if alive.get() {
+                gc::unregister_weak(self.target_addr(), alive);
+            }
+        }
+        gc::track_free(self);
+    }
+}
+
+impl WeakRef {
+    /// Create a weak reference to `target`.
+    pub fn new(vm: &mut VM, target: Val) -> Val {
+        let alive = if target.valkind() == ValKind::GCBOX {
+            let alive = Rc::new(Cell::new(true));
+            gc::register_weak(target.obj_addr(), Rc::clone(&alive));
+            Some(alive)
+        } else {
+            None
+        };
+        // We take the referent's bits without its reference count: dropping `target` here
+        // decrements it.
+        let wr = WeakRef {
+            target: target.val,
+            alive,
+        };
+        Val::from_obj(vm, wr)
+    }
+
+    /// Return the referent, or `nil` if it has been freed.
+    pub fn value(&self, vm: &VM) -> Val {
+        match &self.alive {
+            Some(alive) if !alive.get() => vm.nil.clone(),
+            _ => {
+                // Cloning a `Val` increments its reference count, but we must not decrement the
+                // count we don't own when our temporary copy goes out of scope.
+                let v = ManuallyDrop::new(Val { val: self.target });
+                (*v).clone()
+            }
+        }
+    }
+
+    fn target_addr(&self) -> usize {
+        ManuallyDrop::new(Val { val: self.target }).obj_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::objects::String_;
+
+    #[test]
+    fn test_cleared() {
+        let mut vm = VM::new_no_bootstrap();
+        let s = String_::new(&mut vm, "s".to_owned(), true);
+        let w = WeakRef::new(&mut vm, s.clone());
+        let wr = w.downcast::<WeakRef>(&vm).unwrap();
+        assert_eq!(wr.value(&vm), s);
+        drop(s);
+        vm.drop_caches();
+        assert!(wr.value(&vm).is_nil(&vm));
+
+        // Weak references to unboxed values are never cleared.
+        let i = Val::from_isize(&mut vm, 42).unwrap();
+        let w = WeakRef::new(&mut vm, i);
+        let wr = w.downcast::<WeakRef>(&vm).unwrap();
+        assert_eq!(wr.value(&vm).as_isize(&mut vm).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_weakref_dropped_first() {
+        let mut vm = VM::new_no_bootstrap();
+        let s = String_::new(&mut vm, "s".to_owned(), true);
+        let w1 = WeakRef::new(&mut vm, s.clone());
+        let w2 = WeakRef::new(&mut vm, s.clone());
+        drop(w1);
+        assert_eq!(w2.downcast::<WeakRef>(&vm).unwrap().value(&vm), s);
+        drop(s);
+        assert!(w2.downcast::<WeakRef>(&vm).unwrap().value(&vm).is_nil(&vm));
+    }
+}
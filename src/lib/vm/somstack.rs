@@ -1,27 +1,43 @@
 use std::{
-    alloc::{alloc, dealloc, Layout},
+    alloc::{alloc, dealloc, handle_alloc_error, realloc, Layout},
+    cmp::max,
     mem::forget,
     ptr,
 };
 
 use crate::vm::val::Val;
 
+/// The default initial capacity of a [`SOMStack`].
 pub const SOM_STACK_LEN: usize = 4096;
 
-/// A fixed size stack of SOM values. This stack does minimal or no checking on important
-/// operations and users must ensure that they obey the constraints on each function herein, or
-/// undefined behaviour will occur.
+/// A growable stack of SOM values. Space is not reserved automatically: before pushing values,
+/// users must ensure there is sufficient room (using [`SOMStack::reserve`]). More generally, this
+/// stack does minimal or no checking on important operations and users must ensure that they obey
+/// the constraints on each function herein, or undefined behaviour will occur.
 pub struct SOMStack {
     storage: *mut Val,
-    /// How many items are used? Note that the stack has an implicit capacity of [`SOM_STACK_LEN`].
+    /// How many items can be stored in `storage`?
+    capacity: usize,
+    /// How many items are used?
     len: usize,
 }
 
 impl SOMStack {
-    pub fn new() -> SOMStack {
+    /// Create a new stack which can initially store up to `capacity` values. Since allocating
+    /// zero bytes is undefined behaviour, the stack always has room for at least one value.
+    pub fn new(capacity: usize) -> SOMStack {
         #![allow(clippy::cast_ptr_alignment)]
-        let storage = unsafe { alloc(Layout::array::<Val>(SOM_STACK_LEN).unwrap()) as *mut Val };
-        SOMStack { storage, len: 0 }
+        let capacity = max(capacity, 1);
+        let layout = Layout::array::<Val>(capacity).unwrap();
+        let storage = unsafe { alloc(layout) as *mut Val };
+        if storage.is_null() {
+            handle_alloc_error(layout);
+        }
+        SOMStack {
+            storage,
+            capacity,
+            len: 0,
+        }
     }
 
     /// Returns `true` if the stack contains no elements.
@@ -34,9 +50,38 @@ impl SOMStack {
         self.len
     }
 
+    /// Returns the number of elements the stack can store without growing.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     /// Returns the number of elements the stack can store before running out of room.
     pub fn remaining_capacity(&self) -> usize {
-        SOM_STACK_LEN - self.len()
+        self.capacity - self.len()
+    }
+
+    /// Ensure that the stack has room for at least `additional` more elements, growing the
+    /// stack if necessary.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        if self.remaining_capacity() < additional {
+            self.grow(additional);
+        }
+    }
+
+    #[cold]
+    fn grow(&mut self, additional: usize) {
+        #![allow(clippy::cast_ptr_alignment)]
+        let old_layout = Layout::array::<Val>(self.capacity).unwrap();
+        let capacity = max(self.capacity * 2, self.len + additional);
+        let new_layout = Layout::array::<Val>(capacity).unwrap();
+        let storage =
+            unsafe { realloc(self.storage as *mut u8, old_layout, new_layout.size()) as *mut Val };
+        if storage.is_null() {
+            handle_alloc_error(new_layout);
+        }
+        self.storage = storage;
+        self.capacity = capacity;
     }
 
     /// Returns the top-most value of the stack without removing it. If the stack is empty, calling
@@ -49,6 +94,17 @@ impl SOMStack {
         v2
     }
 
+    /// Returns the value at index `i` of the stack (where 0 is the bottom of the stack) without
+    /// removing it. If `i` is not less than the stack's length, calling this function will lead to
+    /// undefined behaviour.
+    pub fn peek_at(&self, i: usize) -> Val {
+        debug_assert!(i < self.len());
+        let v = unsafe { ptr::read(self.storage.add(i)) };
+        let v2 = v.clone();
+        forget(v);
+        v2
+    }
+
     /// Pops the top-most value of the stack and returns it. If the stack is empty, calling
     /// this function will lead to undefined behaviour.
     pub fn pop(&mut self) -> Val {
@@ -68,9 +124,9 @@ impl SOMStack {
         v
     }
 
-    /// Push `v` onto the end of the stack. You must previously have checked (using
-    /// [`SOMStack::remaining_capacity`]) that there is room for this value: if there is not,
-    /// undefined behaviour will occur.
+    /// Push `v` onto the end of the stack. You must previously have ensured (using
+    /// [`SOMStack::reserve`]) that there is room for this value: if there is not, undefined
+    /// behaviour will occur.
     pub fn push(&mut self, v: Val) {
         debug_assert!(self.remaining_capacity() > 0);
         unsafe { ptr::write(self.storage.add(self.len), v) };
@@ -94,7 +150,7 @@ impl Drop for SOMStack {
         unsafe {
             dealloc(
                 self.storage as *mut _,
-                Layout::array::<Val>(SOM_STACK_LEN).unwrap(),
+                Layout::array::<Val>(self.capacity).unwrap(),
             )
         };
     }
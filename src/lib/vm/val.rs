@@ -17,7 +17,8 @@ use num_traits::{FromPrimitive, ToPrimitive, Zero};
 use super::{
     core::VM,
     error::{VMError, VMErrorKind},
-    objects::{ArbInt, Double, Int, Obj, ObjType, StaticObjType, String_, ThinObj},
+    gc,
+    objects::{ArbInt, Class, Double, Int, Obj, ObjType, StaticObjType, String_, ThinObj},
 };
 
 // We use a fairly standard pointer tagging model where the low `TAG_BITSIZE` bits of a machine
@@ -33,7 +34,42 @@ pub const TAG_BITMASK: usize = 0b11;
 #[cfg(target_pointer_width = "64")]
 pub const INT_BITMASK: usize = 0b111;
 
-#[cfg(target_pointer_width = "64")]
+// Gc pointers are at least 4-byte aligned on 32-bit platforms, so the same number of tag bits is
+// available as on 64-bit platforms.
+#[cfg(target_pointer_width = "32")]
+pub const BITSIZE: usize = 32;
+#[cfg(target_pointer_width = "32")]
+pub const TAG_BITSIZE: usize = 2; // Number of bits
+#[cfg(target_pointer_width = "32")]
+pub const TAG_BITMASK: usize = 0b11;
+#[cfg(target_pointer_width = "32")]
+pub const INT_BITMASK: usize = 0b111;
+
+/// The most negative integer which can be stored as a tagged integer. A tagged integer is stored in
+/// the top `BITSIZE - TAG_BITSIZE` bits of a `Val` in two's complement form.
+pub const MIN_TAGGED_INT: isize = -(1 << (BITSIZE - TAG_BITSIZE - 1));
+/// The most positive integer which can be stored as a tagged integer.
+pub const MAX_TAGGED_INT: isize = (1 << (BITSIZE - TAG_BITSIZE - 1)) - 1;
+
+/// Return the bits of a tagged integer `Val` representing `i`, or `None` if `i` is outside the
+/// range `MIN_TAGGED_INT..=MAX_TAGGED_INT`.
+fn encode_int(i: isize) -> Option<usize> {
+    if (MIN_TAGGED_INT..=MAX_TAGGED_INT).contains(&i) {
+        // Shifting left only discards bits which are copies of the sign bit.
+        Some(((i as usize) << TAG_BITSIZE) | (ValKind::INT as usize))
+    } else {
+        None
+    }
+}
+
+/// Return the integer represented by the bits `val` of a tagged integer `Val`.
+fn decode_int(val: usize) -> isize {
+    debug_assert_eq!(val & TAG_BITMASK, ValKind::INT as usize);
+    // An arithmetic shift right pads negative integers' top TAG_BITSIZE bits with 1s.
+    val as isize >> TAG_BITSIZE
+}
+
+#[cfg(any(target_pointer_width = "32", target_pointer_width = "64"))]
 #[derive(Debug, PartialEq, IntoPrimitive, UnsafeFromPrimitive)]
 #[repr(usize)]
 // All of the values here must:
@@ -58,7 +94,7 @@ pub trait NotUnboxable {}
 
 /// The core struct representing values in the language runtime: boxed and unboxed values are
 /// hidden behind this, such that they can be treated in exactly the same way.
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Val {
     // We use this usize for pointer tagging. Needless to say, this is highly dangerous, and needs
     // several parts of the code to cooperate in order to be correct.
@@ -70,14 +106,17 @@ impl Val {
     ///
     /// [In an ideal world, this would be a function on `Obj` itself, but that would mean that
     /// `Obj` couldn't be a trait object. Oh well.]
-    pub fn from_obj<T: Obj + 'static>(_: &mut VM, obj: T) -> Self {
+    pub fn from_obj<T: Obj + 'static>(vm: &mut VM, obj: T) -> Self {
         debug_assert_eq!(size_of::<*const ThinObj>(), size_of::<usize>());
         let ptr = ThinObj::new(obj).into_raw();
-        Val {
+        let v = Val {
             val: unsafe {
                 transmute::<*const ThinObj, usize>(ptr.as_ptr()) | (ValKind::GCBOX as usize)
             },
-        }
+        };
+        gc::track_alloc::<T>(v.obj_addr(), ptr.as_ptr());
+        vm.check_heap_limit();
+        v
     }
 
     /// If this `Val` is a `GCBox` then convert it into `ThinObj`; if this `Val` is not a `GCBox`
@@ -100,6 +139,14 @@ impl Val {
         }
     }
 
+    /// Return the address of the object this `Val` points to, which is the address that the
+    /// object's `Drop` implementation passes to [`gc::track_free`](crate::vm::gc::track_free).
+    /// This `Val` must be boxed.
+    pub(crate) fn obj_addr(&self) -> usize {
+        debug_assert_eq!(self.valkind(), ValKind::GCBOX);
+        unsafe { &**self.val_to_tobj() as *const dyn Obj as *const u8 as usize }
+    }
+
     /// Create a value upon which all operations are invalid. This can be used as a sentinel or
     /// while initialising part of the system.
     pub fn illegal() -> Val {
@@ -126,8 +173,21 @@ impl Val {
         unsafe { ValKind::from_unchecked(self.val & TAG_BITMASK) }
     }
 
+    /// Is this `Val` a tagged (i.e. unboxed) integer? Note that integers which don't fit in a
+    /// tagged `Val` are boxed, so this can be `false` for integers.
+    pub fn is_tagged_int(&self) -> bool {
+        self.valkind() == ValKind::INT
+    }
+
+    /// Is this `Val` a pointer to a boxed object?
+    pub fn is_gcbox(&self) -> bool {
+        self.valkind() == ValKind::GCBOX
+    }
+
     /// Cast a `Val` into an instance of type `T` (where `T` must statically be a type that cannot
-    /// be boxed) or return a `VMError` if the cast is invalid.
+    /// be boxed) or return a `VMError` if the cast is invalid. This is the preferred way for
+    /// embedders to get at the Rust object behind a `Val`. Integers, which may be unboxed, should
+    /// instead be accessed with `as_isize` and friends.
     pub fn downcast<T: Obj + StaticObjType + NotUnboxable>(
         &self,
         vm: &VM,
@@ -187,27 +247,20 @@ impl Val {
 
     /// Create a (possibly boxed) `Val` representing the `isize` integer `i`.
     pub fn from_isize(vm: &mut VM, i: isize) -> Result<Val, Box<VMError>> {
-        let top_bits = i as usize & (INT_BITMASK << (BITSIZE - TAG_BITSIZE - 1));
-        if top_bits == 0 || top_bits == INT_BITMASK << (BITSIZE - TAG_BITSIZE - 1) {
-            // top_bits == 0: A positive integer that fits in our tagging scheme
-            // top_bits all set to 1: A negative integer that fits in our tagging scheme
-            Ok(Val {
-                val: ((i as usize) << TAG_BITSIZE) | (ValKind::INT as usize),
-            })
-        } else {
-            Int::boxed_isize(vm, i)
+        match encode_int(i) {
+            Some(val) => Ok(Val { val }),
+            None => Int::boxed_isize(vm, i),
         }
     }
 
-    /// Create a (possibly boxed) `Val` representing the `usize` integer `i`. Notice that this can
-    /// fail if `i` is too big (since we don't have BigNum support and ints are internally
-    /// represented as `isize`).
+    /// Create a (possibly boxed) `Val` representing the `usize` integer `i`. Integers which are
+    /// too big to be tagged but which fit in an `isize` are boxed as `Int`s; anything larger is
+    /// represented as an `ArbInt`.
     pub fn from_usize(vm: &mut VM, i: usize) -> Result<Val, Box<VMError>> {
-        if i & (INT_BITMASK << (BITSIZE - TAG_BITSIZE - 1)) == 0 {
-            // The top TAG_BITSIZE bits aren't set, so this fits within our pointer tagging scheme.
-            Ok(Val {
-                val: (i << TAG_BITSIZE) | (ValKind::INT as usize),
-            })
+        if let Some(val) = isize::try_from(i).ok().and_then(encode_int) {
+            Ok(Val { val })
+        } else if i <= isize::max_value() as usize {
+            Int::boxed_isize(vm, i as isize)
         } else {
             ArbInt::new(vm, BigInt::from_usize(i).unwrap())
         }
@@ -231,17 +284,7 @@ impl Val {
                 .unwrap()
                 .downcast::<Int>()
                 .map(|tobj| tobj.as_isize()),
-            ValKind::INT => {
-                if self.val & 1 << (BITSIZE - 1) == 0 {
-                    Some((self.val >> TAG_BITSIZE) as isize)
-                } else {
-                    // For negative integers we need to pad the top TAG_BITSIZE bits with 1s.
-                    Some(
-                        ((self.val >> TAG_BITSIZE) | (TAG_BITMASK << (BITSIZE - TAG_BITSIZE)))
-                            as isize,
-                    )
-                }
-            }
+            ValKind::INT => Some(decode_int(self.val)),
             ValKind::ILLEGAL => unreachable!(),
         }
     }
@@ -254,13 +297,7 @@ impl Val {
                 .unwrap()
                 .downcast::<Int>()
                 .and_then(|tobj| tobj.as_usize()),
-            ValKind::INT => {
-                if self.val & 1 << (BITSIZE - 1) == 0 {
-                    Some(self.val >> TAG_BITSIZE)
-                } else {
-                    None
-                }
-            }
+            ValKind::INT => usize::try_from(decode_int(self.val)).ok(),
             ValKind::ILLEGAL => unreachable!(),
         }
     }
@@ -271,8 +308,44 @@ impl Val {
     pub fn bit_eq(&self, other: &Val) -> bool {
         self.val == other.val
     }
+
+    /// If this `Val` is `vm.true_` or `vm.false_`, return the corresponding `bool`, otherwise
+    /// return a `NotABoolean` error.
+    pub fn as_bool(&self, vm: &mut VM) -> Result<bool, Box<VMError>> {
+        if self.bit_eq(&vm.true_) {
+            Ok(true)
+        } else if self.bit_eq(&vm.false_) {
+            Ok(false)
+        } else {
+            let got = self.dyn_objtype(vm);
+            Err(VMError::new(vm, VMErrorKind::NotABoolean { got }))
+        }
+    }
+
+    /// If this `Val` is boxed, call `visitor` on each `Val` the boxed object directly references.
+    pub fn trace(&self, visitor: &mut dyn FnMut(&Val)) {
+        if self.is_gcbox() {
+            unsafe { self.val_to_tobj() }.trace(visitor);
+        }
+    }
+
+    /// Is this `Val` the `nil` object?
+    pub fn is_nil(&self, vm: &VM) -> bool {
+        self.bit_eq(&vm.nil)
+    }
 }
 
+/// `Val`s are equal if they are identical: tagged integers are compared by value, and boxed
+/// objects by the address of the box (so two distinct boxes with the same contents, including
+/// boxed integers, are not equal). This is not SOM `=`, which can be overridden by user code.
+impl PartialEq for Val {
+    fn eq(&self, other: &Val) -> bool {
+        self.bit_eq(other)
+    }
+}
+
+impl Eq for Val {}
+
 // Implement each function from the `Obj` type so that we can efficiently deal with tagged values.
 impl Val {
     /// What `ObjType` does this `Val` represent?
@@ -293,6 +366,65 @@ impl Val {
         }
     }
 
+    /// Return the name of the SOM class this `Val` is an instance of (e.g. `Integer` for both
+    /// tagged and boxed integers, or a user class's name for its instances).
+    pub fn type_name(&self, vm: &mut VM) -> String {
+        let cls_val = self.get_class(vm);
+        let cls: &Class = cls_val.downcast(vm).unwrap();
+        cls.name_str(vm).to_owned()
+    }
+
+    /// Produce a shallow copy of this `Val`. Since tagged integers have no identity, they are
+    /// returned unchanged.
+    pub fn shallow_copy(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.valkind() {
+            ValKind::INT => Ok(self.clone()),
+            ValKind::GCBOX => self.tobj(vm).unwrap().shallow_copy(vm),
+            ValKind::ILLEGAL => unreachable!(),
+        }
+    }
+
+    /// Return this `Val`'s hashcode.
+    pub fn hashcode(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.valkind() {
+            ValKind::INT => Ok(self.clone()),
+            ValKind::GCBOX => self.tobj(vm).unwrap().hashcode(vm),
+            ValKind::ILLEGAL => unreachable!(),
+        }
+    }
+
+    /// Return this `Val`'s identity hash, which is unique to this object and stable for its
+    /// lifetime, even if the object is moved in memory. Unlike [`Val::hashcode`], this is never
+    /// overridden: objects which are equal but not identical have different identity hashes.
+    pub fn identity_hash(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.valkind() {
+            ValKind::INT => Ok(self.clone()),
+            ValKind::GCBOX => Val::from_usize(vm, gc::identity(self.obj_addr())),
+            ValKind::ILLEGAL => unreachable!(),
+        }
+    }
+
+    /// Convert this `Val` to a `Val` that represents a SOM double.
+    pub fn to_doubleval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.valkind() {
+            ValKind::INT => {
+                let i = self.as_isize(vm).unwrap();
+                Ok(Double::new(vm, i as f64))
+            }
+            ValKind::GCBOX => self.tobj(vm).unwrap().to_doubleval(vm),
+            ValKind::ILLEGAL => unreachable!(),
+        }
+    }
+
+    /// Convert this `Val` to a `Val` that represents a SOM integer.
+    pub fn to_intval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
+        match self.valkind() {
+            ValKind::INT => Ok(self.clone()),
+            ValKind::GCBOX => self.tobj(vm).unwrap().to_intval(vm),
+            ValKind::ILLEGAL => unreachable!(),
+        }
+    }
+
     pub fn to_strval(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
         match self.valkind() {
             ValKind::INT => {
@@ -334,13 +466,21 @@ impl Val {
     pub fn div(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
         debug_assert_eq!(ValKind::INT as usize, 0);
         if self.valkind() == ValKind::INT && other.valkind() == ValKind::INT {
-            if other.val != 0 {
-                return Ok(Val {
-                    val: (self.val / other.val) * (1 << TAG_BITSIZE),
-                });
-            } else {
+            // Both tags are zero, so dividing the tagged representations gives the untagged
+            // quotient.
+            let (lhs, rhs) = (self.val as isize, other.val as isize);
+            if rhs == 0 {
                 return Err(VMError::new(vm, VMErrorKind::DivisionByZero));
+            } else if lhs.wrapping_rem(rhs) == 0 {
+                if let Some(i) = lhs
+                    .checked_div(rhs)
+                    .and_then(|i| i.checked_mul(1 << TAG_BITSIZE))
+                {
+                    return Ok(Val { val: i as usize });
+                }
             }
+            // Inexact divisions produce a `Fraction`, and overflowing divisions an `ArbInt`: both
+            // are handled by the slow path.
         }
         self.tobj(vm).unwrap().div(vm, other)
     }
@@ -402,6 +542,21 @@ impl Val {
         self.tobj(vm).unwrap().mul(vm, other)
     }
 
+    /// Produce a new `Val` which performs a bitwise or operation with `other` and this.
+    pub fn or(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        if let Some(lhs) = self.as_isize(vm) {
+            if let Some(rhs) = other.as_isize(vm) {
+                return Val::from_isize(vm, lhs | rhs);
+            } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+                return ArbInt::new(vm, BigInt::from_isize(lhs).unwrap() | rhs.bigint());
+            }
+            let expected = self.dyn_objtype(vm);
+            let got = other.dyn_objtype(vm);
+            return Err(VMError::new(vm, VMErrorKind::TypeError { expected, got }));
+        }
+        self.tobj(vm).unwrap().or(vm, other)
+    }
+
     /// Produce a new `Val` which shifts `self` `other` bits to the left.
     pub fn shl(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
         if let Some(lhs) = self.as_isize(vm) {
@@ -412,14 +567,13 @@ impl Val {
                     let rhs_i = u32::try_from(rhs)
                         .map_err(|_| VMError::new(vm, VMErrorKind::ShiftTooBig))?;
                     if let Some(i) = lhs.checked_shl(rhs_i) {
-                        // We have to be careful as shifting bits in an isize can lead to positive
-                        // numbers becoming negative in two's complement. For example, on a 64-bit
-                        // machine, (1isize<<63) == -9223372036854775808. To avoid this, if
-                        // shifting +ve number leads to a -ve number being produced, we know we've
-                        // exceeded an isize's ability to store the result, and need to fall back
-                        // to the ArbInt case.
-                        if lhs < 0 || (lhs > 0 && i > 0) {
-                            return Val::from_isize(vm, i as isize);
+                        // `checked_shl` only checks that `rhs_i` is smaller than an isize's width:
+                        // bits can still be shifted out of the top (e.g. on a 64-bit machine,
+                        // (1isize<<63) == -9223372036854775808). If shifting the result back
+                        // doesn't give us `lhs`, we've exceeded an isize's ability to store the
+                        // result, and need to fall back to the ArbInt case.
+                        if i >> rhs_i == lhs {
+                            return Val::from_isize(vm, i);
                         }
                     }
                     return ArbInt::new(
@@ -437,6 +591,51 @@ impl Val {
         self.tobj(vm).unwrap().shl(vm, other)
     }
 
+    /// Produce a new `Val` which arithmetically shifts `self` `other` bits to the right.
+    pub fn shr(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        if let Some(lhs) = self.as_isize(vm) {
+            if let Some(rhs) = other.as_isize(vm) {
+                if rhs < 0 {
+                    return Err(VMError::new(vm, VMErrorKind::NegativeShift));
+                }
+                // Shifting by an isize's width or more leaves only the sign bit.
+                let all_bits = if lhs < 0 { -1 } else { 0 };
+                let i = u32::try_from(rhs)
+                    .ok()
+                    .and_then(|rhs| lhs.checked_shr(rhs))
+                    .unwrap_or(all_bits);
+                return Val::from_isize(vm, i);
+            } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+                if rhs.bigint() < &Zero::zero() {
+                    return Err(VMError::new(vm, VMErrorKind::NegativeShift));
+                }
+                return Val::from_isize(vm, if lhs < 0 { -1 } else { 0 });
+            }
+            let got = other.dyn_objtype(vm);
+            return Err(VMError::new(vm, VMErrorKind::NotANumber { got }));
+        }
+        self.tobj(vm).unwrap().shr(vm, other)
+    }
+
+    /// Produce a new `Val` which shifts `self` `other` bits to the left if `other` is positive, or
+    /// arithmetically shifts it `-other` bits to the right if `other` is negative.
+    pub fn bit_shift(&self, vm: &mut VM, other: Val) -> Result<Val, Box<VMError>> {
+        if let Some(rhs) = other.as_isize(vm) {
+            if rhs < 0 {
+                // Shifting right by `isize::max_value()` bits is no different to shifting by one
+                // more, so we needn't worry about `-isize::min_value()` overflowing.
+                let rhs = Val::from_isize(vm, rhs.checked_neg().unwrap_or(isize::max_value()))?;
+                return self.shr(vm, rhs);
+            }
+        } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
+            if rhs.bigint() < &Zero::zero() {
+                let rhs = ArbInt::new(vm, -rhs.bigint())?;
+                return self.shr(vm, rhs);
+            }
+        }
+        self.shl(vm, other)
+    }
+
     /// Produces a new `Val` which is the square root of this.
     pub fn sqrt(&self, vm: &mut VM) -> Result<Val, Box<VMError>> {
         if let Some(lhs) = self.as_isize(vm) {
@@ -501,6 +700,8 @@ macro_rules! binop_all {
                     } else if let Some(rhs) = other.try_downcast::<ArbInt>(vm) {
                         Ok(Val::from_bool(vm,
                             &BigInt::from_isize(lhs).unwrap() $op rhs.bigint()))
+                    } else if let Some(rhs) = other.try_downcast::<Double>(vm) {
+                        Ok(Val::from_bool(vm, (lhs as f64) $op rhs.double()))
                     } else {
                         Ok(vm.$tf.clone())
                     }
@@ -544,7 +745,14 @@ binop_typeerror!(less_than, <);
 binop_typeerror!(less_than_equals, <=);
 
 impl Clone for Val {
+    #[inline]
     fn clone(&self) -> Self {
+        // Cloning is extremely common, so we want tagged (and illegal) values -- which are by far
+        // the most common cases, and which need no refcount adjustment -- to take the cheapest
+        // possible route.
+        if self.val & TAG_BITMASK != ValKind::GCBOX as usize {
+            return Val { val: self.val };
+        }
         let val = match self.valkind() {
             ValKind::GCBOX => unsafe {
                 transmute::<*const ThinObj, usize>(
@@ -583,11 +791,43 @@ mod tests {
     use super::*;
     use crate::vm::{
         core::VM,
-        objects::{Class, ObjType, String_},
+        objects::{Array, Class, ObjType, String_},
     };
 
     use std::ops::Deref;
 
+    #[test]
+    fn test_clone() {
+        let mut vm = VM::new_no_bootstrap();
+
+        let v = Val::from_isize(&mut vm, 42).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        let live = vm.gc_stats().live_objects;
+        for _ in 0..1000 {
+            let v2 = v.clone();
+            assert!(v2.bit_eq(&v));
+        }
+        assert_eq!(v.as_isize(&mut vm).unwrap(), 42);
+        // Cloning a tagged integer must not box it.
+        assert_eq!(vm.gc_stats().live_objects, live);
+
+        let v = Val::from_isize(&mut vm, isize::max_value()).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        let live = vm.gc_stats().live_objects;
+        let clones = (0..1000).map(|_| v.clone()).collect::<Vec<_>>();
+        assert!(clones.iter().all(|v2| v2.bit_eq(&v)));
+        // Clones share the original object rather than copying it...
+        assert_eq!(vm.gc_stats().live_objects, live);
+        drop(clones);
+        // ...and if cloning didn't increment the reference count, the object would have been freed
+        // by now.
+        assert_eq!(vm.gc_stats().live_objects, live);
+        assert_eq!(v.as_isize(&mut vm).unwrap(), isize::max_value());
+
+        let v = Val::illegal();
+        assert_eq!(v.clone().valkind(), ValKind::ILLEGAL);
+    }
+
     #[test]
     fn test_isize() {
         let mut vm = VM::new_no_bootstrap();
@@ -624,6 +864,40 @@ mod tests {
         assert_eq!(v.valkind(), ValKind::GCBOX);
         assert_eq!(v.as_usize(&mut vm).unwrap(), 1 << (BITSIZE - 2));
         assert_eq!(v.as_isize(&mut vm).unwrap(), 1 << (BITSIZE - 2));
+
+        // The exact boundary between tagged and boxed integers.
+        let v = Val::from_isize(&mut vm, (1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        assert_eq!(
+            v.as_usize(&mut vm).unwrap(),
+            (1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1
+        );
+        assert_eq!(
+            v.as_isize(&mut vm).unwrap(),
+            (1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1
+        );
+        let v = Val::from_isize(&mut vm, 1 << (BITSIZE - 1 - TAG_BITSIZE)).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        assert_eq!(
+            v.as_usize(&mut vm).unwrap(),
+            1 << (BITSIZE - 1 - TAG_BITSIZE)
+        );
+        assert_eq!(
+            v.as_isize(&mut vm).unwrap(),
+            1 << (BITSIZE - 1 - TAG_BITSIZE)
+        );
+        let v = Val::from_isize(&mut vm, -(1 << (BITSIZE - 1 - TAG_BITSIZE))).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        assert_eq!(
+            v.as_isize(&mut vm).unwrap(),
+            -(1 << (BITSIZE - 1 - TAG_BITSIZE))
+        );
+        let v = Val::from_isize(&mut vm, -(1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        assert_eq!(
+            v.as_isize(&mut vm).unwrap(),
+            -(1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1
+        );
     }
 
     #[test]
@@ -655,6 +929,152 @@ mod tests {
         assert_eq!(v.valkind(), ValKind::GCBOX);
         assert_eq!(v.as_usize(&mut vm).unwrap(), 1 << (BITSIZE - 2));
         assert_eq!(v.as_isize(&mut vm).unwrap(), 1 << (BITSIZE - 2));
+
+        // The exact boundary between tagged and boxed integers.
+        let v = Val::from_usize(&mut vm, (1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        assert_eq!(
+            v.as_usize(&mut vm).unwrap(),
+            (1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1
+        );
+        assert_eq!(
+            v.as_isize(&mut vm).unwrap(),
+            (1 << (BITSIZE - 1 - TAG_BITSIZE)) - 1
+        );
+        let v = Val::from_usize(&mut vm, 1 << (BITSIZE - 1 - TAG_BITSIZE)).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        assert_eq!(
+            v.as_usize(&mut vm).unwrap(),
+            1 << (BITSIZE - 1 - TAG_BITSIZE)
+        );
+        assert_eq!(
+            v.as_isize(&mut vm).unwrap(),
+            1 << (BITSIZE - 1 - TAG_BITSIZE)
+        );
+
+        // The largest `usize` that still fits in an `isize` is boxed as an `Int`.
+        let v = Val::from_usize(&mut vm, isize::max_value() as usize).unwrap();
+        assert!(v.try_downcast::<Int>(&vm).is_some());
+        assert_eq!(v.as_usize(&mut vm).unwrap(), isize::max_value() as usize);
+    }
+
+    #[test]
+    fn test_tagged_range() {
+        let mut vm = VM::new_no_bootstrap();
+
+        assert_eq!(MIN_TAGGED_INT, -MAX_TAGGED_INT - 1);
+        assert_eq!(
+            MAX_TAGGED_INT as usize,
+            usize::max_value() >> (TAG_BITSIZE + 1)
+        );
+
+        // Every power of two (and its neighbours), positive and negative, either side of the
+        // boundaries.
+        let mut cases = vec![
+            MIN_TAGGED_INT,
+            MAX_TAGGED_INT,
+            isize::min_value(),
+            isize::max_value(),
+        ];
+        for b in 0..BITSIZE - 1 {
+            for d in -1..=1 {
+                cases.push((1isize << b).wrapping_add(d));
+                cases.push((-(1isize << b)).wrapping_add(d));
+            }
+        }
+        for d in 0..1000 {
+            cases.push(d - 500);
+            cases.push(MIN_TAGGED_INT.wrapping_add(d - 500));
+            cases.push(MAX_TAGGED_INT.wrapping_add(d - 500));
+        }
+        for i in cases {
+            let v = Val::from_isize(&mut vm, i).unwrap();
+            let tagged = i >= MIN_TAGGED_INT && i <= MAX_TAGGED_INT;
+            assert_eq!(v.valkind() == ValKind::INT, tagged, "{}", i);
+            assert_eq!(v.as_isize(&mut vm).unwrap(), i);
+            if i >= 0 {
+                assert_eq!(v.as_usize(&mut vm).unwrap(), i as usize);
+                let v = Val::from_usize(&mut vm, i as usize).unwrap();
+                assert_eq!(v.valkind() == ValKind::INT, tagged, "{}", i);
+                assert_eq!(v.as_isize(&mut vm).unwrap(), i);
+            } else {
+                assert!(v.as_usize(&mut vm).is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_tagged_int_is_gcbox() {
+        let mut vm = VM::new_no_bootstrap();
+        let v = Val::from_isize(&mut vm, 42).unwrap();
+        assert!(v.is_tagged_int());
+        assert!(!v.is_gcbox());
+        let v = Val::from_isize(&mut vm, MAX_TAGGED_INT + 1).unwrap();
+        assert!(!v.is_tagged_int());
+        assert!(v.is_gcbox());
+        let v = String_::new(&mut vm, "s".to_owned(), true);
+        assert!(!v.is_tagged_int());
+        assert!(v.is_gcbox());
+        assert!(!Val::illegal().is_tagged_int());
+        assert!(!Val::illegal().is_gcbox());
+    }
+
+    #[test]
+    fn test_encode_decode_int() {
+        assert_eq!(encode_int(0), Some(ValKind::INT as usize));
+        assert_eq!(encode_int(MIN_TAGGED_INT - 1), None);
+        assert_eq!(encode_int(MAX_TAGGED_INT + 1), None);
+        assert_eq!(encode_int(isize::min_value()), None);
+        assert_eq!(encode_int(isize::max_value()), None);
+
+        let mut cases = vec![MIN_TAGGED_INT, MAX_TAGGED_INT];
+        for b in 0..BITSIZE - TAG_BITSIZE - 1 {
+            for d in -1..=1 {
+                cases.push((1isize << b) + d);
+                cases.push(-(1isize << b) + d);
+            }
+        }
+        for d in 0..1000 {
+            cases.push(d - 500);
+            cases.push(MIN_TAGGED_INT + d);
+            cases.push(MAX_TAGGED_INT - d);
+        }
+        for i in cases {
+            let val = encode_int(i).unwrap();
+            assert_eq!(val & TAG_BITMASK, ValKind::INT as usize, "{}", i);
+            assert_eq!(decode_int(val), i);
+        }
+    }
+
+    #[test]
+    fn test_bitsize() {
+        assert_eq!(BITSIZE, size_of::<usize>() * 8);
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn test_32bit_boundary() {
+        let mut vm = VM::new_no_bootstrap();
+
+        let v = Val::from_isize(&mut vm, (1 << 29) - 1).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        assert_eq!(v.as_isize(&mut vm).unwrap(), (1 << 29) - 1);
+        let v = Val::from_isize(&mut vm, 1 << 29).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        assert_eq!(v.as_isize(&mut vm).unwrap(), 1 << 29);
+        let v = Val::from_isize(&mut vm, -(1 << 29)).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        assert_eq!(v.as_isize(&mut vm).unwrap(), -(1 << 29));
+        let v = Val::from_isize(&mut vm, -(1 << 29) - 1).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        assert_eq!(v.as_isize(&mut vm).unwrap(), -(1 << 29) - 1);
+
+        let v = Val::from_usize(&mut vm, (1 << 29) - 1).unwrap();
+        assert_eq!(v.valkind(), ValKind::INT);
+        assert_eq!(v.as_usize(&mut vm).unwrap(), (1 << 29) - 1);
+        let v = Val::from_usize(&mut vm, 1 << 29).unwrap();
+        assert_eq!(v.valkind(), ValKind::GCBOX);
+        assert_eq!(v.as_usize(&mut vm).unwrap(), 1 << 29);
     }
 
     #[test]
@@ -666,7 +1086,7 @@ mod tests {
             let v_tobj = v.tobj(&mut vm).unwrap();
             let v_int: &dyn Obj = v_tobj.deref().deref();
             let v_recovered = Val::recover(v_int);
-            assert_eq!(v_recovered.val, v.val);
+            assert_eq!(v_recovered, v);
             v_recovered
         };
         // At this point, we will have dropped one of the references to the String above so the
@@ -674,6 +1094,29 @@ mod tests {
         assert_eq!(v.downcast::<String_>(&mut vm).unwrap().as_str(), "s");
     }
 
+    #[test]
+    fn test_eq() {
+        let mut vm = VM::new_no_bootstrap();
+
+        let v1 = Val::from_isize(&mut vm, 42).unwrap();
+        let v2 = Val::from_isize(&mut vm, 42).unwrap();
+        assert_eq!(v1, v2);
+        assert_ne!(v1, Val::from_isize(&mut vm, 43).unwrap());
+
+        // Boxed objects are compared by identity, not by content.
+        let s1 = String_::new(&mut vm, "s".to_owned(), true);
+        let s2 = String_::new(&mut vm, "s".to_owned(), true);
+        assert_eq!(s1, s1.clone());
+        assert_ne!(s1, s2);
+
+        let big = 1 << (BITSIZE - 2);
+        let b1 = Val::from_isize(&mut vm, big).unwrap();
+        let b2 = Val::from_isize(&mut vm, big).unwrap();
+        assert_eq!(b1.valkind(), ValKind::GCBOX);
+        assert_eq!(b1, b1.clone());
+        assert_ne!(b1, b2);
+    }
+
     #[test]
     fn test_cast() {
         let mut vm = VM::new_no_bootstrap();
@@ -686,6 +1129,15 @@ mod tests {
                 got: ObjType::String_
             }
         );
+
+        let v = Val::from_isize(&mut vm, 1).unwrap();
+        assert_eq!(
+            v.downcast::<String_>(&mut vm).unwrap_err().kind,
+            VMErrorKind::TypeError {
+                expected: ObjType::String_,
+                got: ObjType::Int
+            }
+        );
     }
 
     #[test]
@@ -701,4 +1153,22 @@ mod tests {
         assert!(v.downcast::<String_>(&mut vm).is_err());
         assert!(v.try_downcast::<String_>(&mut vm).is_none());
     }
+
+    #[test]
+    fn test_type_name() {
+        let mut vm = VM::new(vec!["lib/SOM".to_owned()]);
+        let v = Val::from_isize(&mut vm, 42).unwrap();
+        assert!(v.is_tagged_int());
+        assert_eq!(v.type_name(&mut vm), "Integer");
+        let v = ArbInt::new(&mut vm, BigInt::from(isize::max_value()) * 2).unwrap();
+        assert_eq!(v.type_name(&mut vm), "Integer");
+        let v = String_::new(&mut vm, "s".to_owned(), true);
+        assert_eq!(v.type_name(&mut vm), "String");
+        let v = String_::new(&mut vm, "s".to_owned(), false);
+        assert_eq!(v.type_name(&mut vm), "Symbol");
+        let v = Array::new(&mut vm, 2);
+        assert_eq!(v.type_name(&mut vm), "Array");
+        let v = vm.nil.clone();
+        assert_eq!(v.type_name(&mut vm), "Nil");
+    }
 }
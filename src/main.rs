@@ -1,5 +1,3 @@
-#![feature(box_patterns)]
-
 use std::{
     env,
     io::{stderr, Write},
@@ -7,9 +5,9 @@ use std::{
     process,
 };
 
-use getopts::Options;
+use getopts::{Options, ParsingStyle};
 
-use yksom::vm::{objects::Inst, VMError, VMErrorKind, VM};
+use yksom::vm::VM;
 
 fn usage(prog: &str) -> ! {
     let path = Path::new(prog);
@@ -17,7 +15,12 @@ fn usage(prog: &str) -> ! {
         .file_name()
         .map(|x| x.to_str().unwrap_or("yksom"))
         .unwrap_or("yksom");
-    writeln!(&mut stderr(), "Usage: {} [-h] --cp <path> <file.som>", leaf).ok();
+    writeln!(
+        &mut stderr(),
+        "Usage: {} [-h] --cp <path> <file.som> [<arg> ...]",
+        leaf
+    )
+    .ok();
     process::exit(1)
 }
 
@@ -25,23 +28,21 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     let prog = &args[0];
     let matches = Options::new()
+        // Everything after <file.som> is an argument to the SOM program.
+        .parsing_style(ParsingStyle::StopAtFirstFree)
         .optmulti("", "cp", "Path to System classes", "<path>")
         .optflag("h", "help", "")
         .parse(&args[1..])
         .unwrap_or_else(|_| usage(prog));
-    if matches.opt_present("h") || matches.free.len() != 1 {
+    if matches.opt_present("h") || matches.free.is_empty() {
         usage(prog);
     }
 
     let mut vm = VM::new(matches.opt_strs("cp"));
+    vm.set_args(matches.free[1..].to_vec());
     let cls = vm.compile(&Path::new(&matches.free[0]).canonicalize().unwrap(), true);
-    let app = Inst::new(&mut vm, cls);
-    match vm.top_level_send(app, "run", vec![]) {
-        Ok(_)
-        | Err(box VMError {
-            kind: VMErrorKind::Exit,
-            ..
-        }) => (),
+    match vm.run(cls) {
+        Ok(code) => process::exit(code),
         Err(e) => {
             e.console_print(&vm);
             process::exit(1);